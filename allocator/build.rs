@@ -1,17 +1,55 @@
 use std::env;
+use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
 
+#[path = "src/target.rs"]
+mod target;
+
+use target::Target;
+
 const BOEHM_REPO: &str = "https://github.com/ivmai/bdwgc.git";
 const BOEHM_ATOMICS_REPO: &str = "https://github.com/ivmai/libatomic_ops.git";
 const BOEHM_DIR: &str = "bdwgc";
 const BUILD_DIR: &str = ".libs";
+/// Records the `CFLAGS` the collector was last built with, in `OUT_DIR`.
+const CFLAGS_FILE: &str = "cflags";
+
+impl Target {
+    fn from_env() -> Self {
+        let target = Target {
+            triple: env::var("TARGET").unwrap(),
+            os: env::var("CARGO_CFG_TARGET_OS").unwrap(),
+            env: env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default(),
+            pointer_width: env::var("CARGO_CFG_TARGET_POINTER_WIDTH")
+                .unwrap()
+                .parse()
+                .unwrap(),
+        };
+        if env::var("CARGO_CFG_TARGET_ARCH").unwrap() != "x86_64" || target.pointer_width != 64 {
+            panic!("Requires x86_64 with 64 bit pointer width.");
+        }
+        target
+    }
+}
 
-#[cfg(not(all(target_pointer_width = "64", target_arch = "x86_64")))]
-compile_error!("Requires x86_64 with 64 bit pointer width.");
-static POINTER_MASK: &str = "-DPOINTER_MASK=0xFFFFFFFFFFFFFFF8";
-static FPIC: &str = "-fPIC";
-static MULTITHREADED: &str = "-DGC_ALWAYS_MULTITHREADED";
+/// The collector grows its mark stack by doubling it each time it overflows,
+/// and marking slows down until it has grown enough. Programs with large,
+/// deep heaps can avoid this by setting `LIBGC_MARK_STACK_SIZE` to the number
+/// of entries to start with. Overflows are logged when the program is run
+/// with `GC_PRINT_STATS` set.
+///
+/// Changing this rebuilds the collector.
+fn mark_stack_size() -> Option<usize> {
+    // Each entry is 2 words, and the stack must be a whole number of 4KiB
+    // heap blocks.
+    const ENTRIES_PER_BLOCK: usize = 4096 / 16;
+    let size = env::var("LIBGC_MARK_STACK_SIZE").ok()?;
+    let size = size
+        .parse::<usize>()
+        .unwrap_or_else(|_| panic!("LIBGC_MARK_STACK_SIZE must be a number, not {:?}", size));
+    Some((size.max(1) + ENTRIES_PER_BLOCK - 1) / ENTRIES_PER_BLOCK * ENTRIES_PER_BLOCK)
+}
 
 fn run<F>(name: &str, mut configure: F)
 where
@@ -25,9 +63,16 @@ where
 }
 
 fn main() {
-    let out_dir = env::var("OUT_DIR").unwrap();
-    let mut boehm_src = PathBuf::from(out_dir);
+    let target = Target::from_env();
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut boehm_src = out_dir.clone();
     boehm_src.push(BOEHM_DIR);
+    let cflags_file = out_dir.join(CFLAGS_FILE);
+    let cflags = target.cflags(mark_stack_size());
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/target.rs");
+    println!("cargo:rerun-if-env-changed=LIBGC_MARK_STACK_SIZE");
 
     if !boehm_src.exists() {
         run("git", |cmd| {
@@ -41,16 +86,31 @@ fn main() {
         });
 
         env::set_current_dir(&boehm_src).unwrap();
-
         run("./autogen.sh", |cmd| cmd);
+    }
+
+    // The collector is rebuilt from scratch if it was last built with
+    // different flags, e.g. because `LIBGC_MARK_STACK_SIZE` has changed.
+    if fs::read_to_string(&cflags_file).ok().as_deref() != Some(cflags.as_str()) {
+        env::set_current_dir(&boehm_src).unwrap();
+        if boehm_src.join("Makefile").exists() {
+            run("make", |cmd| cmd.arg("distclean"));
+        }
         run("./configure", |cmd| {
-            cmd.arg("--enable-static").arg("--disable-shared").env(
-                "CFLAGS",
-                format!("{} {} {}", POINTER_MASK, FPIC, MULTITHREADED),
-            )
+            cmd.arg("--enable-static")
+                .arg("--disable-shared")
+                .env("CFLAGS", &cflags);
+            if target.triple != env::var("HOST").unwrap() {
+                cmd.arg(format!("--host={}", target.gnu_triple()));
+            }
+            if let Some(cc) = target.cc(env::var("CC").ok()) {
+                cmd.env("CC", cc);
+            }
+            cmd
         });
 
         run("make", |cmd| cmd.arg("-j"));
+        fs::write(&cflags_file, &cflags).unwrap();
     }
 
     let mut libpath = PathBuf::from(&boehm_src);
@@ -61,4 +121,5 @@ fn main() {
         &libpath.as_path().to_str().unwrap()
     );
     println!("cargo:rustc-link-lib=static=gc");
+    println!("cargo:rustc-env=GC_POINTER_MASK={}", target.pointer_mask());
 }
@@ -47,6 +47,14 @@ extern "C" {
         old_client_data: *mut *mut u8,
     );
 
+    pub(crate) fn GC_register_finalizer_ignore_self(
+        ptr: *mut u8,
+        finalizer: Option<unsafe extern "C" fn(*mut u8, *mut u8)>,
+        client_data: *mut u8,
+        old_finalizer: *mut extern "C" fn(*mut u8, *mut u8),
+        old_client_data: *mut *mut u8,
+    );
+
     pub(crate) fn GC_register_finalizer_no_order(
         ptr: *mut u8,
         finalizer: Option<unsafe extern "C" fn(*mut u8, *mut u8)>,
@@ -57,19 +65,116 @@ extern "C" {
 
     pub(crate) fn GC_gcollect();
 
+    pub(crate) fn GC_stop_world_external();
+
+    pub(crate) fn GC_start_world_external();
+
+    pub(crate) fn GC_call_with_alloc_lock(
+        f: unsafe extern "C" fn(*mut u8) -> *mut u8,
+        client_data: *mut u8,
+    ) -> *mut u8;
+
     pub(crate) fn GC_get_full_gc_total_time() -> usize;
 
+    pub(crate) fn GC_set_on_collection_event(f: Option<unsafe extern "C" fn(crate::GcEvent)>);
+
+    pub(crate) fn GC_set_on_heap_resize(f: Option<unsafe extern "C" fn(usize)>);
+
+    pub(crate) fn GC_set_markers_count(n: u32);
+
+    pub(crate) fn GC_register_has_static_roots_callback(
+        f: Option<unsafe extern "C" fn(*const u8, *mut u8, usize) -> i32>,
+    );
+
+    pub(crate) fn GC_set_handle_fork(value: i32);
+
+    pub(crate) fn GC_set_finalize_on_demand(value: i32);
+
+    pub(crate) fn GC_set_finalizer_notifier(notifier: Option<unsafe extern "C" fn()>);
+
+    pub(crate) fn GC_invoke_finalizers() -> i32;
+
+    pub(crate) fn GC_should_invoke_finalizers() -> i32;
+
+    pub(crate) fn GC_set_await_finalize_proc(f: Option<unsafe extern "C" fn(obj: *mut u8)>);
+
+    pub(crate) fn GC_general_register_disappearing_link(link: *mut *mut u8, obj: *const u8) -> i32;
+
+    pub(crate) fn GC_unregister_disappearing_link(link: *mut *mut u8) -> i32;
+
+    pub(crate) fn GC_get_gc_no() -> usize;
+
+    pub(crate) fn GC_get_version() -> u32;
+
+    pub(crate) fn GC_get_heap_size() -> usize;
+
+    pub(crate) fn GC_get_free_space_divisor() -> usize;
+
+    pub(crate) fn GC_set_free_space_divisor(value: usize);
+
+    pub(crate) fn GC_set_max_heap_size(n: usize);
+
+    pub(crate) fn GC_expand_hp(n: usize) -> i32;
+
+    pub(crate) fn GC_get_full_freq() -> i32;
+
+    pub(crate) fn GC_set_full_freq(value: i32);
+
+    pub(crate) fn GC_enable_incremental();
+
+    pub(crate) fn GC_set_manual_vdb_allowed(value: i32);
+
+    pub(crate) fn GC_get_time_limit() -> usize;
+
+    pub(crate) fn GC_set_time_limit(value: usize);
+
+    pub(crate) fn GC_collect_a_little() -> i32;
+
+    pub(crate) fn GC_is_incremental_mode() -> i32;
+
+    pub(crate) fn GC_get_parallel() -> i32;
+
+    pub(crate) fn GC_get_all_interior_pointers() -> i32;
+
+    pub(crate) fn GC_get_finalize_on_demand() -> i32;
+
     pub(crate) fn GC_get_prof_stats(prof_stats: *mut ProfileStats, stats_size: usize) -> usize;
 
-    #[cfg(feature = "rustgc")]
+    pub(crate) fn GC_get_prof_stats_unsafe(
+        prof_stats: *mut ProfileStats,
+        stats_size: usize,
+    ) -> usize;
+
     pub(crate) fn GC_malloc_explicitly_typed(size: usize, descriptor: usize) -> *mut u8;
 
-    #[cfg(feature = "rustgc")]
     pub(crate) fn GC_make_descriptor(bitmap: *const usize, len: usize) -> usize;
 
-    #[cfg(feature = "rustgc")]
+    pub(crate) fn GC_change_stubborn(p: *const u8);
+
+    pub(crate) fn GC_end_stubborn_change(p: *const u8);
+
     pub(crate) fn GC_malloc_atomic(nbytes: usize) -> *mut u8;
 
+    pub(crate) fn GC_new_free_list_inner() -> *mut *mut u8;
+
+    pub(crate) fn GC_new_kind_inner(
+        free_list: *mut *mut u8,
+        mark_descriptor: usize,
+        add_size_to_descriptor: i32,
+        clear_new_objects: i32,
+    ) -> u32;
+
+    pub(crate) fn GC_generic_malloc(nbytes: usize, kind: i32) -> *mut u8;
+
+    pub(crate) fn GC_enumerate_reachable_objects_inner(
+        proc_: unsafe extern "C" fn(obj: *mut u8, bytes: usize, client_data: *mut u8),
+        client_data: *mut u8,
+    );
+
+    pub(crate) fn GC_base(displaced_pointer: *const u8) -> *mut u8;
+
+    pub(crate) fn GC_size(obj: *const u8) -> usize;
+
     #[cfg(feature = "rustgc")]
     pub(crate) fn GC_malloc_atomic_uncollectable(nbytes: usize) -> *mut u8;
 
@@ -79,6 +184,10 @@ extern "C" {
 
     pub(crate) fn GC_unregister_my_thread() -> i32;
 
+    pub(crate) fn GC_get_stack_base(sb: *mut *mut u8) -> i32;
+
+    pub(crate) fn GC_is_init_called() -> i32;
+
     pub(crate) fn GC_allow_register_threads();
 
     pub(crate) fn GC_init();
@@ -1,18 +1,29 @@
 #![no_std]
 #![feature(allocator_api)]
 #![feature(nonnull_slice_from_raw_parts)]
+#![feature(thread_local)]
 
 use core::{
     alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    cell::Cell,
     ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
 };
 
 mod boehm;
+#[cfg(test)]
+mod target;
+
+#[cfg(test)]
+extern crate std;
 
 pub struct GcAllocator;
 
 unsafe impl GlobalAlloc for GcAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !may_allocate() {
+            return ::core::ptr::null_mut();
+        }
         return boehm::GC_malloc_uncollectable(layout.size()) as *mut u8;
     }
 
@@ -21,7 +32,12 @@ unsafe impl GlobalAlloc for GcAllocator {
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, _: Layout, new_size: usize) -> *mut u8 {
-        boehm::GC_realloc(ptr, new_size) as *mut u8
+        if !may_allocate() {
+            return ::core::ptr::null_mut();
+        }
+        // Uncollectable blocks are never finalized, so there is no finalizer
+        // to move.
+        boehm::GC_realloc(ptr, new_size)
     }
 
     #[cfg(feature = "rustgc")]
@@ -33,18 +49,27 @@ unsafe impl GlobalAlloc for GcAllocator {
     #[cfg(feature = "rustgc")]
     #[inline]
     fn alloc_conservative(&self, layout: Layout) -> *mut u8 {
+        if !may_allocate() {
+            return ::core::ptr::null_mut();
+        }
         unsafe { boehm::GC_malloc_uncollectable(layout.size()) as *mut u8 }
     }
 
     #[cfg(feature = "rustgc")]
     #[inline]
     unsafe fn alloc_untraceable(&self, layout: Layout) -> *mut u8 {
+        if !may_allocate() {
+            return ::core::ptr::null_mut();
+        }
         boehm::GC_malloc_atomic_uncollectable(layout.size()) as *mut u8
     }
 }
 
 unsafe impl Allocator for GcAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !may_allocate() {
+            return Err(AllocError);
+        }
         unsafe {
             let ptr = boehm::GC_malloc(layout.size()) as *mut u8;
             let ptr = NonNull::new_unchecked(ptr);
@@ -54,19 +79,44 @@ unsafe impl Allocator for GcAllocator {
 
     unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
 
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        _: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !may_allocate() {
+            return Err(AllocError);
+        }
+        let ptr = NonNull::new(realloc(ptr.as_ptr(), new_layout.size())).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        _: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !may_allocate() {
+            return Err(AllocError);
+        }
+        let ptr = NonNull::new(realloc(ptr.as_ptr(), new_layout.size())).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+
     #[cfg(feature = "rustgc")]
     #[inline]
     fn alloc_untraceable(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        unsafe {
-            let ptr = boehm::GC_malloc_atomic(layout.size()) as *mut u8;
-            let ptr = NonNull::new_unchecked(ptr);
-            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
-        }
+        self.allocate_atomic(layout)
     }
 
     #[cfg(feature = "rustgc")]
     #[inline]
     fn alloc_conservative(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !may_allocate() {
+            return Err(AllocError);
+        }
         unsafe {
             let ptr = boehm::GC_malloc(layout.size()) as *mut u8;
             let ptr = NonNull::new_unchecked(ptr);
@@ -82,20 +132,285 @@ unsafe impl Allocator for GcAllocator {
         bitmap: usize,
         bitmap_size: usize,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        unsafe {
-            let gc_descr = boehm::GC_make_descriptor(&bitmap as *const usize, bitmap_size);
-            let ptr = boehm::GC_malloc_explicitly_typed(layout.size(), gc_descr);
-            let ptr = NonNull::new_unchecked(ptr);
-            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
-        }
+        self.allocate_precise(layout, bitmap, bitmap_size)
     }
 }
 
+/// True while this thread is running `with_world_stopped`.
+#[thread_local]
+static WORLD_STOPPED: Cell<bool> = Cell::new(false);
+
+/// Allocating while this thread has the world stopped would deadlock, as the
+/// allocation lock is held. In debug builds, such allocations fail instead,
+/// which turns the hang into an allocation error. Release builds don't pay
+/// for the check.
+#[inline]
+fn may_allocate() -> bool {
+    !cfg!(debug_assertions) || !WORLD_STOPPED.get()
+}
+
+/// Resize the block at `ptr` with `GC_realloc`, moving any finalizer
+/// registered on it to the resized block.
+///
+/// Finalizers are keyed by address, so if `GC_realloc` moved the block, its
+/// finalizer would otherwise stay registered on the old (now free) address:
+/// the value would never be finalized, and whatever is later allocated at the
+/// old address would be finalized in its place. The finalizer's client data,
+/// and whether it was registered as ordered, are carried over unchanged.
+///
+/// Only registrations already handed to the collector are moved. A caller
+/// which buffers registrations (e.g. libgc's finalizer batching) must hand
+/// over any for `ptr` before resizing it.
+unsafe fn realloc(ptr: *mut u8, new_size: usize) -> *mut u8 {
+    if !COUNTING_QUEUED.load(Ordering::Relaxed) {
+        // No finalizer has been registered yet.
+        return boehm::GC_realloc(ptr, new_size);
+    }
+    let mut finalizer: Option<unsafe extern "C" fn(*mut u8, *mut u8)> = None;
+    let mut client_data = ::core::ptr::null_mut();
+    // Unregister before resizing (retrieving the old registration), so that no
+    // stale entry can outlive the old block.
+    boehm::GC_register_finalizer_no_order(
+        ptr,
+        None,
+        ::core::ptr::null_mut(),
+        &mut finalizer as *mut _ as *mut extern "C" fn(*mut u8, *mut u8),
+        &mut client_data,
+    );
+    let new = boehm::GC_realloc(ptr, new_size);
+    let ordered = ordered_finalizer as unsafe extern "C" fn(*mut u8, *mut u8);
+    if finalizer.map(|f| f as usize) == Some(ordered as usize) {
+        boehm::GC_register_finalizer_ignore_self(
+            new,
+            finalizer,
+            client_data,
+            ::core::ptr::null_mut(),
+            ::core::ptr::null_mut(),
+        );
+    } else if finalizer.is_some() {
+        boehm::GC_register_finalizer_no_order(
+            new,
+            finalizer,
+            client_data,
+            ::core::ptr::null_mut(),
+            ::core::ptr::null_mut(),
+        );
+    }
+    new
+}
+
+/// The collector doesn't say how a finalizer was registered, so ordered
+/// finalizers are registered as `ordered_finalizer`, with one of these as its
+/// client data, for `realloc` to tell them apart. It is kept alive by the
+/// collector's finalizer table.
+#[repr(C)]
+struct OrderedFinalizer {
+    finalizer: unsafe extern "C" fn(*mut u8, *mut u8),
+    client_data: *mut u8,
+}
+
+unsafe extern "C" fn ordered_finalizer(obj: *mut u8, data: *mut u8) {
+    let data = &*(data as *const OrderedFinalizer);
+    (data.finalizer)(obj, data.client_data)
+}
+
+// The collector ignores any bits of a candidate pointer which are cleared by
+// `POINTER_MASK`. If it cleared anything other than alignment bits, real
+// pointers would be mis-scanned and live objects freed.
+const _: () = assert!(
+    GcAllocator::POINTER_MASK | (core::mem::align_of::<usize>() - 1) == usize::MAX,
+    "the collector was built with an invalid POINTER_MASK"
+);
+
 impl GcAllocator {
+    /// The mask which the collector applies to candidate pointers while
+    /// marking. The bits it clears are ignored, so programs can store tags
+    /// in them without hiding the pointer from the collector.
+    pub const POINTER_MASK: usize = parse_usize(env!("GC_POINTER_MASK"));
+
+    /// Allocate a block which the collector will never scan for pointers. The
+    /// block is not zeroed.
+    pub fn allocate_atomic(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !may_allocate() {
+            return Err(AllocError);
+        }
+        let ptr = unsafe { boehm::GC_malloc_atomic(layout.size()) };
+        let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    /// Allocate a zeroed block which is scanned conservatively, as with
+    /// `Allocator::allocate`, but from heap blocks set aside for objects which
+    /// are rarely accessed. Keeping these apart means they don't dilute the
+    /// blocks holding frequently used objects.
+    pub fn allocate_cold(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !may_allocate() {
+            return Err(AllocError);
+        }
+        let ptr = unsafe { boehm::GC_generic_malloc(layout.size(), cold_kind() as i32) };
+        let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    /// Allocate a block which the collector will scan precisely: only the
+    /// first `bitmap_size` words of the block whose bits are set in `bitmap`
+    /// are treated as possible pointers.
+    pub fn allocate_precise(
+        &self,
+        layout: Layout,
+        bitmap: usize,
+        bitmap_size: usize,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !may_allocate() {
+            return Err(AllocError);
+        }
+        let descr = unsafe { boehm::GC_make_descriptor(&bitmap as *const usize, bitmap_size) };
+        self.allocate_typed(layout, descr)
+    }
+
+    /// Returns the collector's descriptor for blocks scanned as described by
+    /// `bitmap` and `bitmap_size` (see `allocate_precise`). Descriptors
+    /// never change, so callers which allocate many blocks with the same
+    /// bitmap can make the descriptor once and use `allocate_typed`.
+    pub fn make_descriptor(bitmap: usize, bitmap_size: usize) -> usize {
+        unsafe {
+            // Typed allocation is set up the first time a descriptor is made,
+            // which needs the collector to be initialized.
+            boehm::GC_init();
+            boehm::GC_make_descriptor(&bitmap as *const usize, bitmap_size)
+        }
+    }
+
+    /// Allocate a block which the collector will scan as described by
+    /// `descr`, which was returned by `make_descriptor`.
+    pub fn allocate_typed(
+        &self,
+        layout: Layout,
+        descr: usize,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !may_allocate() {
+            return Err(AllocError);
+        }
+        let ptr = unsafe { boehm::GC_malloc_explicitly_typed(layout.size(), descr) };
+        let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    /// Tell the collector that the object containing `ptr` is about to be
+    /// mutated. This is a no-op in current collectors, but pairs with
+    /// `end_stubborn_change`.
+    pub fn change_stubborn(ptr: *const u8) {
+        unsafe { boehm::GC_change_stubborn(ptr) }
+    }
+
+    /// Tell the collector that the object containing `ptr` has been
+    /// mutated. In incremental mode with manual dirty bits, this marks its
+    /// page dirty so that it is re-scanned; otherwise it does nothing.
+    /// `enable_incremental` never chooses manual dirty bits.
+    pub fn end_stubborn_change(ptr: *const u8) {
+        unsafe { boehm::GC_end_stubborn_change(ptr) }
+    }
+
     pub fn force_gc() {
         unsafe { boehm::GC_gcollect() }
     }
 
+    /// The number of collections so far. This may wrap.
+    pub fn num_collections() -> usize {
+        unsafe { boehm::GC_get_gc_no() }
+    }
+
+    /// Returns the base of the block which `ptr` points into, or `None` if it
+    /// doesn't point into the GC heap.
+    pub fn base(ptr: *const u8) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { boehm::GC_base(ptr) })
+    }
+
+    /// Returns the size of the block at `base`. This may be larger than was
+    /// requested when it was allocated.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the base of a GC block, as returned by `base`.
+    pub unsafe fn block_size(base: *const u8) -> usize {
+        boehm::GC_size(base)
+    }
+
+    /// Run `f` while holding the collector's allocation lock. No collection
+    /// can start, and no other thread can allocate from the GC heap, until `f`
+    /// returns. This allows multi-object operations (e.g. walking a handle
+    /// table) to appear atomic with respect to the collector.
+    ///
+    /// The allocation lock is not re-entrant: `f` must not allocate (either
+    /// directly or through the global allocator), register finalizers, force a
+    /// collection, or call `with_alloc_lock` again. Doing any of these will
+    /// deadlock. `f` must not panic, as it is called from C.
+    pub fn with_alloc_lock<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        unsafe extern "C" fn call<F, R>(data: *mut u8) -> *mut u8
+        where
+            F: FnOnce() -> R,
+        {
+            let (f, ret) = &mut *(data as *mut (Option<F>, Option<R>));
+            *ret = Some((f.take().unwrap())());
+            ::core::ptr::null_mut()
+        }
+
+        let mut data: (Option<F>, Option<R>) = (Some(f), None);
+        unsafe {
+            boehm::GC_call_with_alloc_lock(call::<F, R>, &mut data as *mut _ as *mut u8);
+        }
+        data.1.unwrap()
+    }
+
+    /// Call `f` with the base and size of every object found reachable by
+    /// the most recent collection (and not freed since).
+    ///
+    /// # Safety
+    ///
+    /// The allocation lock must be held, e.g. by calling this from
+    /// `with_alloc_lock` or `with_world_stopped`, and `f` is subject to the
+    /// same restrictions as the closures passed to those.
+    pub unsafe fn enumerate_reachable_objects(f: &mut dyn FnMut(*mut u8, usize)) {
+        unsafe extern "C" fn call(obj: *mut u8, bytes: usize, f: *mut u8) {
+            let f = &mut *(f as *mut &mut dyn FnMut(*mut u8, usize));
+            f(obj, bytes)
+        }
+        let mut f = f;
+        boehm::GC_enumerate_reachable_objects_inner(call, &mut f as *mut _ as *mut u8)
+    }
+
+    /// Run `f` with the allocation lock held and every other thread
+    /// registered with the collector stopped, so that the heap can be
+    /// inspected (e.g. walked or snapshotted) without mutators changing it.
+    /// Calling this again from inside `f` just calls the inner closure.
+    ///
+    /// # Safety
+    ///
+    /// `f` has the same restrictions as with `with_alloc_lock`: it must not
+    /// allocate, register finalizers, force a collection, call
+    /// `with_alloc_lock`, or panic. In debug builds, allocating fails rather
+    /// than deadlocking. Other threads are stopped wherever they happen to
+    /// be, so `f` must also not wait for anything another thread might hold,
+    /// such as a `Mutex`.
+    pub unsafe fn with_world_stopped<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Only this thread can have stopped the world if the flag is set.
+        if WORLD_STOPPED.get() {
+            return f();
+        }
+        boehm::GC_stop_world_external();
+        WORLD_STOPPED.set(true);
+        let ret = f();
+        WORLD_STOPPED.set(false);
+        boehm::GC_start_world_external();
+        ret
+    }
+
     pub unsafe fn register_finalizer(
         &self,
         obj: *mut u8,
@@ -104,6 +419,9 @@ impl GcAllocator {
         old_finalizer: *mut extern "C" fn(*mut u8, *mut u8),
         old_client_data: *mut *mut u8,
     ) {
+        if finalizer.is_some() {
+            count_queued_finalizers();
+        }
         boehm::GC_register_finalizer_no_order(
             obj,
             finalizer,
@@ -113,6 +431,71 @@ impl GcAllocator {
         )
     }
 
+    /// Register `finalizer` to be called with `client_data` once `obj` is
+    /// unreachable, in topological order: everything `obj` points to is kept
+    /// alive until the finalizer has run, so it may still be used there.
+    /// Pointers from `obj` to itself are ignored, but objects in a longer
+    /// cycle of such finalizers are never finalized.
+    ///
+    /// Each registration allocates a small block as well, so that the
+    /// ordering survives `obj` being moved by `Allocator::grow`.
+    ///
+    /// # Safety
+    ///
+    /// `obj` must be the base of a block allocated by the collector, and
+    /// `finalizer` must be safe to call on it once it is unreachable.
+    pub unsafe fn register_ordered_finalizer(
+        &self,
+        obj: *mut u8,
+        finalizer: Option<unsafe extern "C" fn(*mut u8, *mut u8)>,
+        client_data: *mut u8,
+    ) {
+        let (finalizer, client_data) = match finalizer {
+            Some(finalizer) => {
+                count_queued_finalizers();
+                let size = core::mem::size_of::<OrderedFinalizer>();
+                let data = boehm::GC_malloc(size) as *mut OrderedFinalizer;
+                assert!(!data.is_null(), "out of memory");
+                data.write(OrderedFinalizer {
+                    finalizer,
+                    client_data,
+                });
+                let ordered = ordered_finalizer as unsafe extern "C" fn(*mut u8, *mut u8);
+                (Some(ordered), data as *mut u8)
+            }
+            None => (None, client_data),
+        };
+        boehm::GC_register_finalizer_ignore_self(
+            obj,
+            finalizer,
+            client_data,
+            ::core::ptr::null_mut(),
+            ::core::ptr::null_mut(),
+        )
+    }
+
+    /// Register `link` to be cleared (set to null) once `obj` is unreachable.
+    /// Returns false if `link` was already registered.
+    ///
+    /// # Safety
+    ///
+    /// `link` must point into a block allocated by the collector, and `obj`
+    /// must be the base of a block allocated by the collector. `link` must
+    /// not be scanned as a strong reference to `obj`: either it must be in a
+    /// block which isn't scanned, or the pointer stored there hidden.
+    pub unsafe fn register_disappearing_link(link: *mut *mut u8, obj: *const u8) -> bool {
+        boehm::GC_general_register_disappearing_link(link, obj) == 0
+    }
+
+    /// Returns false if `link` wasn't registered.
+    ///
+    /// # Safety
+    ///
+    /// `link` must point into a block allocated by the collector.
+    pub unsafe fn unregister_disappearing_link(link: *mut *mut u8) -> bool {
+        boehm::GC_unregister_disappearing_link(link) != 0
+    }
+
     pub fn unregister_finalizer(&self, gcbox: *mut u8) {
         unsafe {
             boehm::GC_register_finalizer(
@@ -125,6 +508,163 @@ impl GcAllocator {
         }
     }
 
+    /// Set the function which the collector calls at each stage of a
+    /// collection, replacing any previous one.
+    ///
+    /// # Safety
+    ///
+    /// The callback is called with the allocation lock held (and possibly
+    /// with the world stopped), so it must not allocate, take locks which a
+    /// mutator might hold while allocating, or panic.
+    pub unsafe fn set_on_collection_event(callback: Option<unsafe extern "C" fn(GcEvent)>) {
+        boehm::GC_set_on_collection_event(callback)
+    }
+
+    /// Set the function which the collector calls, with the new heap size in
+    /// bytes, whenever it grows the heap. This replaces any previous one.
+    ///
+    /// # Safety
+    ///
+    /// As with `set_on_collection_event`, the callback is called with the
+    /// allocation lock held, and so must not allocate, take locks which a
+    /// mutator might hold while allocating, or panic.
+    pub unsafe fn set_on_heap_resize(callback: Option<unsafe extern "C" fn(usize)>) {
+        boehm::GC_set_on_heap_resize(callback)
+    }
+
+    /// Set the function which the collector calls for each writable segment
+    /// of each loaded object (the program and its dynamic libraries) when it
+    /// registers them as roots, which it does before each collection. It is
+    /// given the object's name as a NUL-terminated string, and the
+    /// segment's start and size in bytes, and returns whether the segment
+    /// should be scanned. This replaces any previous one.
+    ///
+    /// # Safety
+    ///
+    /// As with `set_on_collection_event`, the callback is called with the
+    /// allocation lock held, and so must not allocate, take locks which a
+    /// mutator might hold while allocating, or panic.
+    pub unsafe fn set_has_static_roots_callback(
+        callback: Option<unsafe extern "C" fn(*const u8, *mut u8, usize) -> i32>,
+    ) {
+        boehm::GC_register_has_static_roots_callback(callback)
+    }
+
+    /// Limit the heap to `bytes`, or remove the limit if `bytes` is 0. Once
+    /// the heap can't grow any further, and a collection doesn't free enough
+    /// space, allocations fail, so `Gc::new` and friends panic.
+    pub fn set_max_heap_size(bytes: usize) {
+        unsafe { boehm::GC_set_max_heap_size(bytes) }
+    }
+
+    /// Grow the heap by at least `bytes` up front, e.g. to avoid the
+    /// collections that growing it gradually would cause during start-up.
+    /// Returns false if the heap couldn't be grown, e.g. because it would
+    /// exceed the limit set by `set_max_heap_size`.
+    pub fn expand_heap(bytes: usize) -> bool {
+        unsafe { boehm::GC_expand_hp(bytes) != 0 }
+    }
+
+    /// Set how eagerly the collector collects rather than growing the heap.
+    /// Roughly, it collects once `heap size / divisor` bytes have been
+    /// allocated since the last collection, so larger values mean more
+    /// frequent collections and a smaller heap. The default is 3.
+    ///
+    /// # Panics
+    ///
+    /// If `divisor` is 0.
+    pub fn set_free_space_divisor(divisor: usize) {
+        assert!(divisor > 0, "the free space divisor must be at least 1");
+        unsafe { boehm::GC_set_free_space_divisor(divisor) }
+    }
+
+    /// Switch to incremental, generational collection: most collections only
+    /// mark from objects which were recently allocated or written to, and
+    /// marking may be split into slices (see `set_time_limit`). This can't
+    /// be undone.
+    ///
+    /// The collector finds the objects written to itself, with page
+    /// protection or the kernel's soft-dirty bits, rather than relying on
+    /// every write being reported with `end_stubborn_change`.
+    pub fn enable_incremental() {
+        unsafe {
+            boehm::GC_set_manual_vdb_allowed(0);
+            boehm::GC_enable_incremental()
+        }
+    }
+
+    /// Limit each slice of an incremental collection to about `ms`
+    /// milliseconds, or remove the limit if `ms` is `None`, in which case
+    /// each collection runs to completion.
+    pub fn set_time_limit(ms: Option<usize>) {
+        let ms = ms.map_or(TIME_UNLIMITED, |ms| ms.min(TIME_UNLIMITED - 1));
+        unsafe { boehm::GC_set_time_limit(ms) }
+    }
+
+    /// Set the number of partial collections between full ones, when
+    /// incremental.
+    pub fn set_full_freq(n: usize) {
+        unsafe { boehm::GC_set_full_freq(n.min(i32::MAX as usize) as i32) }
+    }
+
+    /// Do a small amount of collection work, e.g. one slice of an
+    /// incremental collection. Returns true if a collection is still in
+    /// progress.
+    pub fn collect_a_little() -> bool {
+        unsafe { boehm::GC_collect_a_little() != 0 }
+    }
+
+    /// Set the number of threads used for marking. 0 lets the collector
+    /// decide. This only has an effect before the collector is initialized.
+    pub fn set_markers_count(n: u32) {
+        unsafe { boehm::GC_set_markers_count(n) }
+    }
+
+    /// Whether the collector should install `pthread_atfork` handlers (which
+    /// call `GC_atfork_prepare`, `GC_atfork_parent` and `GC_atfork_child`)
+    /// so that it stays usable in the child of a `fork`. This only takes
+    /// effect if set before the collector is initialized.
+    pub fn set_handle_fork(enable: bool) {
+        unsafe { boehm::GC_set_handle_fork(enable as i32) }
+    }
+
+    /// When enabled, finalizers of unreachable objects are queued rather than
+    /// run, until `invoke_finalizers` is called.
+    pub fn set_finalize_on_demand(enable: bool) {
+        unsafe { boehm::GC_set_finalize_on_demand(enable as i32) }
+    }
+
+    /// Set the function which the collector calls, outside of the allocation
+    /// lock, when finalizers have been queued while `set_finalize_on_demand`
+    /// is enabled. It is called on whichever thread allocated or collected.
+    ///
+    /// # Safety
+    ///
+    /// The callback is called at arbitrary points in the program, e.g. at
+    /// the end of an allocation, so it must not run finalizers itself, or
+    /// take locks which the interrupted code may hold.
+    pub unsafe fn set_finalizer_notifier(notifier: Option<unsafe extern "C" fn()>) {
+        boehm::GC_set_finalizer_notifier(notifier)
+    }
+
+    /// Run any queued finalizers on the current thread, returning how many
+    /// were run.
+    pub fn invoke_finalizers() -> usize {
+        unsafe { boehm::GC_invoke_finalizers() as usize }
+    }
+
+    /// Whether there are queued finalizers waiting to be run.
+    pub fn has_queued_finalizers() -> bool {
+        unsafe { boehm::GC_should_invoke_finalizers() != 0 }
+    }
+
+    /// The number of objects whose finalizers have been queued to run since
+    /// the first finalizer was registered. This includes finalizers which
+    /// have since run.
+    pub fn finalizers_queued() -> usize {
+        FINALIZERS_QUEUED.load(Ordering::Relaxed)
+    }
+
     pub fn get_stats() -> GcStats {
         let mut ps = boehm::ProfileStats::default();
         unsafe {
@@ -134,24 +674,51 @@ impl GcAllocator {
             );
         }
         let total_gc_time = unsafe { boehm::GC_get_full_gc_total_time() };
+        GcStats::new(&ps, total_gc_time)
+    }
 
-        GcStats {
-            total_gc_time,
-            num_collections: ps.gc_no,
-            total_freed: ps.bytes_reclaimed_since_gc,
-            total_alloced: ps.bytes_allocd_since_gc,
+    /// As `get_stats`, but without taking the allocation lock, so that it can
+    /// be called from a signal handler. The numbers may be inconsistent if
+    /// the collector is running, and the total collection time (which can
+    /// only be read with the lock held) is reported as 0.
+    pub fn get_stats_unlocked() -> GcStats {
+        let mut ps = boehm::ProfileStats::default();
+        unsafe {
+            boehm::GC_get_prof_stats_unsafe(
+                &mut ps as *mut boehm::ProfileStats,
+                core::mem::size_of::<boehm::ProfileStats>(),
+            );
         }
+        GcStats::new(&ps, 0)
     }
 
     pub fn init() {
         unsafe { boehm::GC_init() }
     }
 
+    /// Whether the collector has been initialized, either by `init` or by
+    /// the first allocation.
+    pub fn is_initialized() -> bool {
+        unsafe { boehm::GC_is_init_called() != 0 }
+    }
+
     /// Returns true if thread was successfully registered.
     pub unsafe fn register_thread(stack_base: *mut u8) -> bool {
         boehm::GC_register_my_thread(stack_base) == 0
     }
 
+    /// Register the current thread, using the collector's own estimate of
+    /// where its stack begins. Returns true if the thread was successfully
+    /// registered.
+    pub fn register_current_thread() -> bool {
+        // On x86_64, the only field of `GC_stack_base` is the base.
+        let mut stack_base = core::ptr::null_mut();
+        unsafe {
+            boehm::GC_get_stack_base(&mut stack_base) == 0
+                && Self::register_thread(&mut stack_base as *mut _ as *mut u8)
+        }
+    }
+
     /// Returns true if thread was successfully unregistered.
     pub unsafe fn unregister_thread() -> bool {
         boehm::GC_unregister_my_thread() == 0
@@ -164,6 +731,129 @@ impl GcAllocator {
     pub fn allow_register_threads() {
         unsafe { boehm::GC_allow_register_threads() }
     }
+
+    /// The settings the collector is actually running with, whether they
+    /// came from its build, environment variables, or calls made at run
+    /// time.
+    pub fn collector_config() -> CollectorConfig {
+        unsafe {
+            let version = boehm::GC_get_version();
+            CollectorConfig {
+                version: ((version >> 16) as u8, (version >> 8) as u8, version as u8),
+                heap_size: boehm::GC_get_heap_size(),
+                free_space_divisor: boehm::GC_get_free_space_divisor(),
+                full_freq: boehm::GC_get_full_freq() as usize,
+                incremental: boehm::GC_is_incremental_mode() != 0,
+                time_limit_ms: match boehm::GC_get_time_limit() {
+                    TIME_UNLIMITED => None,
+                    ms => Some(ms),
+                },
+                markers: boehm::GC_get_parallel() as usize + 1,
+                all_interior_pointers: boehm::GC_get_all_interior_pointers() != 0,
+                finalize_on_demand: boehm::GC_get_finalize_on_demand() != 0,
+            }
+        }
+    }
+}
+
+/// See `GcAllocator::collector_config`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CollectorConfig {
+    /// The collector's (major, minor, micro) version.
+    pub version: (u8, u8, u8),
+    /// The current heap size in bytes.
+    pub heap_size: usize,
+    /// Higher values collect more often, keeping the heap smaller.
+    pub free_space_divisor: usize,
+    /// The number of partial collections between full ones, when
+    /// incremental.
+    pub full_freq: usize,
+    pub incremental: bool,
+    /// The longest an incremental collection slice should take, if limited.
+    pub time_limit_ms: Option<usize>,
+    /// The number of threads which mark, including the one which initiates
+    /// a collection.
+    pub markers: usize,
+    pub all_interior_pointers: bool,
+    pub finalize_on_demand: bool,
+}
+
+/// The collector's `GC_TIME_UNLIMITED`.
+const TIME_UNLIMITED: usize = 999999;
+
+static FINALIZERS_QUEUED: AtomicUsize = AtomicUsize::new(0);
+static COUNTING_QUEUED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" fn on_finalizer_queued(_: *mut u8) {
+    FINALIZERS_QUEUED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Start counting queued finalizers, if that hasn't been done already. This
+/// is done before the first finalizer is registered, rather than when the
+/// collector is initialized, so that it works however that happens.
+fn count_queued_finalizers() {
+    if !COUNTING_QUEUED.load(Ordering::Relaxed) && !COUNTING_QUEUED.swap(true, Ordering::Relaxed) {
+        unsafe { boehm::GC_set_await_finalize_proc(Some(on_finalizer_queued)) }
+    }
+}
+
+/// The collector's kind for `allocate_cold`, or `NO_KIND` if it hasn't been
+/// created yet.
+static COLD_KIND: AtomicU32 = AtomicU32::new(NO_KIND);
+const NO_KIND: u32 = u32::MAX;
+
+/// Returns the kind used by `allocate_cold`, creating it on first use. It is
+/// scanned and cleared just like the collector's normal kind.
+fn cold_kind() -> u32 {
+    let kind = COLD_KIND.load(Ordering::Acquire);
+    if kind != NO_KIND {
+        return kind;
+    }
+    unsafe { boehm::GC_init() };
+    // Creating the kind under the lock stops racing threads from each using
+    // up one of the collector's few kinds.
+    GcAllocator::with_alloc_lock(|| {
+        let kind = COLD_KIND.load(Ordering::Relaxed);
+        if kind != NO_KIND {
+            return kind;
+        }
+        // A length descriptor (0) with the object size added to it, as for
+        // the normal kind, means every word of the object is scanned.
+        let kind = unsafe { boehm::GC_new_kind_inner(boehm::GC_new_free_list_inner(), 0, 1, 1) };
+        COLD_KIND.store(kind, Ordering::Release);
+        kind
+    })
+}
+
+/// Parse a decimal number from the environment at compile time.
+const fn parse_usize(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut n = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        n = n * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+    n
+}
+
+/// The stages of a collection reported to `set_on_collection_event`. These
+/// mirror Boehm's `GC_EventType`.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GcEvent {
+    Start,
+    MarkStart,
+    MarkEnd,
+    ReclaimStart,
+    ReclaimEnd,
+    End,
+    PreStopWorld,
+    PostStopWorld,
+    PreStartWorld,
+    PostStartWorld,
+    ThreadSuspended,
+    ThreadUnsuspended,
 }
 
 #[derive(Debug)]
@@ -172,4 +862,35 @@ pub struct GcStats {
     num_collections: usize,
     total_freed: usize,   // In bytes
     total_alloced: usize, // In bytes
+    heap_size: usize,     // In bytes
+    free_bytes: usize,    // In bytes
+}
+
+impl GcStats {
+    fn new(ps: &boehm::ProfileStats, total_gc_time: usize) -> Self {
+        GcStats {
+            total_gc_time,
+            num_collections: ps.gc_no,
+            total_freed: ps.bytes_reclaimed_since_gc,
+            total_alloced: ps.bytes_allocd_since_gc,
+            heap_size: ps.heapsize_full,
+            free_bytes: ps.free_bytes_full,
+        }
+    }
+
+    /// Write these statistics to `w` on a single line. This doesn't allocate
+    /// (unless `w` does).
+    pub fn write_to<W: core::fmt::Write>(&self, w: &mut W) -> core::fmt::Result {
+        write!(
+            w,
+            "{} collections taking {} ms, heap {} bytes ({} free), \
+             {} bytes allocated and {} freed around the last collection",
+            self.num_collections,
+            self.total_gc_time,
+            self.heap_size,
+            self.free_bytes,
+            self.total_alloced,
+            self.total_freed
+        )
+    }
 }
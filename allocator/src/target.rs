@@ -0,0 +1,110 @@
+//! How the collector is configured for the target being compiled for. This is
+//! used by `build.rs`, and is part of the crate only so that it can be unit
+//! tested.
+use std::{borrow::ToOwned, format, string::String, vec};
+
+static FPIC: &str = "-fPIC";
+static MULTITHREADED: &str = "-DGC_ALWAYS_MULTITHREADED";
+
+/// Flags which depend on the target being compiled for. Build scripts run on
+/// the host, so these must be read from cargo's `CARGO_CFG_*` variables rather
+/// than with `#[cfg]`.
+pub struct Target {
+    pub triple: String,
+    pub os: String,
+    pub env: String,
+    pub pointer_width: usize,
+}
+
+impl Target {
+    /// Pointers are word aligned, so the collector can ignore the low bits of
+    /// any candidate pointer. Programs may use those bits as tags.
+    pub fn pointer_mask(&self) -> u64 {
+        !((self.pointer_width as u64 / 8) - 1)
+    }
+
+    /// The triple to pass to `./configure --host` when cross-compiling.
+    pub fn gnu_triple(&self) -> String {
+        match (self.os.as_str(), self.env.as_str()) {
+            ("windows", "gnu") => "x86_64-w64-mingw32".to_owned(),
+            ("linux", "musl") => "x86_64-linux-musl".to_owned(),
+            _ => self.triple.clone(),
+        }
+    }
+
+    /// `mark_stack_size` is the initial number of mark stack entries, if
+    /// one was given.
+    pub fn cflags(&self, mark_stack_size: Option<usize>) -> String {
+        let mut flags = vec![
+            format!("-DPOINTER_MASK={:#X}", self.pointer_mask()),
+            MULTITHREADED.to_owned(),
+        ];
+        if let Some(size) = mark_stack_size {
+            flags.push(format!("-DINITIAL_MARK_STACK_SIZE={}", size));
+        }
+        // Code is always position independent on Windows, and mingw's gcc
+        // warns if asked for it explicitly.
+        if self.os != "windows" {
+            flags.push(FPIC.to_owned());
+        }
+        flags.join(" ")
+    }
+
+    /// musl targets need a C compiler which links against musl rather than
+    /// glibc. An explicit `CC` always takes precedence.
+    pub fn cc(&self, cc: Option<String>) -> Option<String> {
+        match cc {
+            Some(cc) => Some(cc),
+            None if self.env == "musl" => Some("musl-gcc".to_owned()),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn target(triple: &str, os: &str, env: &str) -> Target {
+        Target {
+            triple: triple.to_owned(),
+            os: os.to_owned(),
+            env: env.to_owned(),
+            pointer_width: 64,
+        }
+    }
+
+    #[test]
+    fn test_linux_gnu() {
+        let t = target("x86_64-unknown-linux-gnu", "linux", "gnu");
+        assert_eq!(t.pointer_mask(), !7);
+        assert_eq!(t.gnu_triple(), "x86_64-unknown-linux-gnu");
+        assert_eq!(t.cc(None), None);
+        assert_eq!(
+            t.cflags(None),
+            "-DPOINTER_MASK=0xFFFFFFFFFFFFFFF8 -DGC_ALWAYS_MULTITHREADED -fPIC"
+        );
+        assert_eq!(
+            t.cflags(Some(512)),
+            "-DPOINTER_MASK=0xFFFFFFFFFFFFFFF8 -DGC_ALWAYS_MULTITHREADED \
+             -DINITIAL_MARK_STACK_SIZE=512 -fPIC"
+        );
+    }
+
+    #[test]
+    fn test_linux_musl() {
+        let t = target("x86_64-unknown-linux-musl", "linux", "musl");
+        assert_eq!(t.gnu_triple(), "x86_64-linux-musl");
+        assert_eq!(t.cc(None).as_deref(), Some("musl-gcc"));
+        assert_eq!(t.cc(Some("clang".to_owned())).as_deref(), Some("clang"));
+        assert!(t.cflags(None).ends_with(" -fPIC"));
+    }
+
+    #[test]
+    fn test_windows_gnu() {
+        let t = target("x86_64-pc-windows-gnu", "windows", "gnu");
+        assert_eq!(t.gnu_triple(), "x86_64-w64-mingw32");
+        assert_eq!(t.cc(None), None);
+        assert!(!t.cflags(None).contains("-fPIC"));
+    }
+}
@@ -0,0 +1,46 @@
+//! Microbenchmarks for the operations on `Gc` which interpreters do in their
+//! inner loops, run with `cargo bench --features standalone`. Each should
+//! cost about the same as the equivalent operation on a `&T`.
+#![feature(test)]
+
+extern crate test;
+
+use libgc::{Gc, GcAllocator};
+use test::{black_box, Bencher};
+
+#[global_allocator]
+static ALLOCATOR: GcAllocator = GcAllocator;
+
+const N: usize = 1000;
+
+#[bench]
+fn deref(b: &mut Bencher) {
+    let gcs = (0..N).map(Gc::new).collect::<Vec<_>>();
+    b.iter(|| gcs.iter().map(|gc| **gc).sum::<usize>());
+}
+
+#[bench]
+fn deref_ref(b: &mut Bencher) {
+    let refs = (0..N).map(|i| &*Box::leak(Box::new(i))).collect::<Vec<_>>();
+    b.iter(|| refs.iter().map(|r| **r).sum::<usize>());
+}
+
+#[bench]
+fn clone(b: &mut Bencher) {
+    let gc = Gc::new(1usize);
+    b.iter(|| {
+        for _ in 0..N {
+            black_box(black_box(&gc).clone());
+        }
+    });
+}
+
+#[bench]
+fn ptr_eq(b: &mut Bencher) {
+    let (x, y) = (Gc::new(1usize), Gc::new(1usize));
+    b.iter(|| {
+        (0..N)
+            .filter(|_| Gc::ptr_eq(black_box(&x), black_box(&y)))
+            .count()
+    });
+}
@@ -0,0 +1,38 @@
+//! Allocation microbenchmarks, run with `cargo bench --features standalone`.
+//!
+//! `usize` and `[usize; 4]` don't need dropping, so allocating them should
+//! cost no more than the allocation itself. `String` shows the cost of
+//! registering a finalizer for comparison, and `String` with finalizers
+//! disabled the cost of allocating it without one.
+#![feature(test)]
+
+extern crate test;
+
+use libgc::{Gc, GcAllocator, GcConfig};
+use test::{black_box, Bencher};
+
+#[global_allocator]
+static ALLOCATOR: GcAllocator = GcAllocator;
+
+#[bench]
+fn alloc_usize(b: &mut Bencher) {
+    b.iter(|| black_box(Gc::new(1usize)));
+}
+
+#[bench]
+fn alloc_array(b: &mut Bencher) {
+    b.iter(|| black_box(Gc::new([1usize; 4])));
+}
+
+#[bench]
+fn alloc_string(b: &mut Bencher) {
+    b.iter(|| black_box(Gc::new(String::new())));
+}
+
+#[bench]
+fn alloc_string_finalizers_disabled(b: &mut Bencher) {
+    // Nothing is leaked, as an empty `String` owns no memory.
+    unsafe { GcConfig::disable_all_finalizers() };
+    b.iter(|| black_box(Gc::new(String::new())));
+    GcConfig::enable_all_finalizers();
+}
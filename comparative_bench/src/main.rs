@@ -0,0 +1,269 @@
+//! Runs the same workloads with `Gc`, `Rc`, and `Arc`, and prints a markdown
+//! table of the results:
+//!
+//! ```text
+//! cargo run --release -p comparative_bench -- [--scale N] [--max-slowdown X]
+//! ```
+//!
+//! `--scale` multiplies the size of every workload. With `--max-slowdown`,
+//! the exit status is non-zero if `Gc` takes more than `X` times as long as
+//! the fastest other backend on any workload, so this can be used as a
+//! regression gate.
+//!
+//! `GcAllocator` is the global allocator throughout, as `Gc` requires, so
+//! the numbers compare reference management rather than `malloc`
+//! implementations. `Rc` and `Arc` leak the cycles made by `graph_churn`.
+#![feature(test)]
+
+extern crate test;
+
+use std::{
+    env, process,
+    time::{Duration, Instant},
+};
+
+use libgc::GcAllocator;
+
+#[global_allocator]
+static ALLOCATOR: GcAllocator = GcAllocator;
+
+/// Each workload is timed this many times, and the fastest run reported.
+const RUNS: usize = 3;
+
+/// Defines a module of workloads for one smart pointer type. Every backend
+/// runs the same code, differing only in `$ptr` and in how it is created.
+macro_rules! backend {
+    ($module:ident, $name:expr, $ptr:ident, $new:path) => {
+        mod $module {
+            use std::{cell::RefCell, collections::HashMap};
+            use test::black_box;
+
+            #[allow(unused_imports)]
+            use libgc::Gc;
+            #[allow(unused_imports)]
+            use std::{rc::Rc, sync::Arc};
+
+            pub const NAME: &str = $name;
+
+            struct Tree {
+                children: Option<($ptr<Tree>, $ptr<Tree>)>,
+            }
+
+            fn make_tree(depth: usize) -> $ptr<Tree> {
+                $new(Tree {
+                    children: match depth {
+                        0 => None,
+                        _ => Some((make_tree(depth - 1), make_tree(depth - 1))),
+                    },
+                })
+            }
+
+            fn check_tree(tree: &Tree) -> usize {
+                match &tree.children {
+                    None => 1,
+                    Some((l, r)) => 1 + check_tree(l) + check_tree(r),
+                }
+            }
+
+            /// Build and walk many short-lived trees while one long-lived
+            /// tree stays reachable, as in the classic binary-trees
+            /// benchmark.
+            pub fn binary_trees(scale: usize) {
+                let long_lived = make_tree(16);
+                for _ in 0..8 * scale {
+                    for depth in (4..=14).step_by(2) {
+                        black_box(check_tree(&make_tree(depth)));
+                    }
+                }
+                black_box(check_tree(&long_lived));
+            }
+
+            struct Node {
+                edges: RefCell<Vec<$ptr<Node>>>,
+            }
+
+            /// Repeatedly replace nodes in a graph whose edges form cycles.
+            pub fn graph_churn(scale: usize) {
+                const NODES: usize = 1000;
+                let mut nodes = (0..NODES)
+                    .map(|_| {
+                        $new(Node {
+                            edges: RefCell::new(Vec::new()),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut rng = 0x9e37_79b9_7f4a_7c15u64;
+                for _ in 0..200_000 * scale {
+                    rng ^= rng << 13;
+                    rng ^= rng >> 7;
+                    rng ^= rng << 17;
+                    let (i, j) = ((rng as usize) % NODES, ((rng >> 32) as usize) % NODES);
+                    let node = $new(Node {
+                        edges: RefCell::new(vec![nodes[j].clone()]),
+                    });
+                    nodes[j].edges.borrow_mut().push(node.clone());
+                    nodes[i] = node;
+                }
+                black_box(nodes);
+            }
+
+            /// Intern strings, so that equal strings share one allocation.
+            pub fn string_interning(scale: usize) {
+                let mut table: HashMap<String, $ptr<String>> = HashMap::new();
+                let mut interned = Vec::new();
+                for i in 0..200_000 * scale {
+                    let s = format!("symbol{}", i % 5000);
+                    let p = match table.get(&s) {
+                        Some(p) => p.clone(),
+                        None => {
+                            let p = $new(s.clone());
+                            table.insert(s, p.clone());
+                            p
+                        }
+                    };
+                    interned.push(p);
+                }
+                black_box(interned);
+            }
+
+            /// Pass values from a producer thread to a consumer. `None` if
+            /// the pointer type can't be sent between threads.
+            pub fn producer_consumer(scale: usize) -> Option<()> {
+                backend!(@producer_consumer $ptr, $new, scale)
+            }
+        }
+    };
+    (@producer_consumer Rc, $new:path, $scale:expr) => {{
+        let _ = $scale;
+        None
+    }};
+    (@producer_consumer $ptr:ident, $new:path, $scale:expr) => {{
+        let scale = $scale;
+        let (tx, rx) = std::sync::mpsc::sync_channel::<$ptr<Vec<usize>>>(1024);
+        let producer = crate::spawn(move || {
+            for i in 0..100_000 * scale {
+                tx.send($new(vec![i; 4])).unwrap();
+            }
+        });
+        let consumer = crate::spawn(move || rx.iter().map(|v| v[0]).sum::<usize>());
+        producer.join().unwrap();
+        black_box(consumer.join().unwrap());
+        Some(())
+    }};
+}
+
+backend!(gc, "Gc", Gc, Gc::new);
+backend!(rc, "Rc", Rc, Rc::new);
+backend!(arc, "Arc", Arc, Arc::new);
+
+/// Spawn a thread which is registered with the collector, so that values
+/// on its stack are roots.
+fn spawn<F, T>(f: F) -> std::thread::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    GcAllocator::allow_register_threads();
+    std::thread::spawn(move || {
+        // The address of a local in the thread's outermost frame is a good
+        // enough stack base.
+        let mut base = 0usize;
+        let mut stack_base = &mut base as *mut usize as *mut u8;
+        assert!(unsafe { GcAllocator::register_thread(&mut stack_base as *mut _ as *mut u8) });
+        let ret = f();
+        assert!(unsafe { GcAllocator::unregister_thread() });
+        ret
+    })
+}
+
+/// Returns the fastest of `RUNS` runs of `f`, or `None` if `f` doesn't
+/// support this backend.
+fn time(f: impl Fn() -> Option<()>) -> Option<Duration> {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            f().map(|()| start.elapsed())
+        })
+        .min()
+        .flatten()
+}
+
+struct Workload {
+    name: &'static str,
+    /// Times for `Gc`, `Rc`, and `Arc`, in that order.
+    times: [Option<Duration>; 3],
+}
+
+fn main() {
+    let mut scale = 1;
+    let mut max_slowdown = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        match (arg.as_str(), value.as_deref().map(str::parse::<f64>)) {
+            ("--scale", Some(Ok(v))) if v >= 1.0 => scale = v as usize,
+            ("--max-slowdown", Some(Ok(v))) if v > 0.0 => max_slowdown = Some(v),
+            _ => {
+                eprintln!("usage: comparative_bench [--scale N] [--max-slowdown X]");
+                process::exit(2);
+            }
+        }
+    }
+
+    macro_rules! workload {
+        ($name:ident) => {
+            Workload {
+                name: stringify!($name),
+                times: [
+                    time(|| Some(gc::$name(scale))),
+                    time(|| Some(rc::$name(scale))),
+                    time(|| Some(arc::$name(scale))),
+                ],
+            }
+        };
+    }
+    let workloads = [
+        workload!(binary_trees),
+        workload!(graph_churn),
+        workload!(string_interning),
+        Workload {
+            name: "producer_consumer",
+            times: [
+                time(|| gc::producer_consumer(scale)),
+                time(|| rc::producer_consumer(scale)),
+                time(|| arc::producer_consumer(scale)),
+            ],
+        },
+    ];
+
+    println!("| workload | {} | {} | {} |", gc::NAME, rc::NAME, arc::NAME);
+    println!("|---|---:|---:|---:|");
+    let mut regressed = false;
+    for w in &workloads {
+        let fastest_other = w.times[1..].iter().flatten().min();
+        let cells = w
+            .times
+            .iter()
+            .map(|t| match t {
+                Some(t) => format!("{:.1} ms", t.as_secs_f64() * 1000.0),
+                None => String::from("n/a"),
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "| {} | {} | {} | {} |",
+            w.name, cells[0], cells[1], cells[2]
+        );
+        if let (Some(limit), Some(gc), Some(other)) = (max_slowdown, w.times[0], fastest_other) {
+            if gc.as_secs_f64() > limit * other.as_secs_f64() {
+                eprintln!(
+                    "{}: Gc is more than {}x slower than the fastest alternative",
+                    w.name, limit
+                );
+                regressed = true;
+            }
+        }
+    }
+    if regressed {
+        process::exit(1);
+    }
+}
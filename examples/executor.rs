@@ -0,0 +1,68 @@
+//! Drive async tasks whose state lives on the GC heap, with `cargo run
+//! --example executor --features standalone`.
+//!
+//! Each task shares a `Gc` counter with the others, and a task which is
+//! never woken again is reclaimed by the collector rather than leaked.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use libgc::{
+    executor::{yield_now, Executor},
+    Gc, GcAllocator,
+};
+
+#[global_allocator]
+static ALLOCATOR: GcAllocator = GcAllocator;
+
+static STUCK_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// A future which is never ready, and drops its waker.
+struct Stuck;
+
+impl Future for Stuck {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+        Poll::Pending
+    }
+}
+
+impl Drop for Stuck {
+    fn drop(&mut self) {
+        STUCK_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn main() {
+    GcAllocator::init();
+    let ex = Executor::new();
+    let counter = Gc::new(AtomicUsize::new(0));
+    for i in 0..4 {
+        ex.spawn(async move {
+            for step in 0..3 {
+                println!("task {} step {}", i, step);
+                counter.fetch_add(1, Ordering::Relaxed);
+                yield_now().await;
+            }
+        });
+    }
+    for _ in 0..1000 {
+        ex.spawn(Stuck);
+    }
+    let polls = ex.run_until_stalled();
+    println!(
+        "{} polls, counter = {}",
+        polls,
+        counter.load(Ordering::Relaxed)
+    );
+
+    GcAllocator::force_gc();
+    println!(
+        "{} of 1000 stuck tasks were collected",
+        STUCK_DROPPED.load(Ordering::Relaxed)
+    );
+}
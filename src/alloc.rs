@@ -0,0 +1,477 @@
+//! An object-safe facade over the GC allocator.
+//!
+//! `GcAllocator` is a concrete type, and most of its functionality is only
+//! reachable through inherent methods or (with rustgc) unstable allocator
+//! traits. `GcAlloc` collects the allocation surface used by `Gc` into a
+//! trait which can be used as `dyn GcAlloc`, so embedders can substitute mock
+//! or instrumented allocators without recompiling this crate with extra
+//! features.
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    any::Any,
+    cell::{Cell, RefCell},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    config::{self, FinalizerPanic},
+    GcAllocator, ALLOCATOR,
+};
+
+/// A finalizer as understood by the collector. It is called with a pointer to
+/// the base of the dead object and the client data it was registered with.
+pub type Finalizer = unsafe extern "C" fn(*mut u8, *mut u8);
+
+pub trait GcAlloc: Send + Sync {
+    /// Allocate a zeroed block which is scanned conservatively.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+    /// Allocate a block which is never scanned for pointers.
+    fn alloc_atomic(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+    /// Allocate a block where only the words whose bits are set in `bitmap`
+    /// are scanned for pointers.
+    fn alloc_precise(
+        &self,
+        layout: Layout,
+        bitmap: usize,
+        bitmap_size: usize,
+    ) -> Result<NonNull<[u8]>, AllocError>;
+
+    /// Register `finalizer` to be called with `client_data` once `obj` is
+    /// unreachable. Any previously registered finalizer is replaced.
+    ///
+    /// # Safety
+    ///
+    /// `obj` must point to the base of a block returned by this allocator,
+    /// and `finalizer` must be safe to call on it once it is unreachable.
+    unsafe fn register_finalizer(
+        &self,
+        obj: *mut u8,
+        finalizer: Option<Finalizer>,
+        client_data: *mut u8,
+    );
+
+    /// As `register_finalizer`, but the finalizer is ordered: see
+    /// `Gc::new_with_ordered_finalizer`. Allocators which can't order
+    /// finalizers register an unordered one instead, which is the default.
+    ///
+    /// # Safety
+    ///
+    /// As for `register_finalizer`.
+    unsafe fn register_ordered_finalizer(
+        &self,
+        obj: *mut u8,
+        finalizer: Option<Finalizer>,
+        client_data: *mut u8,
+    ) {
+        self.register_finalizer(obj, finalizer, client_data)
+    }
+
+    fn unregister_finalizer(&self, obj: *mut u8);
+}
+
+impl GcAlloc for GcAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Allocator::allocate(self, layout)
+    }
+
+    #[cfg(feature = "standalone")]
+    fn alloc_atomic(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_atomic(layout)
+    }
+
+    #[cfg(not(feature = "standalone"))]
+    fn alloc_atomic(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Allocator::alloc_untraceable(self, layout)
+    }
+
+    #[cfg(feature = "standalone")]
+    fn alloc_precise(
+        &self,
+        layout: Layout,
+        bitmap: usize,
+        bitmap_size: usize,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_precise(layout, bitmap, bitmap_size)
+    }
+
+    #[cfg(not(feature = "standalone"))]
+    fn alloc_precise(
+        &self,
+        layout: Layout,
+        bitmap: usize,
+        bitmap_size: usize,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Allocator::alloc_precise(self, layout, bitmap, bitmap_size)
+    }
+
+    unsafe fn register_finalizer(
+        &self,
+        obj: *mut u8,
+        finalizer: Option<Finalizer>,
+        client_data: *mut u8,
+    ) {
+        if let Some(finalizer) = finalizer {
+            if buffer_finalizer((obj, finalizer, client_data, false)) {
+                return;
+            }
+        }
+        GcAllocator::register_finalizer(
+            self,
+            obj,
+            finalizer,
+            client_data,
+            ::std::ptr::null_mut(),
+            ::std::ptr::null_mut(),
+        )
+    }
+
+    #[cfg(feature = "standalone")]
+    unsafe fn register_ordered_finalizer(
+        &self,
+        obj: *mut u8,
+        finalizer: Option<Finalizer>,
+        client_data: *mut u8,
+    ) {
+        if let Some(finalizer) = finalizer {
+            if buffer_finalizer((obj, finalizer, client_data, true)) {
+                return;
+            }
+        }
+        GcAllocator::register_ordered_finalizer(self, obj, finalizer, client_data)
+    }
+
+    fn unregister_finalizer(&self, obj: *mut u8) {
+        // Otherwise a pending registration for `obj` would be flushed after,
+        // and undo, the unregistration.
+        flush_finalizers();
+        GcAllocator::unregister_finalizer(self, obj)
+    }
+}
+
+/// Types which never contain pointers to GC objects, so the collector need
+/// not scan blocks which only hold values of them.
+///
+/// `Gc` allocates such values with `GcAlloc::alloc_atomic`. For strings and
+/// byte buffers, which make up much of a typical script heap, this saves the
+/// collector from scanning every byte while marking.
+///
+/// # Safety
+///
+/// Implementing this for a type which can hold a pointer into the GC heap,
+/// even indirectly (e.g. through a `Box` allocated by `GcAllocator`), lets the
+/// collector free objects which are still in use.
+#[rustc_unsafe_specialization_marker]
+pub unsafe trait NoGcPointers {}
+
+macro_rules! no_gc_pointers {
+    ($($t:ty),*) => {
+        $(unsafe impl NoGcPointers for $t {})*
+    };
+}
+
+no_gc_pointers!(
+    (),
+    bool,
+    char,
+    f32,
+    f64,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    str
+);
+
+unsafe impl<T: NoGcPointers> NoGcPointers for [T] {}
+unsafe impl<T: NoGcPointers, const N: usize> NoGcPointers for [T; N] {}
+
+macro_rules! no_gc_pointers_tuple {
+    ($($t:ident),*) => {
+        unsafe impl<$($t: NoGcPointers),*> NoGcPointers for ($($t,)*) {}
+    };
+}
+
+no_gc_pointers_tuple!(A);
+no_gc_pointers_tuple!(A, B);
+no_gc_pointers_tuple!(A, B, C);
+no_gc_pointers_tuple!(A, B, C, D);
+no_gc_pointers_tuple!(A, B, C, D, E);
+no_gc_pointers_tuple!(A, B, C, D, E, F);
+
+trait Scanned {
+    fn needs_scanning() -> bool;
+}
+
+impl<T: ?Sized> Scanned for T {
+    #[inline(always)]
+    default fn needs_scanning() -> bool {
+        true
+    }
+}
+
+impl<T: ?Sized + NoGcPointers> Scanned for T {
+    #[inline(always)]
+    fn needs_scanning() -> bool {
+        false
+    }
+}
+
+/// Returns false if `T` implements `NoGcPointers`.
+#[inline(always)]
+pub(crate) fn needs_scanning<T: ?Sized>() -> bool {
+    <T as Scanned>::needs_scanning()
+}
+
+/// Allocate a block for values of type `T`, which is only scanned if `T` may
+/// contain GC pointers. Blocks which aren't scanned aren't zeroed either.
+pub(crate) fn alloc_for<T: ?Sized, A: GcAlloc + ?Sized>(
+    alloc: &A,
+    layout: Layout,
+) -> Result<NonNull<[u8]>, AllocError> {
+    if needs_scanning::<T>() {
+        alloc.allocate(layout)
+    } else {
+        alloc.alloc_atomic(layout)
+    }
+}
+
+/// The number of buffered finalizer registrations which triggers a flush when
+/// `GcConfig::finalizer_batching` is enabled.
+const FINALIZER_BATCH_SIZE: usize = 64;
+
+/// Finalizer registrations which have not yet been handed to the collector.
+/// The buffer is allocated by the (global) `GcAllocator`, so the objects it
+/// refers to are kept alive until they are flushed.
+struct PendingFinalizers(Vec<PendingFinalizer>);
+
+/// An object, its finalizer and client data, and whether the finalizer is
+/// ordered.
+type PendingFinalizer = (*mut u8, Finalizer, *mut u8, bool);
+
+impl Drop for PendingFinalizers {
+    fn drop(&mut self) {
+        register_all(mem::take(&mut self.0));
+    }
+}
+
+/// Bumped to ask every thread to flush its buffer, which each does at its
+/// next allocation.
+static FLUSH_EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static PENDING: RefCell<PendingFinalizers> = RefCell::new(PendingFinalizers(Vec::new()));
+    /// The `FLUSH_EPOCH` this thread last saw.
+    static SEEN_FLUSH_EPOCH: Cell<usize> = Cell::new(0);
+}
+
+/// Buffer `pending` if `GcConfig::finalizer_batching` is enabled. Returns
+/// false if it wasn't, and so must be registered now.
+fn buffer_finalizer(pending: PendingFinalizer) -> bool {
+    if !config::finalizer_batching() {
+        return false;
+    }
+    // If this thread is exiting, its buffer may already be gone.
+    let len = PENDING.try_with(|p| {
+        let buf = &mut p.borrow_mut().0;
+        buf.push(pending);
+        buf.len()
+    });
+    match len {
+        Ok(len) => {
+            if len >= FINALIZER_BATCH_SIZE {
+                flush_finalizers();
+            }
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Hand this thread's buffered finalizer registrations to the collector, and
+/// ask every other thread to do the same at its next allocation.
+pub(crate) fn flush_all_finalizers() {
+    FLUSH_EPOCH.fetch_add(1, Ordering::Relaxed);
+    flush_finalizers();
+}
+
+/// Flush this thread's buffer if `flush_all_finalizers` has been called
+/// since it last checked. Called on every `Gc` allocation.
+#[inline]
+pub(crate) fn flush_if_requested() {
+    let epoch = FLUSH_EPOCH.load(Ordering::Relaxed);
+    let requested = SEEN_FLUSH_EPOCH
+        .try_with(|seen| seen.replace(epoch) != epoch)
+        .unwrap_or(false);
+    if requested {
+        flush_finalizers();
+    }
+}
+
+/// Hand any finalizer registrations buffered by this thread to the
+/// collector.
+pub fn flush_finalizers() {
+    // The buffer is swapped out before registering, as registration may
+    // trigger a collection whose finalizers allocate on this thread.
+    let pending = PENDING
+        .try_with(|p| {
+            mem::replace(
+                &mut p.borrow_mut().0,
+                Vec::with_capacity(FINALIZER_BATCH_SIZE),
+            )
+        })
+        .unwrap_or_default();
+    register_all(pending);
+}
+
+fn register_all(pending: Vec<PendingFinalizer>) {
+    for (obj, finalizer, client_data, ordered) in pending {
+        unsafe {
+            #[cfg(feature = "standalone")]
+            if ordered {
+                ALLOCATOR.register_ordered_finalizer(obj, Some(finalizer), client_data);
+                continue;
+            }
+            // Only standalone builds have ordered finalizers.
+            #[cfg(not(feature = "standalone"))]
+            debug_assert!(!ordered);
+            ALLOCATOR.register_finalizer(
+                obj,
+                Some(finalizer),
+                client_data,
+                ::std::ptr::null_mut(),
+                ::std::ptr::null_mut(),
+            )
+        }
+    }
+}
+
+/// A panic caught in a finalizer, recorded when `GcConfig::on_finalizer_panic`
+/// is `FinalizerPanic::Collect`.
+#[derive(Clone, Debug)]
+pub struct FinalizerPanicReport {
+    /// The type of the object being finalized.
+    pub ty: &'static str,
+    pub message: String,
+}
+
+static FINALIZER_PANICS: Mutex<Vec<FinalizerPanicReport>> = Mutex::new(Vec::new());
+
+/// Returns the finalizer panics recorded since the last call.
+pub fn take_finalizer_panics() -> Vec<FinalizerPanicReport> {
+    mem::take(&mut *FINALIZER_PANICS.lock().unwrap())
+}
+
+/// Run `f`, the body of a finalizer for an object of type `ty`, stopping any
+/// panic from unwinding into the collector. Returns `false` if `f` panicked.
+pub(crate) fn catch_finalizer_panic(ty: &'static str, f: impl FnOnce()) -> bool {
+    #[cfg(feature = "stats_counters")]
+    crate::stats::NUM_FINALIZERS_STARTED.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let payload = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(()) => return true,
+        Err(payload) => payload,
+    };
+    #[cfg(feature = "stats_counters")]
+    crate::stats::NUM_FINALIZERS_PANICKED.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+    let message = panic_message(&*payload);
+    match config::finalizer_panic() {
+        FinalizerPanic::Abort => {
+            eprintln!("libgc: finalizer for {} panicked: {}", ty, message);
+            std::process::abort()
+        }
+        FinalizerPanic::Log => eprintln!("libgc: finalizer for {} panicked: {}", ty, message),
+        FinalizerPanic::Collect => {
+            // Finalizers may run while a report is being taken on this thread.
+            if let Ok(mut panics) = FINALIZER_PANICS.try_lock() {
+                panics.push(FinalizerPanicReport { ty, message });
+            }
+        }
+    }
+    // Dropping the payload runs arbitrary code, which could panic in turn.
+    if panic::catch_unwind(AssertUnwindSafe(|| drop(payload))).is_err() {
+        std::process::abort()
+    }
+    false
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Gc, ALLOCATOR};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Forwards to the real allocator, counting allocations on the way.
+    struct Counting(AtomicUsize);
+
+    impl GcAlloc for Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            GcAlloc::allocate(&ALLOCATOR, layout)
+        }
+
+        fn alloc_atomic(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            GcAlloc::alloc_atomic(&ALLOCATOR, layout)
+        }
+
+        fn alloc_precise(
+            &self,
+            layout: Layout,
+            bitmap: usize,
+            bitmap_size: usize,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            GcAlloc::alloc_precise(&ALLOCATOR, layout, bitmap, bitmap_size)
+        }
+
+        unsafe fn register_finalizer(
+            &self,
+            obj: *mut u8,
+            finalizer: Option<Finalizer>,
+            client_data: *mut u8,
+        ) {
+            GcAlloc::register_finalizer(&ALLOCATOR, obj, finalizer, client_data)
+        }
+
+        fn unregister_finalizer(&self, obj: *mut u8) {
+            GcAlloc::unregister_finalizer(&ALLOCATOR, obj)
+        }
+    }
+
+    #[test]
+    fn test_dyn_alloc() {
+        let counting = Counting(AtomicUsize::new(0));
+        let alloc: &dyn GcAlloc = &counting;
+        let a = Gc::new_in(String::from("a"), alloc);
+        let b = Gc::new_in(1usize, alloc);
+        assert_eq!(*a, "a");
+        assert_eq!(*b, 1);
+        assert_eq!(counting.0.load(Ordering::Relaxed), 2);
+    }
+}
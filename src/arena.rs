@@ -0,0 +1,145 @@
+//! Arena allocation whose contents are traced by the collector.
+//!
+//! Arenas (as in the `typed-arena` crate) are a popular way of allocating
+//! AST nodes and the like: allocation is a pointer bump, and everything is
+//! freed at once. But an ordinary arena's chunks come from the system
+//! allocator, so a `Gc` stored in one may not keep its referent alive.
+//! `GcTypedArena` keeps its chunks in the GC heap, where they are scanned.
+use std::{
+    alloc::Layout,
+    cell::{Cell, RefCell},
+    cmp,
+    marker::PhantomData,
+    mem,
+    ptr::{self, NonNull},
+};
+
+use crate::{alloc, ALLOCATOR};
+
+/// The capacity of the first chunk, in bytes. Each chunk after that is twice
+/// the size of the one before.
+const FIRST_CHUNK_BYTES: usize = 4096;
+
+/// An arena of `T`s. Values are dropped when the arena is.
+///
+/// The list of chunks is held by the global allocator, which must be
+/// `GcAllocator` for the chunks to be kept alive.
+pub struct GcTypedArena<T> {
+    /// Every chunk allocated, and its capacity.
+    chunks: RefCell<Vec<(NonNull<T>, usize)>>,
+    /// The next free slot, and the end of the current chunk.
+    next: Cell<*mut T>,
+    end: Cell<*mut T>,
+    /// The number of zero-sized values allocated, which occupy no chunks.
+    num_zsts: Cell<usize>,
+    /// The arena owns the values in it.
+    _phantom: PhantomData<T>,
+}
+
+impl<T> GcTypedArena<T> {
+    pub fn new() -> Self {
+        GcTypedArena {
+            chunks: RefCell::new(Vec::new()),
+            next: Cell::new(ptr::null_mut()),
+            end: Cell::new(ptr::null_mut()),
+            num_zsts: Cell::new(0),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Move `value` into the arena, returning a reference to it which lives
+    /// as long as the arena.
+    #[inline]
+    pub fn alloc(&self, value: T) -> &T {
+        if mem::size_of::<T>() == 0 {
+            self.num_zsts.set(self.num_zsts.get() + 1);
+            mem::forget(value);
+            return unsafe { &*NonNull::dangling().as_ptr() };
+        }
+        if self.next.get() == self.end.get() {
+            self.grow();
+        }
+        unsafe {
+            let slot = self.next.get();
+            ptr::write(slot, value);
+            self.next.set(slot.add(1));
+            &*slot
+        }
+    }
+
+    /// The number of values in the arena.
+    pub fn len(&self) -> usize {
+        if mem::size_of::<T>() == 0 {
+            return self.num_zsts.get();
+        }
+        let chunks = self.chunks.borrow();
+        match chunks.split_last() {
+            Some((_, full)) => {
+                full.iter().map(|(_, cap)| cap).sum::<usize>() + self.last_chunk_len()
+            }
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of values in the most recently allocated chunk.
+    fn last_chunk_len(&self) -> usize {
+        match self.chunks.borrow().last() {
+            Some((start, _)) => unsafe { self.next.get().offset_from(start.as_ptr()) as usize },
+            None => 0,
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn grow(&self) {
+        let mut chunks = self.chunks.borrow_mut();
+        let cap = match chunks.last() {
+            Some((_, cap)) => cap * 2,
+            None => cmp::max(FIRST_CHUNK_BYTES / mem::size_of::<T>(), 1),
+        };
+        let layout = Layout::array::<T>(cap).unwrap();
+        let start = alloc::alloc_for::<T, _>(&ALLOCATOR, layout)
+            .unwrap()
+            .cast::<T>();
+        chunks.push((start, cap));
+        self.next.set(start.as_ptr());
+        self.end.set(unsafe { start.as_ptr().add(cap) });
+    }
+}
+
+impl<T> Default for GcTypedArena<T> {
+    fn default() -> Self {
+        GcTypedArena::new()
+    }
+}
+
+// Values may refer to each other (e.g. a child AST node to its parent), so
+// they may already have been dropped when another value's drop runs. As with
+// `Vec`, only `T`'s own drop must cope with that.
+unsafe impl<#[may_dangle] T> Drop for GcTypedArena<T> {
+    fn drop(&mut self) {
+        if mem::size_of::<T>() == 0 {
+            for _ in 0..self.num_zsts.get() {
+                unsafe { ptr::drop_in_place(NonNull::<T>::dangling().as_ptr()) };
+            }
+            return;
+        }
+        let last_len = self.last_chunk_len();
+        let chunks = self.chunks.get_mut();
+        let num_chunks = chunks.len();
+        for (i, (start, cap)) in chunks.drain(..).enumerate() {
+            let len = if i + 1 == num_chunks { last_len } else { cap };
+            unsafe {
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(start.as_ptr(), len));
+            }
+        }
+        // The chunks themselves are freed by the collector.
+    }
+}
+
+// An arena can be sent to another thread along with the values in it.
+unsafe impl<T: Send> Send for GcTypedArena<T> {}
@@ -0,0 +1,109 @@
+//! Atomic `Gc` slots, for lock-free tables shared between threads (e.g.
+//! global inline caches).
+//!
+//! An `AtomicGc` holds its pointer as is, not hidden in any way, so like a
+//! `Gc` it keeps its referent alive wherever the collector scans: on the
+//! stack of a registered thread, in a static, inside another `Gc`, or in
+//! memory from `GcAllocator`.
+//!
+//! The collector stops the world before it scans, so orderings only matter
+//! between mutator threads. Use `Release` (or stronger) when storing a `Gc`
+//! to an object which was initialized just before, and `Acquire` when
+//! loading one whose contents are then read, so that the reader sees the
+//! object initialized. `Relaxed` suffices when only the pointer's identity
+//! matters, e.g. comparing it against an expected class.
+use std::{
+    fmt,
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::Gc;
+
+/// An optional `Gc<T>` which can be safely shared between threads.
+pub struct AtomicGc<T: Send> {
+    ptr: AtomicPtr<T>,
+    _phantom: PhantomData<Gc<T>>,
+}
+
+fn into_ptr<T: Send>(gc: Option<Gc<T>>) -> *mut T {
+    gc.map_or(ptr::null_mut(), |gc| Gc::into_raw(gc) as *mut T)
+}
+
+fn from_ptr<T: Send>(ptr: *mut T) -> Option<Gc<T>> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(Gc::from_raw(ptr))
+    }
+}
+
+impl<T: Send> AtomicGc<T> {
+    pub fn new(gc: Option<Gc<T>>) -> Self {
+        AtomicGc {
+            ptr: AtomicPtr::new(into_ptr(gc)),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// An empty slot. This is a `const fn`, so can be used for statics.
+    pub const fn empty() -> Self {
+        AtomicGc {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> Option<Gc<T>> {
+        from_ptr(self.ptr.load(order))
+    }
+
+    pub fn store(&self, gc: Option<Gc<T>>, order: Ordering) {
+        self.ptr.store(into_ptr(gc), order)
+    }
+
+    /// Store `gc`, returning the previous value.
+    pub fn swap(&self, gc: Option<Gc<T>>, order: Ordering) -> Option<Gc<T>> {
+        from_ptr(self.ptr.swap(into_ptr(gc), order))
+    }
+
+    /// Store `new` if the slot holds `current` (compared by identity, as
+    /// with `Gc::ptr_eq`). Returns the previous value, as `Ok` if it was
+    /// `current`, and as `Err` otherwise.
+    pub fn compare_exchange(
+        &self,
+        current: Option<Gc<T>>,
+        new: Option<Gc<T>>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Option<Gc<T>>, Option<Gc<T>>> {
+        self.ptr
+            .compare_exchange(into_ptr(current), into_ptr(new), success, failure)
+            .map(from_ptr)
+            .map_err(from_ptr)
+    }
+
+    /// Returns the value, which can't be shared as the slot is owned.
+    pub fn into_inner(self) -> Option<Gc<T>> {
+        from_ptr(self.ptr.into_inner())
+    }
+}
+
+impl<T: Send> Default for AtomicGc<T> {
+    fn default() -> Self {
+        AtomicGc::empty()
+    }
+}
+
+impl<T: Send> From<Gc<T>> for AtomicGc<T> {
+    fn from(gc: Gc<T>) -> Self {
+        AtomicGc::new(Some(gc))
+    }
+}
+
+impl<T: Send> fmt::Debug for AtomicGc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.ptr, f)
+    }
+}
@@ -0,0 +1,188 @@
+//! Cheaply sliceable byte buffers on the GC heap.
+//!
+//! Protocol parsers typically split one buffer into many fields. With
+//! `GcBytes`, every field is a view into the same block, rather than a copy:
+//! the block stays alive for as long as any view of it is reachable. This is
+//! the GC-managed equivalent of the `bytes` crate's `Bytes`.
+use std::{
+    borrow::Borrow,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::{Bound, Deref, RangeBounds},
+};
+
+use crate::Gc;
+
+/// A view of a range of bytes in a `Gc<[u8]>`. Slicing and copying a
+/// `GcBytes` never copies the bytes themselves.
+#[derive(Copy, Clone)]
+pub struct GcBytes {
+    buf: Gc<[u8]>,
+    start: usize,
+    len: usize,
+}
+
+impl GcBytes {
+    /// An empty buffer.
+    pub fn new() -> Self {
+        GcBytes::from(Gc::<[u8]>::from(&[][..]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// A view of `range` of these bytes.
+    ///
+    /// # Panics
+    ///
+    /// If `range` is out of bounds.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Self {
+        let start = match range.start_bound() {
+            Bound::Included(&s) => s,
+            Bound::Excluded(&s) => s + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&e) => e + 1,
+            Bound::Excluded(&e) => e,
+            Bound::Unbounded => self.len,
+        };
+        assert!(
+            start <= end && end <= self.len,
+            "range {}..{} out of bounds for GcBytes of length {}",
+            start,
+            end,
+            self.len
+        );
+        GcBytes {
+            buf: self.buf,
+            start: self.start + start,
+            len: end - start,
+        }
+    }
+
+    /// A view of `subset`, which must be a slice of these bytes (e.g. one
+    /// returned by a parser given `&self[..]`).
+    ///
+    /// # Panics
+    ///
+    /// If `subset` isn't within these bytes.
+    pub fn slice_ref(&self, subset: &[u8]) -> Self {
+        let base = self.as_ptr() as usize;
+        let sub = subset.as_ptr() as usize;
+        assert!(
+            sub >= base && sub + subset.len() <= base + self.len,
+            "slice isn't part of this GcBytes"
+        );
+        self.slice(sub - base..sub - base + subset.len())
+    }
+
+    /// Split off and return the bytes before `at`, leaving `self` with the
+    /// rest.
+    ///
+    /// # Panics
+    ///
+    /// If `at` is out of bounds.
+    pub fn split_to(&mut self, at: usize) -> Self {
+        let head = self.slice(..at);
+        *self = self.slice(at..);
+        head
+    }
+
+    /// Split off and return the bytes from `at` onwards, leaving `self` with
+    /// the bytes before it.
+    ///
+    /// # Panics
+    ///
+    /// If `at` is out of bounds.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        let tail = self.slice(at..);
+        *self = self.slice(..at);
+        tail
+    }
+
+    /// The whole block this is a view of.
+    pub fn parent(&self) -> Gc<[u8]> {
+        self.buf
+    }
+}
+
+impl Default for GcBytes {
+    fn default() -> Self {
+        GcBytes::new()
+    }
+}
+
+impl From<Gc<[u8]>> for GcBytes {
+    fn from(buf: Gc<[u8]>) -> Self {
+        GcBytes {
+            buf,
+            start: 0,
+            len: buf.len(),
+        }
+    }
+}
+
+/// The bytes are copied into a block which isn't scanned.
+impl From<&[u8]> for GcBytes {
+    fn from(bytes: &[u8]) -> Self {
+        GcBytes::from(Gc::<[u8]>::from(bytes))
+    }
+}
+
+impl From<&str> for GcBytes {
+    fn from(s: &str) -> Self {
+        GcBytes::from(s.as_bytes())
+    }
+}
+
+impl Deref for GcBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.start..self.start + self.len]
+    }
+}
+
+impl AsRef<[u8]> for GcBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Borrow<[u8]> for GcBytes {
+    fn borrow(&self) -> &[u8] {
+        self
+    }
+}
+
+impl PartialEq for GcBytes {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for GcBytes {}
+
+impl PartialEq<[u8]> for GcBytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
+
+impl Hash for GcBytes {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl fmt::Debug for GcBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b\"{}\"", self.escape_ascii())
+    }
+}
@@ -0,0 +1,148 @@
+//! Caches which give memory back to the collector.
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+use crate::{pressure, weak::GcWeak, Gc, GcAllocator};
+
+/// A least-recently-used cache of GC objects.
+///
+/// The `capacity` most recently used values are held strongly. Values which
+/// fall out of that set are still held weakly, so `get` can return them until
+/// the collector reclaims them. Entries whose values have been reclaimed are
+/// purged lazily, after the next collection.
+///
+/// Whenever the collector has had to grow the heap, the cache halves its
+/// capacity, so that more of its values can be reclaimed. After collections
+/// which didn't need to grow the heap, it doubles its capacity again, up to
+/// the one it was created with.
+pub struct GcLruCache<K: Hash + Eq + Clone, V: Send> {
+    entries: HashMap<K, Entry<V>>,
+    /// The keys of strongly held entries, by when they were last used.
+    recent: BTreeMap<u64, K>,
+    capacity: usize,
+    configured_capacity: usize,
+    clock: u64,
+    seen_collections: usize,
+    seen_resizes: usize,
+}
+
+struct Entry<V: Send> {
+    weak: GcWeak<V>,
+    /// When the entry was last used and its value, if held strongly.
+    strong: Option<(u64, Gc<V>)>,
+}
+
+impl<K: Hash + Eq + Clone, V: Send> GcLruCache<K, V> {
+    /// Create a cache which holds up to `capacity` values strongly.
+    pub fn new(capacity: usize) -> Self {
+        GcLruCache {
+            entries: HashMap::new(),
+            recent: BTreeMap::new(),
+            capacity,
+            configured_capacity: capacity,
+            clock: 0,
+            seen_collections: GcAllocator::num_collections(),
+            seen_resizes: pressure::heap_resizes(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of entries, including any whose values have been collected
+    /// but which haven't yet been purged.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn insert(&mut self, key: K, value: Gc<V>) {
+        self.maintain();
+        let entry = Entry {
+            weak: Gc::downgrade(&value),
+            strong: None,
+        };
+        if let Some((used, _)) = self
+            .entries
+            .insert(key.clone(), entry)
+            .and_then(|e| e.strong)
+        {
+            self.recent.remove(&used);
+        }
+        self.promote(&key, value);
+    }
+
+    /// Returns the value for `key`, if it hasn't been collected, and marks it
+    /// as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<Gc<V>> {
+        self.maintain();
+        match self.entries.get(key)?.weak.upgrade() {
+            Some(value) => {
+                self.promote(key, value);
+                Some(value)
+            }
+            None => {
+                self.entries.remove(key);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<Gc<V>> {
+        let entry = self.entries.remove(key)?;
+        if let Some((used, _)) = entry.strong {
+            self.recent.remove(&used);
+        }
+        entry.weak.upgrade()
+    }
+
+    /// Shrink the cache if the heap has grown, or grow it back if there has
+    /// only been a collection, and purge dead entries if there has been a
+    /// collection, since this was last called.
+    fn maintain(&mut self) {
+        let resizes = pressure::heap_resizes();
+        let collections = GcAllocator::num_collections();
+        if resizes != self.seen_resizes {
+            self.seen_resizes = resizes;
+            self.capacity = (self.capacity / 2).max(1);
+            self.evict();
+        } else if collections != self.seen_collections {
+            self.capacity = cmp::min(self.capacity.saturating_mul(2), self.configured_capacity);
+        }
+        if collections != self.seen_collections {
+            self.seen_collections = collections;
+            self.entries
+                .retain(|_, e| e.strong.is_some() || !e.weak.is_dead());
+        }
+    }
+
+    /// Hold `key`'s value strongly, as the most recently used.
+    fn promote(&mut self, key: &K, value: Gc<V>) {
+        let now = self.clock;
+        self.clock += 1;
+        let entry = self.entries.get_mut(key).unwrap();
+        if let Some((used, _)) = entry.strong.replace((now, value)) {
+            self.recent.remove(&used);
+        }
+        self.recent.insert(now, key.clone());
+        self.evict();
+    }
+
+    /// Demote the least recently used values to weak references until no
+    /// more than `capacity` are held strongly.
+    fn evict(&mut self) {
+        while self.recent.len() > self.capacity {
+            let (_, key) = self.recent.pop_first().unwrap();
+            if let Some(entry) = self.entries.get_mut(&key) {
+                entry.strong = None;
+            }
+        }
+    }
+}
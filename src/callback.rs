@@ -0,0 +1,99 @@
+//! Passing GC objects through C callback APIs.
+//!
+//! Libraries such as libuv and glib take a `void *` of user data when a
+//! callback is registered, and hand it back to the callback later. The
+//! collector can't see pointers held by C, so an object passed this way must
+//! be pinned until the library is done with it.
+use std::{any::type_name, collections::BTreeMap, ffi::c_void, sync::Mutex};
+
+use crate::{pin, Gc};
+
+/// Outstanding pins, keyed by the (negated) object address, as in `pin`.
+static CALLBACK_PINS: Mutex<BTreeMap<usize, Pinned>> = Mutex::new(BTreeMap::new());
+
+struct Pinned {
+    type_name: &'static str,
+    count: usize,
+    /// True if the object was already pinned (e.g. by `Gc::new_uncollectable`)
+    /// before the first `CallbackPin`, in which case it stays pinned after
+    /// the last.
+    was_pinned: bool,
+}
+
+/// Pins `Gc`s for use as C callback user data.
+///
+/// ```ignore
+/// let data = CallbackPin::pin(Gc::new(state));
+/// unsafe { g_timeout_add_full(0, 100, Some(on_timeout), data, Some(CallbackPin::unpin)) };
+/// ```
+pub struct CallbackPin;
+
+impl CallbackPin {
+    /// Keep `gc` alive until a matching call to `unpin`, and return a pointer
+    /// to pass to C as user data. An object may be pinned more than once, in
+    /// which case each pin needs unpinning.
+    pub fn pin<T: Send>(gc: Gc<T>) -> *mut c_void {
+        let obj = Gc::into_raw(gc) as *mut c_void;
+        let mut pins = CALLBACK_PINS.lock().unwrap();
+        let pinned = pins.entry(!(obj as usize)).or_insert_with(|| Pinned {
+            type_name: type_name::<T>(),
+            count: 0,
+            was_pinned: pin::is_pinned(obj as *const u8),
+        });
+        if pinned.count == 0 {
+            pin::pin(obj as *const u8);
+        }
+        pinned.count += 1;
+        obj
+    }
+
+    /// Recover the `Gc` from user data returned by `pin`.
+    ///
+    /// # Safety
+    ///
+    /// `user_data` must have been returned by `CallbackPin::pin::<T>` and not
+    /// yet unpinned.
+    pub unsafe fn get<T: Send>(user_data: *mut c_void) -> Gc<T> {
+        Gc::from_raw(user_data as *const T)
+    }
+
+    /// Undo one call to `pin`. This has the signature of a C destroy
+    /// notification, so it can be passed straight to APIs such as glib's.
+    ///
+    /// # Safety
+    ///
+    /// `user_data` must have been returned by `CallbackPin::pin`. Once it has
+    /// been unpinned as many times as it was pinned, the object may be
+    /// collected, so C must not use `user_data` again.
+    ///
+    /// # Panics
+    ///
+    /// If `user_data` isn't pinned. A panic can't unwind into C, so this
+    /// aborts the process when called from C.
+    pub unsafe extern "C" fn unpin(user_data: *mut c_void) {
+        let mut pins = CALLBACK_PINS.lock().unwrap();
+        let key = !(user_data as usize);
+        let pinned = match pins.get_mut(&key) {
+            Some(p) => p,
+            None => panic!("{:p} was not pinned by CallbackPin", user_data),
+        };
+        pinned.count -= 1;
+        if pinned.count == 0 {
+            if !pinned.was_pinned {
+                pin::unpin(user_data as *const u8);
+            }
+            pins.remove(&key);
+        }
+    }
+
+    /// The type names of the objects currently pinned, and how many times
+    /// each is pinned. Anything still here when a library has been shut
+    /// down was leaked.
+    pub fn outstanding() -> Vec<(&'static str, usize)> {
+        let mut by_type = BTreeMap::new();
+        for pinned in CALLBACK_PINS.lock().unwrap().values() {
+            *by_type.entry(pinned.type_name).or_insert(0) += pinned.count;
+        }
+        by_type.into_iter().collect()
+    }
+}
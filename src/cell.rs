@@ -0,0 +1,151 @@
+//! Interior mutability for values on the GC heap.
+//!
+//! A `RefCell` inside a `Gc` works, but the collector never learns that the
+//! object was written to. That is harmless while every collection scans the
+//! whole heap, but an incremental or generational collector must re-scan
+//! objects which were mutated since they were last marked. `GcCell` tells
+//! the collector about every mutable borrow, so code using it is ready for
+//! such collectors.
+//!
+//! In debug builds, dropping a `GcCell` which is still borrowed panics. Safe
+//! code can't do that, so if a finalizer does, the collector (or unsafe code)
+//! has found a reachable object unreachable: the panic reports it, rather than
+//! it showing up much later as memory corruption.
+use std::{
+    cell::{BorrowError, BorrowMutError, Ref, RefCell, RefMut},
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+#[cfg(feature = "standalone")]
+use crate::GcAllocator;
+
+/// A `RefCell` which acts as a write barrier.
+pub struct GcCell<T> {
+    inner: RefCell<T>,
+}
+
+impl<T> GcCell<T> {
+    pub fn new(value: T) -> Self {
+        GcCell {
+            inner: RefCell::new(value),
+        }
+    }
+
+    /// Immutably borrow the value, as with `RefCell::borrow`.
+    ///
+    /// # Panics
+    ///
+    /// If the value is mutably borrowed.
+    #[track_caller]
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.inner.borrow()
+    }
+
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        self.inner.try_borrow()
+    }
+
+    /// Mutably borrow the value, as with `RefCell::borrow_mut`. The
+    /// collector is told that the object has changed once the borrow ends.
+    ///
+    /// # Panics
+    ///
+    /// If the value is borrowed.
+    #[track_caller]
+    pub fn borrow_mut(&self) -> GcRefMut<'_, T> {
+        GcRefMut::new(self, self.inner.borrow_mut())
+    }
+
+    pub fn try_borrow_mut(&self) -> Result<GcRefMut<'_, T>, BorrowMutError> {
+        Ok(GcRefMut::new(self, self.inner.try_borrow_mut()?))
+    }
+
+    /// Replace the value, returning the old one.
+    #[track_caller]
+    pub fn replace(&self, value: T) -> T {
+        std::mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    #[track_caller]
+    pub fn set(&self, value: T) {
+        *self.borrow_mut() = value;
+    }
+
+    pub fn into_inner(self) -> T {
+        // `GcCell` may implement `Drop`, so the value can't be moved out.
+        let this = ManuallyDrop::new(self);
+        unsafe { ptr::read(&this.inner) }.into_inner()
+    }
+
+    /// Mutably borrow the value without a barrier, which isn't needed as
+    /// `self` is borrowed mutably, and so not shared through a `Gc`.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+/// The cell's `RefCell` counts its borrows, so this needs no bookkeeping of
+/// its own.
+#[cfg(debug_assertions)]
+impl<T> Drop for GcCell<T> {
+    fn drop(&mut self) {
+        if self.inner.try_borrow_mut().is_err() {
+            panic!("a GcCell was dropped while borrowed");
+        }
+    }
+}
+
+impl<T: Default> Default for GcCell<T> {
+    fn default() -> Self {
+        GcCell::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for GcCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GcCell")
+            .field("value", &self.inner)
+            .finish()
+    }
+}
+
+/// A mutable borrow of a `GcCell`'s value, returned by `GcCell::borrow_mut`.
+pub struct GcRefMut<'a, T> {
+    value: RefMut<'a, T>,
+    cell: *const u8,
+}
+
+impl<'a, T> GcRefMut<'a, T> {
+    fn new(cell: &'a GcCell<T>, value: RefMut<'a, T>) -> Self {
+        let cell = cell as *const GcCell<T> as *const u8;
+        #[cfg(feature = "standalone")]
+        GcAllocator::change_stubborn(cell);
+        GcRefMut { value, cell }
+    }
+}
+
+impl<T> Deref for GcRefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for GcRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> Drop for GcRefMut<'_, T> {
+    fn drop(&mut self) {
+        // rustgc's collector is never incremental, so has no dirty bits to
+        // maintain.
+        #[cfg(feature = "standalone")]
+        GcAllocator::end_stubborn_change(self.cell);
+    }
+}
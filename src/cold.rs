@@ -0,0 +1,83 @@
+//! Keeping rarely accessed objects apart from the rest of the heap.
+//!
+//! Objects such as debug info or source maps are allocated once and seldom
+//! touched again. Allocating them with `Gc::new_cold` puts them in heap
+//! blocks of their own, so that frequently used objects are packed densely
+//! rather than interleaved with them.
+use std::{
+    alloc::{AllocError, Layout},
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    alloc::{Finalizer, GcAlloc},
+    config, ALLOCATOR,
+};
+
+/// The number of bytes allocated in the cold region so far.
+static COLD_BYTES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "stats_counters")]
+pub(crate) fn bytes_allocated() -> usize {
+    COLD_BYTES_ALLOCATED.load(Ordering::Relaxed)
+}
+
+/// An allocator which places objects in the cold region, until
+/// `GcConfig::cold_region` bytes have been allocated there. After that,
+/// objects are allocated as normal.
+pub(crate) struct ColdAlloc;
+
+impl GcAlloc for ColdAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let size = layout.size();
+        let prev = COLD_BYTES_ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        if prev.saturating_add(size) > config::cold_region() {
+            COLD_BYTES_ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+            return GcAlloc::allocate(&ALLOCATOR, layout);
+        }
+        #[cfg(feature = "standalone")]
+        return ALLOCATOR.allocate_cold(layout);
+        // rustgc's allocator has no separate kind for cold objects, so the
+        // region only limits how much is accounted as cold.
+        #[cfg(not(feature = "standalone"))]
+        GcAlloc::allocate(&ALLOCATOR, layout)
+    }
+
+    /// Blocks which aren't scanned are never visited when marking, so there
+    /// is nothing to gain from segregating them.
+    fn alloc_atomic(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        GcAlloc::alloc_atomic(&ALLOCATOR, layout)
+    }
+
+    fn alloc_precise(
+        &self,
+        layout: Layout,
+        bitmap: usize,
+        bitmap_size: usize,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        GcAlloc::alloc_precise(&ALLOCATOR, layout, bitmap, bitmap_size)
+    }
+
+    unsafe fn register_finalizer(
+        &self,
+        obj: *mut u8,
+        finalizer: Option<Finalizer>,
+        client_data: *mut u8,
+    ) {
+        GcAlloc::register_finalizer(&ALLOCATOR, obj, finalizer, client_data)
+    }
+
+    unsafe fn register_ordered_finalizer(
+        &self,
+        obj: *mut u8,
+        finalizer: Option<Finalizer>,
+        client_data: *mut u8,
+    ) {
+        GcAlloc::register_ordered_finalizer(&ALLOCATOR, obj, finalizer, client_data)
+    }
+
+    fn unregister_finalizer(&self, obj: *mut u8) {
+        GcAlloc::unregister_finalizer(&ALLOCATOR, obj)
+    }
+}
@@ -0,0 +1,336 @@
+//! Run-time configuration of the collector and of `Gc` allocation.
+//!
+//! Settings are process-wide. Most take effect immediately, but are best
+//! applied once, before the first `Gc` is allocated.
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    Arc,
+};
+#[cfg(feature = "standalone")]
+use std::{fmt, sync::Once, time::Duration};
+
+#[cfg(feature = "standalone")]
+use crate::{CollectorConfig, GcAllocator};
+
+static FINALIZER_BATCHING: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "standalone")]
+static DETERMINISTIC: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "standalone")]
+static FORK_FRIENDLY: AtomicBool = AtomicBool::new(false);
+static FINALIZERS_DISABLED: AtomicBool = AtomicBool::new(false);
+static COLD_REGION: AtomicUsize = AtomicUsize::new(usize::MAX);
+static FINALIZER_PANIC: AtomicU8 = AtomicU8::new(FinalizerPanic::Abort as u8);
+#[cfg(feature = "standalone")]
+static LOG_EFFECTIVE_CONFIG: Once = Once::new();
+
+pub struct GcConfig;
+
+impl GcConfig {
+    /// When enabled, finalizer registrations are buffered per thread and
+    /// handed to the collector in batches, rather than making an FFI call for
+    /// every allocation of a type which needs dropping.
+    ///
+    /// A buffered object is kept alive by the buffer until it is flushed,
+    /// which happens every few allocations, when the thread exits, and, for
+    /// the calling thread, by `gc::collect` and when batching is disabled.
+    /// Other threads flush at their next allocation after those. This requires
+    /// `GcAllocator` to be the global allocator, so that the buffer itself is
+    /// scanned.
+    ///
+    /// `GcAllocator` only moves registrations it has been given when it
+    /// resizes a block (e.g. with `Allocator::grow`), so code which resizes
+    /// blocks it allocated with `Gc` must call `alloc::flush_finalizers`
+    /// first.
+    pub fn finalizer_batching(enable: bool) {
+        FINALIZER_BATCHING.store(enable, Ordering::Relaxed);
+        if !enable {
+            crate::alloc::flush_all_finalizers();
+        }
+    }
+
+    /// When enabled, collection behaves reproducibly, so that tests of
+    /// runtime semantics don't depend on the collector's scheduling:
+    ///
+    /// * marking is done by a single thread. This only takes effect if
+    ///   enabled before the collector is initialized.
+    /// * finalizers only run during `gc::collect`, on the calling thread,
+    ///   rather than whenever the collector decides to run them.
+    ///
+    /// libgc itself has no randomized behaviour to seed.
+    #[cfg(feature = "standalone")]
+    pub fn deterministic_test_mode(enable: bool) {
+        DETERMINISTIC.store(enable, Ordering::Relaxed);
+        GcAllocator::set_markers_count(if enable { 1 } else { 0 });
+        GcAllocator::set_finalize_on_demand(enable || crate::finalize::thread_running());
+        if !enable {
+            GcAllocator::invoke_finalizers();
+        }
+    }
+
+    /// When enabled, the collector makes sure its heap is consistent across
+    /// `fork`: it takes its locks and waits for any collection to finish
+    /// before forking, and resets its thread state in the child. This lets
+    /// pre-forking servers and `multiprocessing`-style runtimes keep using
+    /// `Gc` in their children. It only takes effect if enabled before the
+    /// collector is initialized, and only `fork` is handled (e.g. not
+    /// `vfork`, or `clone` without `SIGCHLD`).
+    #[cfg(feature = "standalone")]
+    pub fn fork_friendly(enable: bool) {
+        FORK_FRIENDLY.store(enable, Ordering::Relaxed);
+        GcAllocator::set_handle_fork(enable);
+    }
+
+    /// Choose how the collector collects. See `CollectionMode`.
+    ///
+    /// # Panics
+    ///
+    /// If `mode` is `StopTheWorld` once another mode has been chosen: the
+    /// collector can't leave incremental mode.
+    #[cfg(feature = "standalone")]
+    pub fn collection_mode(mode: CollectionMode) {
+        match mode {
+            CollectionMode::StopTheWorld => assert!(
+                !GcAllocator::collector_config().incremental,
+                "the collector can't leave incremental mode"
+            ),
+            CollectionMode::Generational { full_freq } => {
+                GcAllocator::set_full_freq(full_freq);
+                GcAllocator::set_time_limit(None);
+                GcAllocator::enable_incremental();
+            }
+            CollectionMode::Incremental {
+                time_limit,
+                full_freq,
+            } => {
+                GcAllocator::set_full_freq(full_freq);
+                let ms = time_limit.as_millis().max(1);
+                GcAllocator::set_time_limit(Some(ms.min(usize::MAX as u128) as usize));
+                GcAllocator::enable_incremental();
+            }
+        }
+    }
+
+    /// Allocate at most `bytes` in the region used by `Gc::new_cold`, after
+    /// which cold objects are allocated alongside everything else. The limit
+    /// counts every cold allocation, including those since collected. By
+    /// default, there is no limit.
+    pub fn cold_region(bytes: usize) {
+        COLD_REGION.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Call `observer` with the number of bytes allocated by `Gc::new` and
+    /// friends, e.g. so that an embedder can count the GC heap against a
+    /// container's memory budget and apply backpressure before the process
+    /// is killed. To keep allocation cheap, each thread reports in batches
+    /// of at least 64KiB, on the thread which allocates; up to that much per
+    /// thread may be reported late, or not at all if the thread exits.
+    /// This replaces any previous observer.
+    pub fn alloc_observer(observer: impl Fn(usize) + Send + Sync + 'static) {
+        crate::observer::set(Some(Arc::new(observer)));
+    }
+
+    /// Stop reporting allocations to the observer set by `alloc_observer`.
+    pub fn clear_alloc_observer() {
+        crate::observer::set(None);
+    }
+
+    /// Stop registering finalizers for new `Gc`s, so that their values are
+    /// never dropped. Finalizers which are already registered still run.
+    /// This is intended for measuring how much finalization costs a
+    /// workload (see `benches/alloc.rs`), and for heaps whose values need
+    /// no dropping even though their types say otherwise.
+    ///
+    /// # Safety
+    ///
+    /// Skipping a value's `Drop` impl leaks anything it owns outside the GC
+    /// heap, and breaks any code which relies on the value being dropped.
+    pub unsafe fn disable_all_finalizers() {
+        FINALIZERS_DISABLED.store(true, Ordering::Relaxed);
+    }
+
+    /// Undo `disable_all_finalizers`. `Gc`s created while finalizers were
+    /// disabled still won't be finalized.
+    pub fn enable_all_finalizers() {
+        FINALIZERS_DISABLED.store(false, Ordering::Relaxed);
+    }
+
+    /// What to do when a finalizer (i.e. a `Drop` impl run by the collector)
+    /// panics. The panic is never allowed to unwind into the collector. The
+    /// default is `FinalizerPanic::Abort`.
+    pub fn on_finalizer_panic(action: FinalizerPanic) {
+        FINALIZER_PANIC.store(action as u8, Ordering::Relaxed);
+    }
+
+    /// The configuration actually in effect, gathered from the collector and
+    /// from libgc's own settings and features. This is useful in bug
+    /// reports, as settings may come from environment variables (e.g.
+    /// `GC_MARKERS`) as well as from calls to `GcConfig`.
+    #[cfg(feature = "standalone")]
+    pub fn effective_config() -> EffectiveConfig {
+        EffectiveConfig {
+            collector: GcAllocator::collector_config(),
+            finalizer_batching: finalizer_batching(),
+            deterministic_test_mode: deterministic(),
+            fork_friendly: FORK_FRIENDLY.load(Ordering::Relaxed),
+            finalizers_disabled: finalizers_disabled(),
+            finalizer_panic: finalizer_panic(),
+            features: FEATURES,
+        }
+    }
+
+    /// Write the effective configuration to stderr, the first time this is
+    /// called.
+    #[cfg(feature = "standalone")]
+    pub fn log_effective_config() {
+        LOG_EFFECTIVE_CONFIG.call_once(|| eprintln!("libgc: {}", GcConfig::effective_config()));
+    }
+}
+
+/// See `GcConfig::on_finalizer_panic`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FinalizerPanic {
+    /// Abort the process.
+    Abort,
+    /// Write a message to stderr and carry on. The object's memory is still
+    /// reclaimed, so anything its `Drop` impl didn't get to is leaked.
+    Log,
+    /// As `Log`, but record the panic rather than writing a message. The
+    /// panics can later be retrieved with `alloc::take_finalizer_panics`.
+    Collect,
+}
+
+/// See `GcConfig::collection_mode`.
+///
+/// In the incremental modes, the collector tracks which pages have been
+/// written to since they were last marked, and only re-scans those, along
+/// with recently allocated objects. It finds the written pages itself, by
+/// write-protecting the heap or from the kernel's soft-dirty bits, so writes
+/// of any kind are seen, not just those made through `GcCell`.
+#[cfg(feature = "standalone")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CollectionMode {
+    /// Every collection marks the whole heap, with the world stopped until
+    /// it has finished. This is the default.
+    StopTheWorld,
+    /// Collections are partial, with a full one every `full_freq`
+    /// collections. Each still runs to completion once started.
+    Generational { full_freq: usize },
+    /// As `Generational`, but marking is also split into slices of about
+    /// `time_limit` each, which are interleaved with allocation. An event
+    /// loop can also do slices while it is idle, with `gc::collect_slice`.
+    Incremental {
+        time_limit: Duration,
+        full_freq: usize,
+    },
+}
+
+#[cfg(feature = "standalone")]
+impl CollectionMode {
+    /// The mode the collector is in.
+    pub fn current() -> CollectionMode {
+        let c = GcAllocator::collector_config();
+        match (c.incremental, c.time_limit_ms) {
+            (false, _) => CollectionMode::StopTheWorld,
+            (true, None) => CollectionMode::Generational {
+                full_freq: c.full_freq,
+            },
+            (true, Some(ms)) => CollectionMode::Incremental {
+                time_limit: Duration::from_millis(ms as u64),
+                full_freq: c.full_freq,
+            },
+        }
+    }
+}
+
+/// The cargo features libgc was built with.
+#[cfg(feature = "standalone")]
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "standalone")]
+    "standalone",
+    #[cfg(feature = "stats_counters")]
+    "stats_counters",
+    #[cfg(feature = "stats_profiling")]
+    "stats_profiling",
+    #[cfg(feature = "gc_flamegraph")]
+    "gc_flamegraph",
+    #[cfg(feature = "debug_checks")]
+    "debug_checks",
+    #[cfg(feature = "teardown_checks")]
+    "teardown_checks",
+    #[cfg(feature = "crash_handler")]
+    "crash_handler",
+];
+
+/// See `GcConfig::effective_config`.
+#[cfg(feature = "standalone")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EffectiveConfig {
+    pub collector: CollectorConfig,
+    pub finalizer_batching: bool,
+    pub deterministic_test_mode: bool,
+    pub fork_friendly: bool,
+    pub finalizers_disabled: bool,
+    pub finalizer_panic: FinalizerPanic,
+    pub features: &'static [&'static str],
+}
+
+#[cfg(feature = "standalone")]
+impl fmt::Display for EffectiveConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = &self.collector;
+        write!(
+            f,
+            "collector {}.{}.{}, heap {} bytes, free space divisor {}, \
+             {}incremental (full every {}, time limit {}), {} marker(s), \
+             all interior pointers {}, finalize on demand {}, \
+             finalizer batching {}, deterministic {}, fork friendly {}, \
+             finalizers disabled {}, finalizer panic {:?}, features [{}]",
+            c.version.0,
+            c.version.1,
+            c.version.2,
+            c.heap_size,
+            c.free_space_divisor,
+            if c.incremental { "" } else { "not " },
+            c.full_freq,
+            match c.time_limit_ms {
+                Some(ms) => format!("{} ms", ms),
+                None => "none".to_string(),
+            },
+            c.markers,
+            c.all_interior_pointers,
+            c.finalize_on_demand,
+            self.finalizer_batching,
+            self.deterministic_test_mode,
+            self.fork_friendly,
+            self.finalizers_disabled,
+            self.finalizer_panic,
+            self.features.join(", ")
+        )
+    }
+}
+
+pub(crate) fn finalizer_batching() -> bool {
+    FINALIZER_BATCHING.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "standalone")]
+pub(crate) fn deterministic() -> bool {
+    DETERMINISTIC.load(Ordering::Relaxed)
+}
+
+pub(crate) fn finalizers_disabled() -> bool {
+    FINALIZERS_DISABLED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn cold_region() -> usize {
+    COLD_REGION.load(Ordering::Relaxed)
+}
+
+pub(crate) fn finalizer_panic() -> FinalizerPanic {
+    match FINALIZER_PANIC.load(Ordering::Relaxed) {
+        x if x == FinalizerPanic::Log as u8 => FinalizerPanic::Log,
+        x if x == FinalizerPanic::Collect as u8 => FinalizerPanic::Collect,
+        _ => FinalizerPanic::Abort,
+    }
+}
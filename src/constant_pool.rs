@@ -0,0 +1,171 @@
+//! Constant pools for bytecode compilers.
+//!
+//! Compiled code refers to its constants (string literals, numbers, and so
+//! on) by index. The pool keeps every constant alive for as long as it
+//! exists, and hands out the same index for equal strings and numbers, so
+//! that e.g. a literal used in many functions is only stored once. Like an
+//! `Isolate`'s roots, the table lives in memory allocated by `GcAllocator`,
+//! which the collector scans but never frees, so the constants need no
+//! further registration. A runtime with several isolates typically gives
+//! each its own pool, and tears both down together.
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    convert::TryFrom,
+    sync::Mutex,
+};
+
+use crate::{Gc, GcAllocator};
+
+/// The index of a constant in a `ConstantPool`. Indices are allocated
+/// consecutively from 0, and never change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConstId(u32);
+
+impl ConstId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// What constants are deduplicated by. Values of different types are never
+/// equal, so that a constant always downcasts to the type it was added as.
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Str(TypeId, String),
+    /// Numbers, booleans, and characters, by their bits. Floats are compared
+    /// bitwise, so `0.0` and `-0.0` are different constants, and a NaN is
+    /// the same constant as itself.
+    Bits(TypeId, u64),
+}
+
+macro_rules! bits_key {
+    ($value:expr, $($ty:ty => $bits:expr),* $(,)?) => {
+        $(if let Some(&v) = $value.downcast_ref::<$ty>() {
+            let to_bits: fn($ty) -> u64 = $bits;
+            return Some(Key::Bits(TypeId::of::<$ty>(), to_bits(v)));
+        })*
+    };
+}
+
+impl Key {
+    fn of(value: &dyn Any) -> Option<Key> {
+        if let Some(s) = value.downcast_ref::<String>() {
+            return Some(Key::Str(TypeId::of::<String>(), s.clone()));
+        }
+        if let Some(s) = value.downcast_ref::<&'static str>() {
+            return Some(Key::Str(TypeId::of::<&'static str>(), s.to_string()));
+        }
+        bits_key!(value,
+            i8 => |v| v as u64, i16 => |v| v as u64, i32 => |v| v as u64, i64 => |v| v as u64,
+            isize => |v| v as u64, u8 => |v| v as u64, u16 => |v| v as u64,
+            u32 => |v| v as u64, u64 => |v| v, usize => |v| v as u64,
+            f32 => |v| v.to_bits() as u64, f64 => |v| v.to_bits(),
+            bool => |v| v as u64, char => |v| v as u64,
+        );
+        None
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    consts: Vec<Gc<dyn Any + Send>>,
+    by_value: HashMap<Key, ConstId>,
+    /// Constants which can't be compared structurally are deduplicated by
+    /// identity instead.
+    by_addr: HashMap<usize, ConstId>,
+}
+
+#[derive(Default)]
+pub struct ConstantPool {
+    table: Mutex<Table>,
+}
+
+impl ConstantPool {
+    pub fn new() -> Self {
+        ConstantPool::default()
+    }
+
+    /// Add `value` to the pool, returning its index. If an equal string or
+    /// number of the same type (or, for other types, the same object) has
+    /// already been added, its index is returned instead, and `value` isn't
+    /// kept alive by the pool.
+    ///
+    /// # Panics
+    ///
+    /// If the pool already has `u32::MAX` constants.
+    pub fn add(&self, value: Gc<dyn Any + Send>) -> ConstId {
+        let key = Key::of(&*value);
+        let addr = Gc::as_ptr(&value) as *const u8 as usize;
+        let mut table = self.table.lock().unwrap();
+        let existing = match &key {
+            Some(key) => table.by_value.get(key),
+            None => table.by_addr.get(&addr),
+        };
+        if let Some(&id) = existing {
+            return id;
+        }
+        let id = ConstId(u32::try_from(table.consts.len()).expect("constant pool is full"));
+        table.consts.push(value);
+        match key {
+            Some(key) => table.by_value.insert(key, id),
+            None => table.by_addr.insert(addr, id),
+        };
+        id
+    }
+
+    /// Returns the constant at `id`, or `None` if `id` is from another pool.
+    pub fn get(&self, id: ConstId) -> Option<Gc<dyn Any + Send>> {
+        self.table.lock().unwrap().consts.get(id.index()).copied()
+    }
+
+    /// Returns the constant at `id` if it is a `T`.
+    pub fn get_as<T: Any + Send>(&self, id: ConstId) -> Option<Gc<T>> {
+        self.get(id)?.downcast().ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.lock().unwrap().consts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every constant and force a collection, so that constants which
+    /// were only reachable from the pool are finalized in bulk.
+    pub fn teardown(self) {
+        drop(self.table.into_inner().unwrap());
+        GcAllocator::force_gc();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deduplicates_constants() {
+        let pool = ConstantPool::new();
+        let a = pool.add(Gc::new(String::from("a")));
+        let one = pool.add(Gc::new(1i64));
+        assert_eq!(pool.add(Gc::new(String::from("a"))), a);
+        assert_eq!(pool.add(Gc::new(1i64)), one);
+        // Equal values of different types are different constants.
+        assert_ne!(pool.add(Gc::new("a")), a);
+        assert_ne!(pool.add(Gc::new(1u64)), one);
+        assert_ne!(pool.add(Gc::new(0.0f64)), pool.add(Gc::new(-0.0f64)));
+
+        let list: Gc<dyn Any + Send> = Gc::new(vec![1, 2]);
+        let id = pool.add(list);
+        assert_eq!(pool.add(list), id);
+        assert_ne!(pool.add(Gc::new(vec![1, 2])), id);
+
+        assert_eq!(pool.len(), 8);
+        assert_eq!((a.index(), one.index()), (0, 1));
+        assert_eq!(*pool.get_as::<String>(a).unwrap(), "a");
+        assert!(pool.get_as::<String>(one).is_none());
+        assert!(pool.get(ConstId(100)).is_none());
+        pool.teardown();
+    }
+}
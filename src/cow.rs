@@ -0,0 +1,106 @@
+use std::{cell::Cell, fmt, ops::Deref};
+
+use crate::Gc;
+
+/// A copy-on-write garbage collected pointer.
+///
+/// `GcCow<T>` derefs to a shared `T` like `Gc<T>`, but also offers
+/// `make_mut`, which hands out a mutable reference after first cloning the
+/// value into a fresh GC block if it might be shared. This allows persistent
+/// data structures to share structure cheaply and only copy on write.
+///
+/// Unlike `Rc::make_mut`, sharing can not be determined by counting
+/// references. Instead, each `GcCow` carries a flag which is set when it is
+/// created and cleared (on both sides) when it is cloned. This is
+/// conservative: once a `GcCow` has been cloned it will copy on its next
+/// write even if the clone has since become unreachable.
+pub struct GcCow<T: Clone + Send> {
+    gc: Gc<T>,
+    unique: Cell<bool>,
+}
+
+impl<T: Clone + Send> GcCow<T> {
+    pub fn new(v: T) -> Self {
+        GcCow {
+            gc: Gc::new(v),
+            unique: Cell::new(true),
+        }
+    }
+
+    /// Returns a mutable reference to the value, first cloning it into a new
+    /// GC block if it may be shared with another `GcCow` or `Gc`.
+    pub fn make_mut(&mut self) -> &mut T {
+        if !self.unique.get() {
+            self.gc = Gc::new((*self.gc).clone());
+            self.unique.set(true);
+        }
+        unsafe { &mut *(Gc::into_raw(self.gc) as *mut T) }
+    }
+
+    /// Returns true if writing through this `GcCow` would require a copy.
+    pub fn is_shared(this: &Self) -> bool {
+        !this.unique.get()
+    }
+
+    /// Convert into a plain `Gc<T>`. The value can no longer be mutated in
+    /// place.
+    pub fn into_gc(this: Self) -> Gc<T> {
+        this.gc
+    }
+}
+
+impl<T: Clone + Send> Clone for GcCow<T> {
+    fn clone(&self) -> Self {
+        self.unique.set(false);
+        GcCow {
+            gc: self.gc,
+            unique: Cell::new(false),
+        }
+    }
+}
+
+/// A `Gc<T>` may have been copied any number of times, so the resulting
+/// `GcCow` is always treated as shared.
+impl<T: Clone + Send> From<Gc<T>> for GcCow<T> {
+    fn from(gc: Gc<T>) -> Self {
+        GcCow {
+            gc,
+            unique: Cell::new(false),
+        }
+    }
+}
+
+impl<T: Clone + Send> Deref for GcCow<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.gc
+    }
+}
+
+impl<T: Clone + fmt::Debug + Send> fmt::Debug for GcCow<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_copy_on_write() {
+        let mut a = GcCow::new(vec![1, 2, 3]);
+        let p = &*a as *const Vec<i32>;
+        a.make_mut().push(4);
+        assert_eq!(&*a as *const _, p);
+
+        let b = a.clone();
+        assert!(GcCow::is_shared(&a) && GcCow::is_shared(&b));
+        a.make_mut().push(5);
+        assert_ne!(&*a as *const _, p);
+        assert_eq!(*a, vec![1, 2, 3, 4, 5]);
+        assert_eq!(*b, vec![1, 2, 3, 4]);
+        assert!(!GcCow::is_shared(&a));
+    }
+}
@@ -0,0 +1,161 @@
+//! Reporting the collector's state when the process crashes.
+//!
+//! `install_handlers` hooks `SIGSEGV`, `SIGBUS` and `SIGABRT`. On a fatal
+//! signal, the handler writes to stderr where the fault was relative to the
+//! GC heap, the collector's statistics (see `debug::emergency_dump`), and
+//! the last few collection events, before handing the signal on to whatever
+//! handler was installed before.
+//!
+//! In the incremental `CollectionMode`s, the collector may write-protect the
+//! heap, and relies on `SIGSEGV` (or `SIGBUS`) to find the pages which are
+//! written to. Faults in the GC heap are therefore passed straight on to the
+//! collector's handler, if there was one, without being reported.
+use std::{
+    fmt::Write,
+    mem, ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Once,
+    },
+};
+
+use crate::{debug::DumpBuf, GcAllocator, GcEvent};
+
+const SIGNALS: [libc::c_int; 3] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGABRT];
+
+/// The number of collection events remembered.
+const NUM_EVENTS: usize = 16;
+
+/// The most recent collection events, as a ring buffer indexed by
+/// `NEXT_EVENT`. Slots which have never been written hold `NO_EVENT`.
+static EVENTS: [AtomicU32; NUM_EVENTS] = [const { AtomicU32::new(NO_EVENT) }; NUM_EVENTS];
+static NEXT_EVENT: AtomicUsize = AtomicUsize::new(0);
+const NO_EVENT: u32 = u32::MAX;
+
+static INSTALL: Once = Once::new();
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// The handlers which were installed before ours, indexed as `SIGNALS`.
+static mut PREVIOUS: [mem::MaybeUninit<libc::sigaction>; 3] = [mem::MaybeUninit::uninit(); 3];
+
+/// Install the crash handlers. This only has an effect the first time it is
+/// called.
+pub fn install_handlers() {
+    INSTALL.call_once(|| unsafe {
+        for (i, &sig) in SIGNALS.iter().enumerate() {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_signal
+                as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+                as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            let previous = ptr::addr_of_mut!(PREVIOUS[i]) as *mut libc::sigaction;
+            libc::sigaction(sig, &action, previous);
+        }
+        INSTALLED.store(true, Ordering::Release);
+        // A timeline being recorded forwards events to us, and restores our
+        // callback when it stops.
+        #[cfg(feature = "stats_profiling")]
+        if crate::stats::recording_timeline() {
+            return;
+        }
+        GcAllocator::set_on_collection_event(Some(on_event));
+    });
+}
+
+/// The collection event callback to install when no other is, if any.
+#[cfg(feature = "stats_profiling")]
+pub(crate) fn event_callback() -> Option<unsafe extern "C" fn(GcEvent)> {
+    match INSTALLED.load(Ordering::Acquire) {
+        true => Some(on_event),
+        false => None,
+    }
+}
+
+/// Remember `kind` as the most recent collection event. This is called with
+/// the allocation lock held, so doesn't allocate.
+pub(crate) fn record_event(kind: GcEvent) {
+    let i = NEXT_EVENT.fetch_add(1, Ordering::Relaxed) % NUM_EVENTS;
+    EVENTS[i].store(kind as u32, Ordering::Relaxed);
+}
+
+unsafe extern "C" fn on_event(kind: GcEvent) {
+    record_event(kind);
+}
+
+extern "C" fn on_signal(sig: libc::c_int, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+    let i = SIGNALS.iter().position(|&s| s == sig).unwrap();
+    let addr = unsafe { (*info).si_addr() } as usize;
+    if sig != libc::SIGABRT
+        && GcAllocator::base(addr as *const u8).is_some()
+        && unsafe { chain(i, sig, info, ctx) }
+    {
+        return;
+    }
+
+    let mut out = DumpBuf::new();
+    let _ = write!(out, "libgc: fatal signal {} at address {:#x}, ", sig, addr);
+    let _ = match GcAllocator::base(addr as *const u8) {
+        Some(base) => writeln!(
+            out,
+            "{} bytes into a {} byte GC block at {:p}",
+            addr - base.as_ptr() as usize,
+            unsafe { GcAllocator::block_size(base.as_ptr()) },
+            base
+        ),
+        None => writeln!(out, "which is not in the GC heap"),
+    };
+    out.flush_to_stderr();
+
+    crate::debug::emergency_dump();
+
+    let _ = write!(out, "libgc: last collection events (oldest first):");
+    let next = NEXT_EVENT.load(Ordering::Relaxed);
+    for i in next..next + NUM_EVENTS {
+        let kind = EVENTS[i % NUM_EVENTS].load(Ordering::Relaxed);
+        if kind != NO_EVENT {
+            let _ = write!(out, " {:?}", unsafe {
+                mem::transmute::<u32, GcEvent>(kind)
+            });
+        }
+    }
+    let _ = writeln!(out);
+    out.flush_to_stderr();
+
+    // Hand the signal on to the previous handler (by default, terminating
+    // the process). For a fault, it is raised again when this returns and
+    // the faulting instruction is retried.
+    unsafe {
+        let previous = ptr::addr_of!(PREVIOUS[i]) as *const libc::sigaction;
+        libc::sigaction(sig, previous, ptr::null_mut());
+        if sig == libc::SIGABRT {
+            libc::raise(sig);
+        }
+    }
+}
+
+/// Call the handler which was installed before ours for `SIGNALS[i]`, e.g.
+/// the collector's write fault handler, leaving ours installed. Returns
+/// false if there was no handler to call.
+unsafe fn chain(
+    i: usize,
+    sig: libc::c_int,
+    info: *mut libc::siginfo_t,
+    ctx: *mut libc::c_void,
+) -> bool {
+    let previous = &*(ptr::addr_of!(PREVIOUS[i]) as *const libc::sigaction);
+    match previous.sa_sigaction {
+        libc::SIG_DFL | libc::SIG_IGN => false,
+        handler if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+            let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                mem::transmute(handler);
+            handler(sig, info, ctx);
+            true
+        }
+        handler => {
+            let handler: extern "C" fn(libc::c_int) = mem::transmute(handler);
+            handler(sig);
+            true
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Tools for finding out why an object is still alive.
+//!
+//! The collector is conservative: any word which looks like a pointer into
+//! the heap keeps its target alive, whether or not it really is a pointer.
+//! When an object is retained unexpectedly, `scan_block` shows which words
+//! of a suspected retainer are responsible.
+//!
+//! `verify_heap` checks the collector's view of the heap for corruption.
+//!
+//! `false_retention_experiment` measures how often conservative scanning
+//! keeps dead objects alive in a given program.
+//!
+//! `root_snapshot` lists the static roots the collector scans, so that two
+//! snapshots can be compared.
+//!
+//! `emergency_dump` writes the collector's state to stderr from contexts
+//! where allocating isn't allowed, such as signal handlers.
+use std::{fmt, mem::size_of};
+
+use crate::{Gc, GcAllocator, GcWeak};
+
+mod roots;
+
+pub use roots::{root_snapshot, RootDiff, RootRange, RootSnapshot};
+
+const WORD: usize = size_of::<usize>();
+
+/// A word in a block which points into the GC heap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PossiblePointer {
+    /// The offset of the word in the scanned block, in bytes.
+    pub offset: usize,
+    /// The word itself.
+    pub value: usize,
+    /// The base of the block which the word points into.
+    pub target: *const u8,
+    /// The size of the target block, in bytes.
+    pub target_size: usize,
+}
+
+impl PossiblePointer {
+    /// How far into the target block the word points, in bytes. A non-zero
+    /// offset only retains the target if the collector recognizes interior
+    /// pointers.
+    pub fn target_offset(&self) -> usize {
+        self.value - self.target as usize
+    }
+}
+
+/// Returns every word in the GC block containing `ptr` which points into the
+/// GC heap, or an empty `Vec` if `ptr` isn't in the GC heap.
+///
+/// Every word of the block is checked, including those the collector never
+/// scans (e.g. in strings, or outside a `DynObject`'s layout), so not every
+/// result necessarily retains its target.
+///
+/// # Safety
+///
+/// The block containing `ptr` must not be freed while it is scanned.
+pub unsafe fn scan_block(ptr: *const u8) -> Vec<PossiblePointer> {
+    let base = match GcAllocator::base(ptr) {
+        Some(base) => base.as_ptr() as *const usize,
+        None => return Vec::new(),
+    };
+    let words = GcAllocator::block_size(base as *const u8) / WORD;
+    (0..words)
+        .filter_map(|i| {
+            let value = base.add(i).read();
+            let target = GcAllocator::base(value as *const u8)?.as_ptr();
+            Some(PossiblePointer {
+                offset: i * WORD,
+                value,
+                target,
+                target_size: GcAllocator::block_size(target),
+            })
+        })
+        .collect()
+}
+
+/// The most anomalies `verify_heap` reports. It can't allocate while
+/// walking the heap, so they are stored in a `Vec` reserved up-front.
+const MAX_ANOMALIES: usize = 256;
+
+/// A problem found by `verify_heap`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The collector reported a reachable object whose block headers give
+    /// a different base address.
+    BadBase { obj: *const u8, base: *const u8 },
+    /// The collector reported a reachable object larger than the block
+    /// headers say its block is.
+    BadSize {
+        obj: *const u8,
+        size: usize,
+        block_size: usize,
+    },
+}
+
+/// The results of `verify_heap`.
+#[derive(Clone, Debug, Default)]
+pub struct HeapReport {
+    /// The number of reachable objects checked.
+    pub objects: usize,
+    /// The total size of those objects, in bytes.
+    pub bytes: usize,
+    /// The problems found, up to a limit.
+    pub anomalies: Vec<Anomaly>,
+    /// The number of problems found beyond the limit.
+    pub anomalies_dropped: usize,
+}
+
+impl HeapReport {
+    pub fn is_ok(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+}
+
+/// Force a collection, then walk every reachable object with the world
+/// stopped, checking that the collector's records of where each object
+/// starts and how big it is agree. Disagreement means the collector's own
+/// data structures have been corrupted, e.g. by a write through a dangling
+/// pointer. This is slow, and intended for soak tests.
+///
+/// The collector doesn't expose its finalizer table, and libgc doesn't keep
+/// type metadata for objects, so neither is checked.
+pub fn verify_heap() -> HeapReport {
+    let mut report = HeapReport {
+        anomalies: Vec::with_capacity(MAX_ANOMALIES),
+        ..HeapReport::default()
+    };
+    crate::gc::collect();
+    let mut check = |obj: *mut u8, size: usize| {
+        report.objects += 1;
+        report.bytes += size;
+        let anomaly = match GcAllocator::base(obj) {
+            Some(base) if base.as_ptr() != obj => Anomaly::BadBase {
+                obj,
+                base: base.as_ptr(),
+            },
+            None => Anomaly::BadBase {
+                obj,
+                base: std::ptr::null(),
+            },
+            Some(_) => {
+                let block_size = unsafe { GcAllocator::block_size(obj) };
+                if size <= block_size {
+                    return;
+                }
+                Anomaly::BadSize {
+                    obj,
+                    size,
+                    block_size,
+                }
+            }
+        };
+        if report.anomalies.len() < MAX_ANOMALIES {
+            report.anomalies.push(anomaly);
+        } else {
+            report.anomalies_dropped += 1;
+        }
+    };
+    unsafe {
+        GcAllocator::with_world_stopped(|| GcAllocator::enumerate_reachable_objects(&mut check))
+    };
+    report
+}
+
+/// The high bits of every word of a canary. No heap address has them set,
+/// so a canary never looks like a pointer itself.
+const CANARY: u64 = 0xCA7A_0000_0000_0000;
+
+/// The number of words in a canary.
+const CANARY_WORDS: usize = 4;
+
+/// The results of `false_retention_experiment`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    /// The number of canaries allocated.
+    pub canaries: usize,
+    /// The number of canaries which survived a collection after every
+    /// reference to them was dropped.
+    pub retained: usize,
+}
+
+impl RetentionReport {
+    /// The fraction of canaries which were falsely retained.
+    pub fn rate(&self) -> f64 {
+        if self.canaries == 0 {
+            0.0
+        } else {
+            self.retained as f64 / self.canaries as f64
+        }
+    }
+}
+
+impl fmt::Display for RetentionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} canaries falsely retained ({:.2}%)",
+            self.retained,
+            self.canaries,
+            self.rate() * 100.0
+        )
+    }
+}
+
+#[inline(never)]
+fn make_canaries(n: usize) -> Vec<GcWeak<[u64; CANARY_WORDS]>> {
+    (0..n)
+        .map(|i| Gc::downgrade(&Gc::new([CANARY | i as u64; CANARY_WORDS])))
+        .collect()
+}
+
+/// Allocate `n` small objects ("canaries"), drop every reference to them,
+/// and force a collection. Any canary which survives is kept alive only by
+/// a word which happens to look like a pointer to it: a stale stack slot,
+/// a register, or an integer stored in a scanned block.
+///
+/// Canaries are interleaved with the rest of the heap, so the result
+/// reflects the program's own data and platform. For a representative
+/// measurement, run this on a thread and at a point where the heap looks as
+/// it does in production, and repeat it a few times.
+pub fn false_retention_experiment(n: usize) -> RetentionReport {
+    let canaries = make_canaries(n);
+    crate::gc::collect();
+    let retained = canaries.iter().filter(|c| !c.is_dead()).count();
+    RetentionReport {
+        canaries: n,
+        retained,
+    }
+}
+
+/// The size of the buffer `emergency_dump` formats into. Anything longer is
+/// truncated.
+const DUMP_BUF_SIZE: usize = 512;
+
+/// A fixed-size buffer which silently drops anything that doesn't fit. It is
+/// kept on the stack, rather than in a static, so that signal handlers
+/// running concurrently on different threads don't interleave.
+pub(crate) struct DumpBuf {
+    buf: [u8; DUMP_BUF_SIZE],
+    len: usize,
+}
+
+impl DumpBuf {
+    pub(crate) fn new() -> Self {
+        DumpBuf {
+            buf: [0; DUMP_BUF_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Write the buffer's contents to stderr with `write(2)`, and empty it.
+    pub(crate) fn flush_to_stderr(&mut self) {
+        let mut buf = &self.buf[..self.len];
+        while !buf.is_empty() {
+            let n =
+                unsafe { libc::write(libc::STDERR_FILENO, buf.as_ptr() as *const _, buf.len()) };
+            if n <= 0 {
+                break;
+            }
+            buf = &buf[n as usize..];
+        }
+        self.len = 0;
+    }
+}
+
+impl fmt::Write for DumpBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(DUMP_BUF_SIZE - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Write the collector's statistics, and libgc's own counters if
+/// `stats_counters` is enabled, to stderr as a single line. This neither
+/// allocates nor takes any locks, so it can be called from a signal handler
+/// (e.g. for `SIGUSR1`), at the cost of the numbers being slightly
+/// inconsistent if the collector is running.
+pub fn emergency_dump() {
+    let mut out = DumpBuf::new();
+    let _ = write_dump(&mut out);
+    out.flush_to_stderr();
+}
+
+fn write_dump(w: &mut impl fmt::Write) -> fmt::Result {
+    w.write_str("libgc: ")?;
+    GcAllocator::get_stats_unlocked().write_to(w)?;
+    #[cfg(feature = "stats_counters")]
+    {
+        use crate::stats::*;
+        use std::sync::atomic::Ordering;
+        write!(
+            w,
+            "; {} finalizers registered, {} run, {} panicked; {} bytes allocated by Gc",
+            NUM_REGISTERED_FINALIZERS.load(Ordering::Relaxed),
+            NUM_FINALIZERS_RUN.load(Ordering::Relaxed),
+            NUM_FINALIZERS_PANICKED.load(Ordering::Relaxed),
+            bytes_allocated()
+        )?;
+    }
+    w.write_str("\n")
+}
@@ -0,0 +1,203 @@
+//! Snapshots of the static roots the collector scans.
+//!
+//! Before each collection, the collector walks the program's loaded objects
+//! and registers their writable segments (`.data`, `.bss`, and so on) as
+//! roots. A plugin which brings a large `.bss` with it can keep the heap
+//! from shrinking without anything pointing to it from the program's own
+//! code. Comparing snapshots from before and after loading it shows which
+//! ranges appeared.
+//!
+//! The collector doesn't expose its thread table, or ranges added directly
+//! with `GC_add_roots`, so only loaded objects' segments are covered.
+use std::{
+    cell::UnsafeCell,
+    ffi::CStr,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Once,
+    },
+};
+
+use crate::GcAllocator;
+
+/// The most segments recorded by a snapshot. They are recorded while the
+/// collector holds its lock, so can't be stored anywhere which allocates.
+const MAX_SEGMENTS: usize = 1024;
+/// The most bytes of an object's name which are kept.
+const MAX_NAME: usize = 128;
+
+#[derive(Copy, Clone)]
+struct Segment {
+    start: usize,
+    size: usize,
+    name: [u8; MAX_NAME],
+    name_len: usize,
+}
+
+const EMPTY: Segment = Segment {
+    start: 0,
+    size: 0,
+    name: [0; MAX_NAME],
+    name_len: 0,
+};
+
+/// Only touched with the allocation lock held.
+struct Segments(UnsafeCell<[Segment; MAX_SEGMENTS]>);
+
+unsafe impl Sync for Segments {}
+
+static SEGMENTS: Segments = Segments(UnsafeCell::new([EMPTY; MAX_SEGMENTS]));
+static NUM_SEGMENTS: AtomicUsize = AtomicUsize::new(0);
+static NUM_DROPPED: AtomicUsize = AtomicUsize::new(0);
+/// The collection during which the segments were recorded. The collector
+/// registers every segment afresh each time, so a new collection starts a
+/// new list.
+static RECORDED_AT: AtomicUsize = AtomicUsize::new(usize::MAX);
+static INSTALL: Once = Once::new();
+
+unsafe extern "C" fn record(name: *const u8, start: *mut u8, size: usize) -> i32 {
+    let gc_no = GcAllocator::num_collections();
+    if RECORDED_AT.swap(gc_no, Ordering::Relaxed) != gc_no {
+        NUM_SEGMENTS.store(0, Ordering::Relaxed);
+        NUM_DROPPED.store(0, Ordering::Relaxed);
+    }
+    let i = NUM_SEGMENTS.load(Ordering::Relaxed);
+    if i == MAX_SEGMENTS {
+        NUM_DROPPED.fetch_add(1, Ordering::Relaxed);
+        return 1;
+    }
+    let segments = &mut *SEGMENTS.0.get();
+    let seg = &mut segments[i];
+    seg.start = start as usize;
+    seg.size = size;
+    seg.name_len = 0;
+    if !name.is_null() {
+        let name = CStr::from_ptr(name as *const _).to_bytes();
+        seg.name_len = name.len().min(MAX_NAME);
+        seg.name[..seg.name_len].copy_from_slice(&name[..seg.name_len]);
+    }
+    NUM_SEGMENTS.store(i + 1, Ordering::Relaxed);
+    1
+}
+
+/// A range of memory which the collector scans for pointers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RootRange {
+    pub start: usize,
+    pub size: usize,
+    /// The path of the object the range belongs to, or an empty string for
+    /// the program itself. Long paths are truncated.
+    pub name: String,
+}
+
+impl fmt::Display for RootRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = if self.name.is_empty() {
+            "<program>"
+        } else {
+            &self.name
+        };
+        write!(
+            f,
+            "{:#x}..{:#x} ({} bytes) {}",
+            self.start,
+            self.start + self.size,
+            self.size,
+            name
+        )
+    }
+}
+
+/// The static roots at one point in time, as returned by `root_snapshot`.
+#[derive(Clone, Debug, Default)]
+pub struct RootSnapshot {
+    /// The ranges, sorted by address.
+    pub ranges: Vec<RootRange>,
+    /// The number of ranges beyond the limit which weren't recorded.
+    pub ranges_dropped: usize,
+}
+
+impl RootSnapshot {
+    /// The total size of the ranges, in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.ranges.iter().map(|r| r.size).sum()
+    }
+
+    /// The changes from `self` to `later`.
+    pub fn diff(&self, later: &RootSnapshot) -> RootDiff {
+        RootDiff {
+            added: later
+                .ranges
+                .iter()
+                .filter(|r| self.ranges.binary_search(r).is_err())
+                .cloned()
+                .collect(),
+            removed: self
+                .ranges
+                .iter()
+                .filter(|r| later.ranges.binary_search(r).is_err())
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// The ranges which appeared and disappeared between two snapshots. A
+/// range which changed size shows up as removed and added again.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RootDiff {
+    pub added: Vec<RootRange>,
+    pub removed: Vec<RootRange>,
+}
+
+impl RootDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for RootDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in &self.added {
+            writeln!(f, "+ {}", r)?;
+        }
+        for r in &self.removed {
+            writeln!(f, "- {}", r)?;
+        }
+        Ok(())
+    }
+}
+
+/// Force a collection, and return the static roots the collector scanned
+/// for it.
+///
+/// The first call asks the collector to report the segments it registers,
+/// replacing any callback set with `GcAllocator::set_has_static_roots_callback`.
+pub fn root_snapshot() -> RootSnapshot {
+    INSTALL.call_once(|| unsafe { GcAllocator::set_has_static_roots_callback(Some(record)) });
+    crate::gc::collect();
+    let mut segments = Vec::with_capacity(MAX_SEGMENTS);
+    let dropped = GcAllocator::with_alloc_lock(|| {
+        let n = NUM_SEGMENTS.load(Ordering::Relaxed);
+        let recorded = unsafe { &*SEGMENTS.0.get() };
+        segments.extend_from_slice(&recorded[..n]);
+        NUM_DROPPED.load(Ordering::Relaxed)
+    });
+    let mut ranges = segments
+        .iter()
+        .map(|s| RootRange {
+            start: s.start,
+            size: s.size,
+            name: String::from_utf8_lossy(&s.name[..s.name_len]).into_owned(),
+        })
+        .collect::<Vec<_>>();
+    // The collector may register the same segment twice between
+    // collections, e.g. when it is initialized.
+    ranges.sort();
+    ranges.dedup();
+    RootSnapshot {
+        ranges,
+        ranges_dropped: dropped,
+    }
+}
@@ -0,0 +1,123 @@
+//! Formatting values directly into GC allocated strings.
+//!
+//! Printing a heap object with `format!` or `to_string` builds a `String` on
+//! the malloc heap, which is usually then copied into a GC allocated string
+//! and thrown away. `GcStringBuilder` instead accumulates output in a GC
+//! allocated buffer, which `finish` hands back as a `Gc<str>` without a copy.
+use std::{
+    alloc::Layout,
+    fmt::{self, Write},
+    ptr, slice, str,
+};
+
+use crate::{alloc::GcAlloc, Gc, ALLOCATOR};
+
+/// A growable string whose buffer is allocated by the collector.
+///
+/// The buffer is allocated with `GcAlloc::alloc_atomic`, so the collector
+/// never scans it for pointers. Buffers which are outgrown are left for the
+/// collector to reclaim.
+pub struct GcStringBuilder {
+    buf: *mut u8,
+    len: usize,
+    cap: usize,
+}
+
+impl GcStringBuilder {
+    pub fn new() -> Self {
+        GcStringBuilder::with_capacity(0)
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        let mut sb = GcStringBuilder {
+            buf: ptr::NonNull::dangling().as_ptr(),
+            len: 0,
+            cap: 0,
+        };
+        sb.reserve(cap);
+        sb
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(self.buf, self.len)) }
+    }
+
+    /// Ensure there is room for at least `additional` more bytes.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.len.checked_add(additional).expect("capacity overflow");
+        if needed <= self.cap {
+            return;
+        }
+        let cap = needed.max(self.cap * 2);
+        let layout = Layout::array::<u8>(cap).unwrap();
+        let buf = GcAlloc::alloc_atomic(&ALLOCATOR, layout).unwrap().as_ptr() as *mut u8;
+        unsafe { ptr::copy_nonoverlapping(self.buf, buf, self.len) };
+        self.buf = buf;
+        self.cap = cap;
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        self.reserve(s.len());
+        unsafe { ptr::copy_nonoverlapping(s.as_ptr(), self.buf.add(self.len), s.len()) };
+        self.len += s.len();
+    }
+
+    /// Return the contents as a `Gc<str>`. This doesn't copy: the builder's
+    /// buffer becomes the string.
+    pub fn finish(self) -> Gc<str> {
+        if self.cap == 0 {
+            // There is no buffer to hand over.
+            return Gc::from("");
+        }
+        Gc::from_raw(ptr::slice_from_raw_parts(self.buf, self.len) as *const str)
+    }
+}
+
+impl Default for GcStringBuilder {
+    fn default() -> Self {
+        GcStringBuilder::new()
+    }
+}
+
+impl Write for GcStringBuilder {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for GcStringBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Types which can format themselves into a `GcStringBuilder`.
+///
+/// Every `Display` type implements this by writing its `Display` output.
+/// Runtimes can specialize it for their own types to write more directly,
+/// e.g. by copying out an already GC allocated string.
+pub trait GcDisplay {
+    fn display_into(&self, buf: &mut GcStringBuilder) -> fmt::Result;
+}
+
+impl<T: fmt::Display + ?Sized> GcDisplay for T {
+    default fn display_into(&self, buf: &mut GcStringBuilder) -> fmt::Result {
+        write!(buf, "{}", self)
+    }
+}
+
+impl<T: GcDisplay + ?Sized + Send> Gc<T> {
+    /// Format the value into `buf`, without an intermediate `String`.
+    pub fn display_into(&self, buf: &mut GcStringBuilder) -> fmt::Result {
+        (**self).display_into(buf)
+    }
+}
@@ -0,0 +1,286 @@
+//! Objects whose layout is only known at run time.
+//!
+//! Runtimes which create types as they run (e.g. hidden classes in a JIT)
+//! know where each object's pointers are, but can't describe that to the
+//! collector through Rust's type system. `Gc::new_dynamic` takes that
+//! description as a value instead.
+use std::{
+    alloc::Layout,
+    cell::UnsafeCell,
+    mem::{size_of, transmute},
+    ptr,
+};
+
+use crate::{alloc::GcAlloc, Gc, GcAllocator, ALLOCATOR};
+
+const WORD: usize = size_of::<usize>();
+/// The collector aligns every block to two words.
+const MAX_ALIGN: usize = 2 * WORD;
+/// The collector's descriptors can describe this many words precisely.
+const MAX_PRECISE_WORDS: usize = usize::BITS as usize;
+
+/// How the collector should scan an object. See `LayoutInfo`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Scan {
+    /// Every word may be a pointer.
+    Conservative,
+    /// No word is a pointer.
+    Atomic,
+    /// Only the words whose bits are set may be pointers.
+    Precise { bitmap: usize, words: usize },
+}
+
+impl Scan {
+    /// How to scan an object holding what `self` describes at offset 0, and
+    /// what `other` describes at `offset` bytes.
+    fn place(self, other: Scan, offset: usize) -> Scan {
+        let (other_bitmap, other_words) = match other {
+            Scan::Atomic => return self,
+            Scan::Conservative => (0, 0),
+            Scan::Precise { bitmap, words } => (bitmap, words),
+        };
+        assert!(
+            offset % WORD == 0,
+            "pointers at offset {} would be unaligned",
+            offset
+        );
+        let (bitmap, words) = match self {
+            Scan::Conservative => return Scan::Conservative,
+            Scan::Atomic => (0, 0),
+            Scan::Precise { bitmap, words } => (bitmap, words),
+        };
+        let shift = offset / WORD;
+        if other == Scan::Conservative || shift + other_words > MAX_PRECISE_WORDS {
+            return Scan::Conservative;
+        }
+        Scan::Precise {
+            bitmap: bitmap | other_bitmap << shift,
+            words: words.max(shift + other_words),
+        }
+    }
+}
+
+/// The size and alignment of a dynamically typed object, and which of its
+/// words may hold pointers to GC objects.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LayoutInfo {
+    layout: Layout,
+    scan: Scan,
+    /// The collector's descriptor for precisely scanned objects, made once
+    /// here rather than on every allocation.
+    descr: usize,
+}
+
+impl LayoutInfo {
+    /// An object whose only pointers are at `pointer_offsets` (in bytes,
+    /// which must be word aligned). The collector can only describe the
+    /// first 64 words of an object precisely, so an object with pointers
+    /// beyond that is scanned conservatively.
+    ///
+    /// # Panics
+    ///
+    /// If `align` is larger than the collector's alignment (16 bytes), or an
+    /// offset isn't word aligned or is out of bounds.
+    pub fn new(size: usize, align: usize, pointer_offsets: &[usize]) -> Self {
+        let info = LayoutInfo::conservative(size, align);
+        let mut bitmap = 0;
+        let mut words = 0;
+        for &off in pointer_offsets {
+            assert!(
+                off % WORD == 0 && off + WORD <= size,
+                "pointer offset {} out of bounds or unaligned in a {} byte object",
+                off,
+                size
+            );
+            let word = off / WORD;
+            if word >= MAX_PRECISE_WORDS {
+                return info;
+            }
+            bitmap |= 1 << word;
+            words = words.max(word + 1);
+        }
+        let scan = match words {
+            0 => Scan::Atomic,
+            _ => Scan::Precise { bitmap, words },
+        };
+        LayoutInfo::from_scan(info.layout, scan)
+    }
+
+    /// An object any of whose words may be a pointer.
+    ///
+    /// # Panics
+    ///
+    /// If `align` is larger than the collector's alignment (16 bytes).
+    pub fn conservative(size: usize, align: usize) -> Self {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        assert!(
+            align <= MAX_ALIGN,
+            "alignment {} exceeds the collector's alignment of {}",
+            align,
+            MAX_ALIGN
+        );
+        LayoutInfo::from_scan(layout, Scan::Conservative)
+    }
+
+    fn from_scan(layout: Layout, scan: Scan) -> Self {
+        let descr = match scan {
+            Scan::Precise { bitmap, words } => GcAllocator::make_descriptor(bitmap, words),
+            _ => 0,
+        };
+        LayoutInfo {
+            layout,
+            scan,
+            descr,
+        }
+    }
+
+    /// This layout preceded by `bytes` of data without pointers, as when it
+    /// is a field at that offset in a larger object.
+    ///
+    /// # Panics
+    ///
+    /// If `bytes` isn't a multiple of this layout's alignment, or would
+    /// leave pointers unaligned.
+    pub fn at_offset(&self, bytes: usize) -> Self {
+        assert_eq!(bytes % self.align(), 0, "offset {} is misaligned", bytes);
+        let layout = Layout::from_size_align(bytes + self.size(), self.align()).unwrap();
+        LayoutInfo::from_scan(layout, Scan::Atomic.place(self.scan, bytes))
+    }
+
+    /// This layout followed by `next`, laid out as the fields of a
+    /// `#[repr(C)]` struct would be.
+    ///
+    /// # Panics
+    ///
+    /// If `next`'s pointers would be unaligned.
+    pub fn concat(&self, next: &Self) -> Self {
+        let (layout, offset) = self.layout.extend(next.layout).unwrap();
+        LayoutInfo::from_scan(layout.pad_to_align(), self.scan.place(next.scan, offset))
+    }
+
+    /// `n` copies of this layout, laid out as an array would be.
+    ///
+    /// # Panics
+    ///
+    /// If the copies' pointers would be unaligned.
+    pub fn repeat(&self, n: usize) -> Self {
+        let stride = self.layout.pad_to_align().size();
+        let layout = Layout::from_size_align(stride * n, self.align()).unwrap();
+        let scan = (0..n).fold(Scan::Atomic, |scan, i| scan.place(self.scan, i * stride));
+        LayoutInfo::from_scan(layout, scan)
+    }
+
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    pub fn align(&self) -> usize {
+        self.layout.align()
+    }
+}
+
+/// A GC object with a run time layout, created by `Gc::new_dynamic`. It is
+/// an unstructured sequence of bytes, which the runtime reads and writes
+/// through `as_ptr`.
+#[repr(transparent)]
+pub struct DynObject([UnsafeCell<u8>]);
+
+impl DynObject {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.0.as_ptr() as *mut u8
+    }
+}
+
+impl Gc<DynObject> {
+    /// Allocate an object laid out as described by `layout`, initialized
+    /// with `value_bytes`. If given, `drop_fn` is called with a pointer to
+    /// the object once it is unreachable.
+    ///
+    /// # Panics
+    ///
+    /// If `value_bytes` isn't the size given by `layout`.
+    pub fn new_dynamic(
+        value_bytes: &[u8],
+        layout: &LayoutInfo,
+        drop_fn: Option<unsafe fn(*mut u8)>,
+    ) -> Gc<DynObject> {
+        assert_eq!(
+            value_bytes.len(),
+            layout.size(),
+            "value doesn't match the size of its layout"
+        );
+        // The collector doesn't accept empty allocations.
+        let size = layout.layout.size().max(1);
+        let block = Layout::from_size_align(size, layout.layout.align()).unwrap();
+        let base = match layout.scan {
+            Scan::Conservative => GcAlloc::allocate(&ALLOCATOR, block),
+            Scan::Atomic => GcAlloc::alloc_atomic(&ALLOCATOR, block),
+            Scan::Precise { .. } => ALLOCATOR.allocate_typed(block, layout.descr),
+        }
+        .unwrap()
+        .as_ptr() as *mut u8;
+
+        unsafe {
+            ptr::copy_nonoverlapping(value_bytes.as_ptr(), base, value_bytes.len());
+            if let Some(drop_fn) = drop_fn {
+                GcAlloc::register_finalizer(
+                    &ALLOCATOR,
+                    base,
+                    Some(run_drop_fn),
+                    drop_fn as *mut u8,
+                );
+            }
+        }
+        Gc::from_raw(ptr::slice_from_raw_parts(base, value_bytes.len()) as *const DynObject)
+    }
+}
+
+/// The finalizer for dynamic objects, which is passed the object's drop
+/// function as its client data.
+unsafe extern "C" fn run_drop_fn(obj: *mut u8, drop_fn: *mut u8) {
+    let drop_fn = transmute::<*mut u8, unsafe fn(*mut u8)>(drop_fn);
+    crate::alloc::catch_finalizer_panic("DynObject", || drop_fn(obj));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compose_layouts() {
+        let ptr = LayoutInfo::new(WORD, WORD, &[0]);
+        let bytes = LayoutInfo::new(3, 1, &[]);
+
+        assert_eq!(
+            ptr.concat(&bytes).concat(&ptr),
+            LayoutInfo::new(3 * WORD, WORD, &[0, 2 * WORD])
+        );
+        assert_eq!(
+            ptr.at_offset(WORD),
+            LayoutInfo::new(2 * WORD, WORD, &[WORD])
+        );
+        assert_eq!(
+            ptr.concat(&bytes).repeat(3),
+            LayoutInfo::new(6 * WORD, WORD, &[0, 2 * WORD, 4 * WORD])
+        );
+        assert_eq!(bytes.repeat(4), LayoutInfo::new(12, 1, &[]));
+        assert_eq!(
+            ptr.repeat(MAX_PRECISE_WORDS + 1),
+            LayoutInfo::conservative((MAX_PRECISE_WORDS + 1) * WORD, WORD)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn unaligned_pointers() {
+        LayoutInfo::new(WORD, 1, &[0]).at_offset(1);
+    }
+}
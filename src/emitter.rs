@@ -0,0 +1,74 @@
+//! The observer pattern without unsubscribing.
+//!
+//! An emitter which held its subscribers strongly would keep them alive for
+//! as long as it lives, so every subscriber would have to remember to
+//! unsubscribe. `GcEventEmitter` holds them weakly instead: a subscriber
+//! which becomes unreachable is collected as normal, and is dropped from the
+//! emitter the next time an event is emitted.
+use std::{cell::RefCell, mem};
+
+use crate::Gc;
+
+/// A subscriber: returns false, without being called, if it has been
+/// collected.
+type Subscriber<E> = Box<dyn Fn(&E) -> bool + Send>;
+
+pub struct GcEventEmitter<E> {
+    subscribers: RefCell<Vec<Subscriber<E>>>,
+}
+
+impl<E: 'static> GcEventEmitter<E> {
+    pub fn new() -> Self {
+        GcEventEmitter {
+            subscribers: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Call `f` with `subscriber` and each event emitted from now on, for as
+    /// long as `subscriber` is reachable from elsewhere. `f` is typically a
+    /// method, e.g. `emitter.subscribe(&window, Window::on_resize)`.
+    pub fn subscribe<S: Send + 'static>(&self, subscriber: &Gc<S>, f: fn(&S, &E)) {
+        let weak = Gc::downgrade(subscriber);
+        self.subscribers
+            .borrow_mut()
+            .push(Box::new(move |e| match weak.upgrade() {
+                Some(s) => {
+                    f(&s, e);
+                    true
+                }
+                None => false,
+            }));
+    }
+
+    /// Call every live subscriber with `event`, in the order they subscribed,
+    /// and forget those which have been collected. Returns the number of
+    /// subscribers called.
+    ///
+    /// Subscribers may subscribe others while an event is emitted, but they
+    /// aren't called until the next event.
+    pub fn emit(&self, event: E) -> usize {
+        let mut subscribers = mem::take(&mut *self.subscribers.borrow_mut());
+        subscribers.retain(|s| s(&event));
+        let called = subscribers.len();
+        let mut current = self.subscribers.borrow_mut();
+        subscribers.append(&mut current);
+        *current = subscribers;
+        called
+    }
+
+    /// The number of subscribers, including any which have been collected
+    /// since the last event was emitted.
+    pub fn len(&self) -> usize {
+        self.subscribers.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<E: 'static> Default for GcEventEmitter<E> {
+    fn default() -> Self {
+        GcEventEmitter::new()
+    }
+}
@@ -0,0 +1,51 @@
+//! Errors which live on the GC heap.
+//!
+//! Runtimes which represent exceptions as GC objects can hand them to Rust
+//! code expecting `std::error::Error` without copying them out of the heap.
+//! `Gc<E>` is itself an `Error` when `E` is; `GcError` erases the type.
+use std::{error::Error, fmt};
+
+use crate::Gc;
+
+/// A GC allocated error of any type. Like `Gc`, it is `Copy`.
+#[derive(Copy, Clone)]
+pub struct GcError(Gc<dyn Error + Send>);
+
+impl GcError {
+    pub fn new<E: Error + Send + 'static>(err: E) -> Self {
+        GcError(Gc::new(err))
+    }
+
+    /// Returns the error as an `E`, if that is its type.
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.0.downcast_ref()
+    }
+
+    pub fn get(&self) -> Gc<dyn Error + Send> {
+        self.0
+    }
+}
+
+impl<E: Error + Send + 'static> From<Gc<E>> for GcError {
+    fn from(err: Gc<E>) -> Self {
+        GcError(err)
+    }
+}
+
+impl fmt::Display for GcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for GcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl Error for GcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
@@ -0,0 +1,146 @@
+//! A minimal single-threaded executor whose tasks live on the GC heap.
+//!
+//! This is a reference for runtimes which need to drive Rust futures
+//! alongside a garbage collected guest language. Each task is a `Gc`, and a
+//! `Waker` is just a pointer to its task, so wakers need no reference
+//! counting: a task stays alive as long as it is queued or something holds
+//! one of its wakers. A task which isn't queued and whose wakers have all
+//! been dropped can never be polled again, so it is collected, and its
+//! future dropped by its finalizer, rather than leaking.
+//!
+//! Wakers must therefore be kept where the collector can see them (e.g. on
+//! the GC heap, or on the stack of a registered thread), not in memory it
+//! doesn't scan.
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use crate::Gc;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Queue {
+    tasks: Mutex<VecDeque<Gc<Task>>>,
+}
+
+struct Task {
+    /// `None` once the future has completed.
+    future: Mutex<Option<BoxFuture>>,
+    queue: Gc<Queue>,
+    /// Whether the task is in the queue, so that it is queued at most once
+    /// however many times it is woken.
+    queued: AtomicBool,
+}
+
+impl Task {
+    fn schedule(this: Gc<Task>) {
+        if !this.queued.swap(true, Ordering::AcqRel) {
+            this.queue.tasks.lock().unwrap().push_back(this);
+        }
+    }
+
+    fn waker(this: Gc<Task>) -> Waker {
+        unsafe { Waker::from_raw(raw_waker(this)) }
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake_task, wake_task, drop_waker);
+
+fn raw_waker(task: Gc<Task>) -> RawWaker {
+    RawWaker::new(Gc::into_raw(task) as *const (), &VTABLE)
+}
+
+unsafe fn clone_waker(task: *const ()) -> RawWaker {
+    raw_waker(Gc::from_raw(task as *const Task))
+}
+
+unsafe fn wake_task(task: *const ()) {
+    Task::schedule(Gc::from_raw(task as *const Task))
+}
+
+unsafe fn drop_waker(_: *const ()) {}
+
+/// Runs spawned futures on the thread which calls `run_until_stalled`.
+pub struct Executor {
+    queue: Gc<Queue>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            queue: Gc::new(Queue {
+                tasks: Mutex::new(VecDeque::new()),
+            }),
+        }
+    }
+
+    /// Queue `future` to be polled by the next `run_until_stalled`.
+    pub fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
+        Task::schedule(Gc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            queue: self.queue,
+            queued: AtomicBool::new(false),
+        }));
+    }
+
+    /// Poll queued tasks until none are left, including any woken while
+    /// doing so. Returns the number of polls.
+    pub fn run_until_stalled(&self) -> usize {
+        let mut polls = 0;
+        loop {
+            let task = match self.queue.tasks.lock().unwrap().pop_front() {
+                Some(task) => task,
+                None => return polls,
+            };
+            // A task woken while it is polled is queued again.
+            task.queued.store(false, Ordering::Release);
+            let waker = Task::waker(task);
+            let mut future = task.future.lock().unwrap();
+            if let Some(f) = future.as_mut() {
+                if f.as_mut().poll(&mut Context::from_waker(&waker)).is_ready() {
+                    *future = None;
+                }
+                polls += 1;
+            }
+        }
+    }
+
+    /// The number of tasks waiting to be polled.
+    pub fn num_queued(&self) -> usize {
+        self.queue.tasks.lock().unwrap().len()
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Executor::new()
+    }
+}
+
+/// A future which returns `Pending` the first time it is polled, after
+/// waking itself, letting other tasks run.
+pub fn yield_now() -> impl Future<Output = ()> {
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    YieldNow(false)
+}
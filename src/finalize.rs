@@ -0,0 +1,112 @@
+//! Running finalizers away from the code which allocates.
+//!
+//! By default, the collector runs finalizers (i.e. the `Drop` impls of
+//! unreachable values) as soon as it has found them, on whichever thread
+//! allocated or collected, before returning to it. A `Drop` impl may then
+//! run in the middle of code which holds a lock that the impl needs, or
+//! which has left runtime state half-updated.
+//!
+//! Starting a `FinalizerThread` avoids that: finalizers are queued instead,
+//! and run by a dedicated thread, so no `Drop` impl ever runs inside an
+//! allocation. Runtimes which would rather pick the moment themselves can
+//! instead enable `GcAllocator::set_finalize_on_demand` and call
+//! `run_finalizers` at their safepoints.
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread::{self, JoinHandle, Thread},
+};
+
+use crate::GcAllocator;
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static STOP: AtomicBool = AtomicBool::new(false);
+/// The finalizer thread, for the collector's notifier to wake.
+static WORKER: Mutex<Option<Thread>> = Mutex::new(None);
+
+pub(crate) fn thread_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Run every queued finalizer on the calling thread, returning how many
+/// were run. Finalizers are only queued while a `FinalizerThread` is
+/// running, in `GcConfig::deterministic_test_mode`, or once
+/// `GcAllocator::set_finalize_on_demand` has been enabled.
+pub fn run_finalizers() -> usize {
+    let mut n = 0;
+    while GcAllocator::has_queued_finalizers() {
+        n += GcAllocator::invoke_finalizers();
+    }
+    n
+}
+
+/// A thread which runs finalizers as the collector queues them, until
+/// dropped. Only one can run at a time.
+pub struct FinalizerThread {
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FinalizerThread {
+    /// # Panics
+    ///
+    /// If a `FinalizerThread` is already running.
+    pub fn start() -> FinalizerThread {
+        assert!(
+            !RUNNING.swap(true, Ordering::SeqCst),
+            "a FinalizerThread is already running"
+        );
+        STOP.store(false, Ordering::SeqCst);
+        GcAllocator::allow_register_threads();
+        let thread = thread::Builder::new()
+            .name("libgc-finalizer".to_string())
+            .spawn(run)
+            .expect("failed to spawn the finalizer thread");
+        // The worker must be known before any finalizer can be queued, or
+        // the first notification could be missed.
+        *WORKER.lock().unwrap() = Some(thread.thread().clone());
+        unsafe { GcAllocator::set_finalizer_notifier(Some(notify)) };
+        GcAllocator::set_finalize_on_demand(true);
+        FinalizerThread {
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for FinalizerThread {
+    /// Stop the thread once it has run every finalizer queued so far. Those
+    /// queued afterwards are run by the collector as before.
+    fn drop(&mut self) {
+        GcAllocator::set_finalize_on_demand(crate::config::deterministic());
+        unsafe { GcAllocator::set_finalizer_notifier(None) };
+        STOP.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+        *WORKER.lock().unwrap() = None;
+        RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Called by the collector, outside of its allocation lock, when finalizers
+/// have been queued. Waking the worker neither allocates nor blocks for
+/// long.
+unsafe extern "C" fn notify() {
+    if let Some(worker) = &*WORKER.lock().unwrap() {
+        worker.unpark();
+    }
+}
+
+fn run() {
+    crate::thread::ensure_registered();
+    loop {
+        run_finalizers();
+        if STOP.load(Ordering::SeqCst) {
+            run_finalizers();
+            return;
+        }
+        thread::park();
+    }
+}
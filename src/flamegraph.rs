@@ -0,0 +1,164 @@
+//! Attributing collections and allocations to the code which caused them.
+//!
+//! While recording, a backtrace is taken at the first `Gc` allocation after
+//! each collection, and at every `n`th `Gc` allocation on each thread. When
+//! the process exits, the backtraces are written out in the folded stack
+//! format understood by `flamegraph.pl` and inferno, under two root frames:
+//! `collections` and `allocations`.
+//!
+//! The collector reports collections with its allocation lock held, when
+//! backtraces can't be taken, so a collection is instead charged to the next
+//! allocation. That is usually the allocation which triggered it, but may be
+//! on another thread if several are allocating.
+use std::{
+    backtrace::Backtrace,
+    cell::Cell,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex, Once,
+    },
+};
+
+use crate::GcAllocator;
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static SAMPLE_EVERY: AtomicUsize = AtomicUsize::new(0);
+static SEEN_COLLECTIONS: AtomicUsize = AtomicUsize::new(0);
+static WRITE_AT_EXIT: Once = Once::new();
+static PROFILE: Mutex<Option<Profile>> = Mutex::new(None);
+
+thread_local! {
+    static UNTIL_SAMPLE: Cell<usize> = Cell::new(0);
+    /// Set while sampling, as taking a backtrace can run finalizers which
+    /// themselves allocate.
+    static SAMPLING: Cell<bool> = Cell::new(false);
+}
+
+struct Profile {
+    path: PathBuf,
+    /// Maps each folded stack (including its root frame) to its count.
+    stacks: HashMap<String, usize>,
+}
+
+/// Start recording, to be written to `path` on exit. In addition to the
+/// allocations which follow collections, every `sample_every`th allocation on
+/// each thread is recorded, or none if it's 0.
+pub fn start(path: &Path, sample_every: usize) {
+    *PROFILE.lock().unwrap() = Some(Profile {
+        path: path.to_owned(),
+        stacks: HashMap::new(),
+    });
+    SAMPLE_EVERY.store(sample_every, Ordering::Relaxed);
+    SEEN_COLLECTIONS.store(GcAllocator::num_collections(), Ordering::Relaxed);
+    RECORDING.store(true, Ordering::Release);
+    WRITE_AT_EXIT.call_once(|| unsafe {
+        libc::atexit(write_at_exit);
+    });
+}
+
+/// Stop recording and write out what has been recorded so far, rather than
+/// waiting for the process to exit.
+pub fn stop() -> io::Result<()> {
+    RECORDING.store(false, Ordering::Release);
+    match PROFILE.lock().unwrap().take() {
+        Some(profile) => profile.write(),
+        None => Ok(()),
+    }
+}
+
+extern "C" fn write_at_exit() {
+    if let Err(e) = stop() {
+        eprintln!("libgc: couldn't write flamegraph: {}", e);
+    }
+}
+
+/// Called on every `Gc` allocation.
+#[inline]
+pub(crate) fn on_alloc() {
+    if RECORDING.load(Ordering::Relaxed) {
+        sample();
+    }
+}
+
+#[inline(never)]
+fn sample() {
+    if SAMPLING.with(|s| s.replace(true)) {
+        return;
+    }
+    let collections = GcAllocator::num_collections();
+    if SEEN_COLLECTIONS.swap(collections, Ordering::Relaxed) != collections {
+        record("collections");
+    }
+    let every = SAMPLE_EVERY.load(Ordering::Relaxed);
+    if every != 0 {
+        let until = UNTIL_SAMPLE.with(|u| u.get());
+        if until == 0 {
+            record("allocations");
+            UNTIL_SAMPLE.with(|u| u.set(every - 1));
+        } else {
+            UNTIL_SAMPLE.with(|u| u.set(until - 1));
+        }
+    }
+    SAMPLING.with(|s| s.set(false));
+}
+
+fn record(root: &str) {
+    let stack = fold(root, &Backtrace::force_capture().to_string());
+    if let Some(profile) = PROFILE.lock().unwrap().as_mut() {
+        *profile.stacks.entry(stack).or_insert(0) += 1;
+    }
+}
+
+/// Convert a backtrace, as formatted by `Backtrace`'s `Display`, into a
+/// `;`-separated list of frames from outermost to innermost, dropping the
+/// frames inside this module and `std::backtrace`.
+fn fold(root: &str, backtrace: &str) -> String {
+    let frames = backtrace
+        .lines()
+        .filter_map(|l| {
+            let (idx, name) = l.trim_start().split_once(": ")?;
+            idx.parse::<usize>().ok()?;
+            Some(name.trim())
+        })
+        .skip_while(|f| f.starts_with("std::backtrace") || f.contains("libgc::flamegraph::"))
+        .collect::<Vec<_>>();
+    let mut folded = root.to_owned();
+    for f in frames.iter().rev() {
+        folded.push(';');
+        folded.push_str(&f.replace(';', ":"));
+    }
+    folded
+}
+
+impl Profile {
+    fn write(&self) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        for (stack, count) in &self.stacks {
+            writeln!(out, "{} {}", stack, count)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fold() {
+        let bt = "   0: std::backtrace::Backtrace::force_capture\n\
+                  \x20            at /rustc/library/std/src/backtrace.rs:312:9\n\
+                  \x20  1: libgc::flamegraph::record\n\
+                  \x20  2: libgc::gc::Gc<T>::new\n\
+                  \x20            at ./src/gc.rs:96:9\n\
+                  \x20  3: app::main\n";
+        assert_eq!(
+            fold("allocations", bt),
+            "allocations;app::main;libgc::gc::Gc<T>::new"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Objects which are no longer mutated after construction.
+//!
+//! Most objects in a VM (e.g. strings, code, and class descriptions) are
+//! built up and then never changed again. `Gc::freeze` records that in the
+//! type, as `Gc<Frozen<T>>`. `T` must have no interior mutability of its own
+//! (i.e. no `Cell`s, `RefCell`s, or `Mutex`es directly inside it), so once
+//! frozen the value can only change through unsafe code.
+//!
+//! With the `debug_checks` feature, values of hashable standard types
+//! (integers, `bool`, `char`, `String`, and `Vec`s, `Box`es, `Option`s,
+//! arrays and tuples of them) are hashed when they are frozen, and the hash
+//! is checked every time they are dereferenced, catching unsafe code which
+//! writes to a frozen object. Such checks are slow, and the table of hashes
+//! is never shrunk. Other types can't be picked out by specialization, as it
+//! can't depend on arbitrary `Hash` impls, so aren't checked.
+use std::{fmt, ops::Deref};
+
+/// A value which can't be mutated through a `Gc`. See `Gc::freeze`.
+#[repr(transparent)]
+pub struct Frozen<T>(T);
+
+impl<T> Deref for Frozen<T> {
+    type Target = T;
+
+    #[inline]
+    #[cfg_attr(feature = "debug_checks", track_caller)]
+    fn deref(&self) -> &T {
+        #[cfg(feature = "debug_checks")]
+        checks::verify(&self.0);
+        &self.0
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Frozen<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Frozen<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+/// Record that the value at `value` has been frozen.
+#[inline]
+pub(crate) fn frozen<T>(_value: &T) {
+    #[cfg(feature = "debug_checks")]
+    checks::record(_value);
+}
+
+#[cfg(feature = "debug_checks")]
+mod checks {
+    use std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::{Hash, Hasher},
+        sync::Mutex,
+    };
+
+    /// The hash of each frozen value, by address. An entry may outlive its
+    /// object, but is only looked up through a `Frozen`, and so is replaced
+    /// when another value is frozen at the same address.
+    static HASHES: Mutex<Option<HashMap<usize, u64>>> = Mutex::new(None);
+
+    trait Fingerprint {
+        fn fingerprint(&self) -> Option<u64>;
+    }
+
+    impl<T> Fingerprint for T {
+        default fn fingerprint(&self) -> Option<u64> {
+            None
+        }
+    }
+
+    /// Types whose values are fingerprinted. This must only be implemented
+    /// for types whose `Hash` impls apply whatever their lifetimes, so that
+    /// it can be specialized on.
+    #[rustc_specialization_trait]
+    trait FrozenHash {
+        fn frozen_hash(&self, hasher: &mut DefaultHasher);
+    }
+
+    macro_rules! frozen_hash {
+        ($($t:ty),*) => {
+            $(impl FrozenHash for $t {
+                fn frozen_hash(&self, hasher: &mut DefaultHasher) {
+                    self.hash(hasher);
+                }
+            })*
+        };
+    }
+
+    frozen_hash!(
+        (),
+        bool,
+        char,
+        i8,
+        i16,
+        i32,
+        i64,
+        i128,
+        isize,
+        u8,
+        u16,
+        u32,
+        u64,
+        u128,
+        usize,
+        String
+    );
+
+    impl<T: FrozenHash> FrozenHash for Vec<T> {
+        fn frozen_hash(&self, hasher: &mut DefaultHasher) {
+            self.len().hash(hasher);
+            self.iter().for_each(|v| v.frozen_hash(hasher));
+        }
+    }
+
+    impl<T: FrozenHash, const N: usize> FrozenHash for [T; N] {
+        fn frozen_hash(&self, hasher: &mut DefaultHasher) {
+            self.iter().for_each(|v| v.frozen_hash(hasher));
+        }
+    }
+
+    impl<T: FrozenHash> FrozenHash for Box<T> {
+        fn frozen_hash(&self, hasher: &mut DefaultHasher) {
+            (**self).frozen_hash(hasher);
+        }
+    }
+
+    impl<T: FrozenHash> FrozenHash for Option<T> {
+        fn frozen_hash(&self, hasher: &mut DefaultHasher) {
+            self.is_some().hash(hasher);
+            if let Some(v) = self {
+                v.frozen_hash(hasher);
+            }
+        }
+    }
+
+    macro_rules! frozen_hash_tuple {
+        ($($t:ident $i:tt),*) => {
+            impl<$($t: FrozenHash),*> FrozenHash for ($($t,)*) {
+                fn frozen_hash(&self, hasher: &mut DefaultHasher) {
+                    $(self.$i.frozen_hash(hasher);)*
+                }
+            }
+        };
+    }
+
+    frozen_hash_tuple!(A 0);
+    frozen_hash_tuple!(A 0, B 1);
+    frozen_hash_tuple!(A 0, B 1, C 2);
+    frozen_hash_tuple!(A 0, B 1, C 2, D 3);
+
+    impl<T: FrozenHash> Fingerprint for T {
+        fn fingerprint(&self) -> Option<u64> {
+            let mut hasher = DefaultHasher::new();
+            self.frozen_hash(&mut hasher);
+            Some(hasher.finish())
+        }
+    }
+
+    pub(super) fn record<T>(value: &T) {
+        if let Some(hash) = value.fingerprint() {
+            let addr = value as *const T as usize;
+            HASHES
+                .lock()
+                .unwrap()
+                .get_or_insert_with(HashMap::new)
+                .insert(addr, hash);
+        }
+    }
+
+    #[track_caller]
+    pub(super) fn verify<T>(value: &T) {
+        let hash = match value.fingerprint() {
+            Some(hash) => hash,
+            None => return,
+        };
+        let addr = value as *const T as usize;
+        let expected = HASHES
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|h| h.get(&addr).copied());
+        if expected.map_or(false, |e| e != hash) {
+            panic!(
+                "frozen {} at {:#x} has been written to",
+                std::any::type_name::<T>(),
+                addr
+            );
+        }
+    }
+}
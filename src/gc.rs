@@ -1,15 +1,24 @@
 use std::{
-    alloc::{Allocator, Layout},
+    alloc::Layout,
     any::Any,
+    cell::{Cell, UnsafeCell},
+    error::Error,
     fmt,
     hash::{Hash, Hasher},
-    marker::{PhantomData, Unsize},
-    mem::{forget, ManuallyDrop, MaybeUninit},
+    marker::{Freeze, PhantomData, Unsize},
+    mem::{ManuallyDrop, MaybeUninit},
     ops::{CoerceUnsized, Deref, DispatchFromDyn},
-    ptr::NonNull,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-use crate::ALLOCATOR;
+use crate::{
+    alloc::{self, GcAlloc, NoGcPointers},
+    frozen::Frozen,
+    pin,
+    quota::{self, QuotaExceeded},
+    ALLOCATOR,
+};
 
 /// This is usually a no-op, but if `gc_stats` is enabled it will setup the GC
 /// for profiliing.
@@ -18,6 +27,59 @@ pub fn gc_init() {
     boehm::init();
 }
 
+/// Force a full collection.
+///
+/// Unlike calling `GcAllocator::force_gc` directly, this first hands any
+/// batched finalizer registrations (see `GcConfig::finalizer_batching`) on
+/// this thread to the collector, and, in `GcConfig::deterministic_test_mode`,
+/// runs the finalizers of any objects found to be unreachable. Other threads
+/// hand over their batched registrations at their next allocation, so they
+/// are seen by the collection after.
+///
+/// This and `collect_slice` are the only ways to collect which flush batched
+/// registrations: collections started by the allocator itself, or through
+/// `GcAllocator`, leave them buffered.
+pub fn collect() {
+    crate::alloc::flush_all_finalizers();
+    crate::GcAllocator::force_gc();
+    #[cfg(feature = "standalone")]
+    if crate::config::deterministic() {
+        crate::GcAllocator::invoke_finalizers();
+    }
+}
+
+/// Do one slice of collection work, starting a collection if none is in
+/// progress and the collector thinks one is due. Returns true if a
+/// collection is still in progress, so that an event loop can keep calling
+/// this while it is idle. Slices are bounded by the time limit of
+/// `CollectionMode::Incremental`; in other modes, a collection runs to
+/// completion.
+#[cfg(feature = "standalone")]
+pub fn collect_slice() -> bool {
+    crate::alloc::flush_all_finalizers();
+    let in_progress = crate::GcAllocator::collect_a_little();
+    if crate::config::deterministic() {
+        crate::GcAllocator::invoke_finalizers();
+    }
+    in_progress
+}
+
+static HEAP_TORN_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Record that the heap has been torn down, e.g. at the start of process
+/// shutdown. From then on, `Gc::try_deref` returns `None`, and with the
+/// `teardown_checks` feature, dereferencing a `Gc` panics, rather than stale
+/// copies (in atexit handlers or detached threads, say) reading memory which
+/// may no longer be valid.
+pub fn teardown() {
+    HEAP_TORN_DOWN.store(true, Ordering::Release);
+}
+
+#[inline]
+pub fn is_torn_down() -> bool {
+    HEAP_TORN_DOWN.load(Ordering::Acquire)
+}
+
 /// A garbage collected pointer.
 ///
 /// The type `Gc<T>` provides shared ownership of a value of type `T`,
@@ -41,8 +103,8 @@ pub fn gc_init() {
 ///
 /// `Gc<T>` will implement `Sync` as long as `T` implements `Sync`. `Gc<T>`
 /// will always implement `Send` because it requires `T` to implement `Send`.
-/// This is because if `T` has a finalizer, it will be run on a seperate thread.
-#[derive(PartialEq, Eq)]
+/// This is because if `T` has a finalizer, it may be run on another thread
+/// (e.g. a `finalize::FinalizerThread`).
 pub struct Gc<T: ?Sized + Send> {
     ptr: GcPointer<T>,
     _phantom: PhantomData<T>,
@@ -57,6 +119,15 @@ struct GcPointer<T: ?Sized>(NonNull<GcBox<T>>);
 unsafe impl<T> Send for GcPointer<T> {}
 unsafe impl<T> Sync for GcPointer<T> {}
 
+/// Allocate a value as a `Gc` trait object, e.g. `gc_dyn!(dyn Any + Send,
+/// 1usize)` for a `Gc<dyn Any + Send>`. See `Gc::new_unsized`.
+#[macro_export]
+macro_rules! gc_dyn {
+    ($ty:ty, $value:expr) => {
+        $crate::Gc::<$ty>::new_unsized($value)
+    };
+}
+
 impl<T: ?Sized + Unsize<U> + Send, U: ?Sized + Send> CoerceUnsized<Gc<U>> for Gc<T> {}
 impl<T: ?Sized + Unsize<U> + Send, U: ?Sized + Send> DispatchFromDyn<Gc<U>> for Gc<T> {}
 
@@ -67,14 +138,185 @@ impl<T: ?Sized + Unsize<U> + Send, U: ?Sized + Send> DispatchFromDyn<GcPointer<U
 }
 
 impl<T: Send> Gc<T> {
-    /// Constructs a new `Gc<T>`.
+    /// Constructs a new `Gc<T>`. For plain data, this is a single call to
+    /// the collector; see `allocates_atomically`.
     pub fn new(v: T) -> Self {
+        Gc::new_in(v, &ALLOCATOR)
+    }
+
+    /// Constructs a new `Gc<T>` whose memory is allocated, and whose
+    /// finalizer is registered, through `alloc` rather than the global GC
+    /// allocator.
+    pub fn new_in<A: GcAlloc + ?Sized>(v: T, alloc: &A) -> Self {
+        quota::charge(Layout::new::<T>().size());
+        Gc::from_inner(GcBox::alloc_init(v, alloc))
+    }
+
+    /// Constructs a new `Gc<T>` whose value is dropped in topological
+    /// order. Ordinarily, when several objects become unreachable together,
+    /// their finalizers run in no particular order, so a `Drop` impl which
+    /// reads a `Gc` field may find its referent already dropped. With an
+    /// ordered finalizer, everything reachable from the value is kept alive
+    /// until the value has been dropped, and is only finalized by a later
+    /// collection.
+    ///
+    /// The price is that an object which is part of a cycle through other
+    /// objects with ordered finalizers is never finalized (or freed). A
+    /// value pointing to itself is fine.
+    #[cfg(feature = "standalone")]
+    pub fn new_with_ordered_finalizer(v: T) -> Self {
+        quota::charge(Layout::new::<T>().size());
+        Gc::from_inner(GcBox::alloc_init_ordered(v, &ALLOCATOR))
+    }
+
+    /// Constructs a new `Gc<T>` for a value which refers to itself. `f` is
+    /// given a `Gc` to the allocation before the value is put there, and
+    /// returns the value, which may hold copies of that `Gc` (directly or
+    /// through other objects), e.g. for a node in a circular list.
+    ///
+    /// The copies are `Gc<MaybeUninit<T>>`s, as the value doesn't exist while
+    /// `f` runs. Once `new_cyclic` has returned, they point to the same
+    /// initialized value as the `Gc<T>` it returns, so can be read with
+    /// `MaybeUninit::assume_init_ref`. If `f` panics, the allocation is left
+    /// uninitialized and is collected without being dropped.
+    pub fn new_cyclic<F: FnOnce(&Gc<MaybeUninit<T>>) -> T>(f: F) -> Self {
+        quota::charge(Layout::new::<T>().size());
+        let ptr = GcBox::<T>::alloc_block(&ALLOCATOR);
+        let uninit =
+            Gc::from_inner(unsafe { NonNull::new_unchecked(ptr as *mut GcBox<MaybeUninit<T>>) });
+        let v = f(&uninit);
+        unsafe {
+            ptr::write(ptr, GcBox(ManuallyDrop::new(v)));
+            GcBox::register_finalizer(&mut *ptr, &ALLOCATOR);
+            Gc::from_inner(NonNull::new_unchecked(ptr))
+        }
+    }
+
+    /// A `Gc<T>` which doesn't point to a value, for use as a sentinel (e.g.
+    /// for empty slots in an object table). It must not be dereferenced,
+    /// but can be compared with `ptr_eq`. All dangling `Gc<T>`s are equal.
+    pub const fn dangling() -> Self {
         Gc {
-            ptr: unsafe { GcPointer(NonNull::new_unchecked(GcBox::new(v))) },
+            ptr: GcPointer(NonNull::dangling()),
             _phantom: PhantomData,
         }
     }
 
+    /// Constructs a new `Gc<T>` for a value which will rarely be accessed.
+    /// It is allocated in a separate region of the heap (see
+    /// `GcConfig::cold_region`), leaving more room in cache for frequently
+    /// accessed objects.
+    pub fn new_cold(v: T) -> Self {
+        Gc::new_in(v, &crate::cold::ColdAlloc)
+    }
+
+    /// Mark the value as no longer mutated. The returned `Gc` is the same
+    /// object, but only allows shared access to it. See `frozen`.
+    ///
+    /// Any other copies of `self` still work, but can't mutate the value
+    /// either, as `T` has no interior mutability.
+    pub fn freeze(self) -> Gc<Frozen<T>>
+    where
+        T: Freeze,
+    {
+        crate::frozen::frozen::<T>(&self);
+        Gc::from_raw(Gc::into_raw(self) as *const Frozen<T>)
+    }
+
+    /// Copy the value into a read-only region outside the GC heap, which is
+    /// never scanned or freed, and return a reference to the copy. This is
+    /// for large tables which live as long as the program (e.g. Unicode
+    /// data), which would otherwise be marked by every collection.
+    ///
+    /// Only pointer-free values can be moved there, as the collector can't
+    /// see any `Gc`s the copy holds. `self` stays on the GC heap until it is
+    /// unreachable. Each value is given whole pages, so small values are
+    /// better left where they are.
+    pub fn into_shared_readonly(self) -> &'static T
+    where
+        T: Copy + NoGcPointers + Sync,
+    {
+        let layout = Layout::new::<T>();
+        unsafe { &*(crate::readonly::copy(Gc::as_ptr(&self) as *const u8, layout) as *const T) }
+    }
+
+    /// Register `f` to be called with the value and `data` once `this` is
+    /// unreachable, before the value is dropped. `data` is itself kept on
+    /// the GC heap, so any `Gc`s it holds stay alive until `f` has run.
+    ///
+    /// This replaces any finalizer registered earlier with this function,
+    /// whose data is then dropped without its function being called.
+    pub fn register_finalizer_with<D: Send>(this: &Self, data: D, f: fn(&mut T, D)) {
+        struct Pending<T, D> {
+            f: fn(&mut T, D),
+            data: Cell<Option<D>>,
+        }
+
+        unsafe extern "C" fn fshim<T, D>(obj: *mut u8, pending: *mut u8) {
+            #[cfg(feature = "stats_profiling")]
+            let start = std::time::Instant::now();
+
+            let pending = &*(pending as *const Pending<T, D>);
+            let ty = std::any::type_name::<T>();
+            if !crate::alloc::catch_finalizer_panic(ty, || {
+                let value = obj as *mut T;
+                if let Some(data) = pending.data.take() {
+                    (pending.f)(&mut *value, data);
+                }
+                if GcBox::<T>::needs_finalizer() {
+                    ptr::drop_in_place(value);
+                }
+            }) {
+                return;
+            }
+
+            #[cfg(feature = "stats_counters")]
+            crate::stats::NUM_FINALIZERS_RUN.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            #[cfg(feature = "stats_profiling")]
+            crate::stats::record_finalizer(ty, start.elapsed());
+        }
+
+        #[cfg(feature = "stats_counters")]
+        if !GcBox::<T>::needs_finalizer() {
+            crate::stats::NUM_REGISTERED_FINALIZERS
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        // The collector treats client data as a root until the finalizer has
+        // run, so `pending` can't be freed before it is used.
+        let pending = Gc::new(Pending {
+            f,
+            data: Cell::new(Some(data)),
+        });
+        unsafe {
+            GcAlloc::register_finalizer(
+                &ALLOCATOR,
+                this.ptr.0.as_ptr() as *mut u8,
+                Some(fshim::<T, D>),
+                Gc::into_raw(pending) as *mut u8,
+            )
+        }
+    }
+
+    /// Constructs a new `Gc<T>`, or fails if doing so would exceed the
+    /// current thread's allocation quota (see `Quota`). `v` is dropped on
+    /// failure.
+    pub fn try_new(v: T) -> Result<Self, QuotaExceeded> {
+        quota::try_charge(Layout::new::<T>().size())?;
+        Ok(Gc::from_inner(GcBox::alloc_init(v, &ALLOCATOR)))
+    }
+
+    /// Constructs a new `Gc<T>` which will not be collected, even once
+    /// unreachable, until it is handed back to the collector with
+    /// `Gc::release`. The value is still traced, so anything it references is
+    /// kept alive too. This is useful for e.g. VM-global tables which are
+    /// only referenced from C.
+    pub fn new_uncollectable(v: T) -> Self {
+        let gc = Gc::new(v);
+        pin::pin(Gc::into_raw(gc) as *const u8);
+        gc
+    }
+
     /// Constructs a new `Gc<MaybeUninit<T>>` which is capable of storing data
     /// up-to the size permissible by `layout`.
     ///
@@ -112,12 +354,105 @@ impl<T: Send> Gc<T> {
         Gc::from_inner(GcBox::new_from_layout(layout))
     }
 
+    /// Constructs a new `Gc<MaybeUninit<T>>` whose contents are all zero
+    /// bytes.
+    ///
+    /// Memory handed out by the collector is already zeroed, so unlike
+    /// `Box::new_zeroed`, no extra pass over the allocation is needed.
+    pub fn new_zeroed() -> Gc<MaybeUninit<T>> {
+        unsafe { Gc::new_from_layout_unchecked(Layout::new::<T>()) }
+    }
+
     pub fn unregister_finalizer(&mut self) {
         let ptr = self.ptr.0.as_ptr() as *mut GcBox<T>;
         unsafe {
             GcBox::unregister_finalizer(&mut *ptr);
         }
     }
+
+    /// Register the finalizer `Gc::new` would have, for a value whose
+    /// finalizer has already been used up, e.g. by resurrecting it.
+    #[cfg(feature = "standalone")]
+    pub(crate) fn register_finalizer(&mut self) {
+        let ptr = self.ptr.0.as_ptr() as *mut GcBox<T>;
+        unsafe {
+            GcBox::register_finalizer(&mut *ptr, &ALLOCATOR);
+        }
+    }
+}
+
+impl<T: Send> Gc<[T]> {
+    /// Constructs a new garbage collected slice holding clones of the
+    /// elements of `s`, in a single block.
+    pub fn from_slice(s: &[T]) -> Self
+    where
+        T: Clone,
+    {
+        Gc::from(s)
+    }
+
+    /// Constructs a new garbage collected slice of `len` elements with
+    /// uninitialized contents. Blocks which are scanned are zeroed by the
+    /// collector anyway, but those for `T: NoGcPointers` may hold anything.
+    ///
+    /// # Panics
+    ///
+    /// If the total size of the slice would overflow `isize::MAX`.
+    pub fn new_slice_uninit(len: usize) -> Gc<[MaybeUninit<T>]> {
+        let layout = Layout::array::<T>(len).unwrap();
+        let base = alloc::alloc_for::<T, _>(&ALLOCATOR, layout).unwrap();
+        let base = base.as_ptr() as *mut MaybeUninit<T>;
+        let ptr = ptr::slice_from_raw_parts_mut(base, len) as *mut GcBox<[MaybeUninit<T>]>;
+        unsafe { Gc::from_inner(NonNull::new_unchecked(ptr)) }
+    }
+
+    /// Constructs a new garbage collected slice of `len` elements whose
+    /// contents are all zero bytes.
+    ///
+    /// # Panics
+    ///
+    /// If the total size of the slice would overflow `isize::MAX`.
+    pub fn new_slice_zeroed(len: usize) -> Gc<[MaybeUninit<T>]> {
+        let gc = Gc::<[T]>::new_slice_uninit(len);
+        if !alloc::needs_scanning::<T>() {
+            let size = Layout::array::<T>(len).unwrap().size();
+            unsafe { ptr::write_bytes(Gc::into_raw(gc) as *mut u8, 0, size) };
+        }
+        gc
+    }
+
+    /// Copy the elements into a read-only region outside the GC heap. See
+    /// `Gc::into_shared_readonly`.
+    pub fn into_shared_readonly(self) -> &'static [T]
+    where
+        T: Copy + NoGcPointers + Sync,
+    {
+        let layout = Layout::for_value::<[T]>(&self);
+        unsafe {
+            let base = crate::readonly::copy(self.as_ptr() as *const u8, layout);
+            &*ptr::slice_from_raw_parts(base as *const T, self.len())
+        }
+    }
+}
+
+/// The contents are allocated without being scanned if `T: NoGcPointers`.
+impl<T: Clone + Send> From<&[T]> for Gc<[T]> {
+    fn from(s: &[T]) -> Self {
+        let gc = Gc::<[T]>::new_slice_uninit(s.len());
+        let base = Gc::into_raw(gc) as *mut MaybeUninit<T>;
+        for (i, v) in s.iter().enumerate() {
+            unsafe { base.add(i).write(MaybeUninit::new(v.clone())) };
+        }
+        unsafe { gc.assume_init() }
+    }
+}
+
+/// The string is allocated in a block which isn't scanned.
+impl From<&str> for Gc<str> {
+    fn from(s: &str) -> Self {
+        let bytes = Gc::<[u8]>::from(s.as_bytes());
+        Gc::from_raw(Gc::into_raw(bytes) as *const str)
+    }
 }
 
 impl Gc<dyn Any + Send> {
@@ -132,21 +467,117 @@ impl Gc<dyn Any + Send> {
 }
 
 #[cfg(feature = "standalone")]
-pub fn needs_finalizer<T>() -> bool {
+pub const fn needs_finalizer<T>() -> bool {
     std::mem::needs_drop::<T>()
 }
 
 #[cfg(not(feature = "standalone"))]
-pub fn needs_finalizer<T>() -> bool {
+pub const fn needs_finalizer<T>() -> bool {
     std::mem::needs_finalizer::<T>()
 }
 
+/// Types whose values are not dropped when they are collected, even though
+/// `needs_finalizer` is true for them. Registering a finalizer costs an FFI
+/// call per allocation and slows collection, which adds up for short-lived
+/// values whose drop glue doesn't matter.
+///
+/// Skipping a drop leaks anything it would have released, such as a
+/// `String`'s buffer (which `GcAllocator` never collects), file handles, or
+/// locks. This is only worthwhile where that is acceptable.
+///
+/// # Safety
+///
+/// The type, and every type it contains, must not rely on being dropped for
+/// soundness (e.g. a value pinned in the `Gc` whose `Drop` impl unregisters
+/// pointers to it).
+#[rustc_unsafe_specialization_marker]
+pub unsafe trait NoFinalize {}
+
+trait Finalize {
+    fn needs_finalizer() -> bool;
+}
+
+impl<T> Finalize for T {
+    #[inline(always)]
+    default fn needs_finalizer() -> bool {
+        needs_finalizer::<T>()
+    }
+}
+
+impl<T: NoFinalize> Finalize for T {
+    #[inline(always)]
+    fn needs_finalizer() -> bool {
+        false
+    }
+}
+
+/// Whether `Gc::new` allocates a `T` with a single call to the collector,
+/// in a block which is never scanned, and without registering a finalizer.
+/// This holds for `NoGcPointers` types which don't need dropping, which
+/// includes every `Copy` type built from numbers, arrays, and tuples.
+pub fn allocates_atomically<T>() -> bool {
+    !alloc::needs_scanning::<T>() && !<T as Finalize>::needs_finalizer()
+}
+
 impl<T: ?Sized + Send> Gc<T> {
-    /// Get a raw pointer to the underlying value `T`.
+    /// Constructs a new `Gc<T>` holding `v`, where `T` is unsized (e.g. a
+    /// trait object). This is the same as coercing the result of `Gc::new`,
+    /// but names the target type up front, which helps inference when
+    /// building heaps of trait objects. See also `gc_dyn!`.
+    pub fn new_unsized<V: Unsize<T> + Send>(v: V) -> Gc<T> {
+        Gc::<V>::new(v)
+    }
+
+    /// Get a raw pointer to the underlying value `T`. As `Gc` is `Copy`, this
+    /// is the same as `as_ptr`.
+    #[inline]
     pub fn into_raw(this: Self) -> *const T {
+        Gc::as_ptr(&this)
+    }
+
+    /// Get a raw pointer to the underlying value `T`, e.g. to store in an
+    /// object table.
+    ///
+    /// The collector never moves objects, so the pointer stays valid, and
+    /// can be turned back into a `Gc` with `from_raw`, for as long as the
+    /// object is reachable. The collector is conservative, so the raw pointer
+    /// itself keeps the object reachable if it is stored somewhere which is
+    /// scanned (e.g. on the stack or in another `Gc`), but not if it is
+    /// hidden (e.g. XORed with a key) or stored in memory the collector
+    /// doesn't know about (e.g. allocated by `malloc`).
+    ///
+    /// This is an associated function, rather than a method, so that it
+    /// doesn't shadow `T`'s own methods (e.g. `Vec::as_ptr`).
+    #[inline]
+    pub fn as_ptr(this: &Self) -> *const T {
         this.ptr.0.as_ptr() as *const T
     }
 
+    /// Allow a value created with `Gc::new_uncollectable` to be collected
+    /// (and finalized) as normal once it is unreachable. Returns false if the
+    /// value was not uncollectable.
+    pub fn release(this: Self) -> bool {
+        pin::unpin(Gc::into_raw(this) as *const u8)
+    }
+
+    pub fn is_uncollectable(this: &Self) -> bool {
+        pin::is_pinned(Gc::into_raw(*this) as *const u8)
+    }
+
+    /// Returns a reference to the value, or `None` if the heap has been torn
+    /// down (see `gc::teardown`).
+    #[inline]
+    #[cfg_attr(feature = "debug_checks", track_caller)]
+    pub fn try_deref(&self) -> Option<&T> {
+        if is_torn_down() {
+            return None;
+        }
+        #[cfg(feature = "debug_checks")]
+        check_ptr(self.ptr.0.as_ptr());
+        Some(unsafe { &*(self.ptr.0.as_ptr() as *const T) })
+    }
+
+    #[inline]
     pub fn ptr_eq(this: &Self, other: &Self) -> bool {
         this.ptr.0.as_ptr() == other.ptr.0.as_ptr()
     }
@@ -160,6 +591,7 @@ impl<T: ?Sized + Send> Gc<T> {
     ///
     /// It is legal for `raw` to be an interior pointer if `T` is valid for the
     /// size and alignment of the originally allocated block.
+    #[inline]
     pub fn from_raw(raw: *const T) -> Gc<T> {
         Gc {
             ptr: unsafe { GcPointer(NonNull::new_unchecked(raw as *mut GcBox<T>)) },
@@ -167,6 +599,7 @@ impl<T: ?Sized + Send> Gc<T> {
         }
     }
 
+    #[inline]
     fn from_inner(ptr: NonNull<GcBox<T>>) -> Self {
         Self {
             ptr: GcPointer(ptr),
@@ -186,6 +619,17 @@ impl<T: Send> Gc<MaybeUninit<T>> {
     }
 }
 
+impl<T: Send> Gc<[MaybeUninit<T>]> {
+    /// As with `Gc::<MaybeUninit<T>>::assume_init`, it is up to the caller to
+    /// guarantee that every element of the slice really is in an initialized
+    /// state.
+    pub unsafe fn assume_init(self) -> Gc<[T]> {
+        let ptr = self.ptr.0.as_ptr() as *mut GcBox<[T]>;
+        (&mut *ptr).register_slice_finalizer();
+        Gc::from_inner(NonNull::new_unchecked(ptr))
+    }
+}
+
 impl<T: ?Sized + fmt::Display + Send> fmt::Display for Gc<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt::Display::fmt(&**self, f)
@@ -198,6 +642,14 @@ impl<T: ?Sized + fmt::Debug + Send> fmt::Debug for Gc<T> {
     }
 }
 
+/// This also makes `?` convert a `Gc` holding an error into a `Box<dyn
+/// Error>`.
+impl<T: ?Sized + Error + Send> Error for Gc<T> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        (**self).source()
+    }
+}
+
 impl<T: ?Sized + Send> fmt::Pointer for Gc<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt::Pointer::fmt(&(&**self as *const T), f)
@@ -211,17 +663,68 @@ impl<T: ?Sized + Send> fmt::Pointer for Gc<T> {
 struct GcBox<T: ?Sized>(ManuallyDrop<T>);
 
 impl<T> GcBox<T> {
-    fn new(value: T) -> *mut GcBox<T> {
-        let layout = Layout::new::<T>();
-        let ptr = ALLOCATOR.allocate(layout).unwrap().as_ptr() as *mut GcBox<T>;
-        let gcbox = GcBox(ManuallyDrop::new(value));
+    /// Folded away once inlined, so that allocating a type which doesn't
+    /// need finalizing compiles down to just the allocation.
+    #[inline(always)]
+    fn needs_finalizer() -> bool {
+        <T as Finalize>::needs_finalizer()
+    }
+
+    /// Allocate a block for `value`, move `value` directly into it, and
+    /// register its finalizer.
+    ///
+    /// The finalizer is registered before the pointer is handed back to the
+    /// caller. Until then, `ptr` is live in this frame, so the block can not
+    /// be found unreachable before its finalizer is in place. Only once the
+    /// value has been written can a finalizer run, so it never sees
+    /// uninitialized memory.
+    fn alloc_init<A: GcAlloc + ?Sized>(value: T, alloc: &A) -> NonNull<GcBox<T>> {
+        let ptr = GcBox::<T>::alloc_block(alloc);
+        unsafe {
+            ptr::write(ptr, GcBox(ManuallyDrop::new(value)));
+            if Self::needs_finalizer() {
+                GcBox::register_finalizer(&mut *ptr, alloc);
+            }
+            NonNull::new_unchecked(ptr)
+        }
+    }
 
+    /// As `alloc_init`, but the finalizer is ordered. See
+    /// `Gc::new_with_ordered_finalizer`.
+    #[cfg(feature = "standalone")]
+    fn alloc_init_ordered<A: GcAlloc + ?Sized>(value: T, alloc: &A) -> NonNull<GcBox<T>> {
+        let ptr = GcBox::<T>::alloc_block(alloc);
         unsafe {
-            ptr.copy_from_nonoverlapping(&gcbox, 1);
-            GcBox::register_finalizer(&mut *ptr);
+            ptr::write(ptr, GcBox(ManuallyDrop::new(value)));
+            if Self::needs_finalizer() && !crate::config::finalizers_disabled() {
+                #[cfg(feature = "stats_counters")]
+                crate::stats::NUM_REGISTERED_FINALIZERS
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                alloc.register_ordered_finalizer(
+                    ptr as *mut u8,
+                    Some(finalize::<T>),
+                    ptr::null_mut(),
+                );
+            }
+            NonNull::new_unchecked(ptr)
         }
+    }
+
+    fn alloc_block<A: GcAlloc + ?Sized>(alloc: &A) -> *mut GcBox<T> {
+        alloc::flush_if_requested();
+        let layout = Layout::new::<T>();
+        let ptr = alloc::alloc_for::<T, A>(alloc, layout).unwrap().as_ptr() as *mut GcBox<T>;
+
+        #[cfg(feature = "stats_counters")]
+        crate::stats::record_allocation(layout.size());
+
+        #[cfg(feature = "gc_flamegraph")]
+        crate::flamegraph::on_alloc();
 
-        forget(gcbox);
+        #[cfg(all(feature = "stats_counters", feature = "standalone"))]
+        crate::stats::record_sample(std::any::type_name::<T>(), ptr as *const u8, layout.size());
+
+        crate::observer::record(layout.size());
         ptr
     }
 
@@ -232,58 +735,154 @@ impl<T> GcBox<T> {
         }
     }
 
-    fn register_finalizer(&mut self) {
-        #[cfg(feature = "gc_stats")]
-        crate::stats::NUM_REGISTERED_FINALIZERS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-
-        if !needs_finalizer::<T>() {
+    fn register_finalizer<A: GcAlloc + ?Sized>(&mut self, alloc: &A) {
+        if !Self::needs_finalizer() || crate::config::finalizers_disabled() {
             return;
         }
 
-        unsafe extern "C" fn fshim<T>(obj: *mut u8, _meta: *mut u8) {
-            ManuallyDrop::drop(&mut *(obj as *mut ManuallyDrop<T>));
-        }
+        #[cfg(feature = "stats_counters")]
+        crate::stats::NUM_REGISTERED_FINALIZERS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
         unsafe {
-            ALLOCATOR.register_finalizer(
+            alloc.register_finalizer(
                 self as *mut _ as *mut u8,
-                Some(fshim::<T>),
-                ::std::ptr::null_mut(),
-                ::std::ptr::null_mut(),
+                Some(finalize::<T>),
                 ::std::ptr::null_mut(),
             )
         }
     }
 
     fn unregister_finalizer(&mut self) {
-        ALLOCATOR.unregister_finalizer(self as *mut _ as *mut u8);
+        GcAlloc::unregister_finalizer(&ALLOCATOR, self as *mut _ as *mut u8);
     }
 }
 
+/// Drop the value in the `GcBox<T>` at `obj`, which is unreachable.
+unsafe extern "C" fn finalize<T>(obj: *mut u8, _meta: *mut u8) {
+    #[cfg(feature = "stats_profiling")]
+    let start = std::time::Instant::now();
+
+    let ty = std::any::type_name::<T>();
+    if !crate::alloc::catch_finalizer_panic(ty, || {
+        ManuallyDrop::drop(&mut *(obj as *mut ManuallyDrop<T>))
+    }) {
+        return;
+    }
+
+    #[cfg(feature = "stats_counters")]
+    crate::stats::NUM_FINALIZERS_RUN.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    #[cfg(feature = "stats_profiling")]
+    crate::stats::record_finalizer(ty, start.elapsed());
+}
+
 impl<T> GcBox<MaybeUninit<T>> {
     unsafe fn assume_init(&mut self) -> NonNull<GcBox<T>> {
         // Now that T is initialized, we must make sure that it's dropped when
         // `GcBox<T>` is freed.
         let init = self as *mut _ as *mut GcBox<T>;
-        GcBox::register_finalizer(&mut *init);
+        GcBox::register_finalizer(&mut *init, &ALLOCATOR);
         NonNull::new_unchecked(init)
     }
 }
 
+impl<T> GcBox<[T]> {
+    fn register_slice_finalizer(&mut self) {
+        if !GcBox::<T>::needs_finalizer() || crate::config::finalizers_disabled() {
+            return;
+        }
+
+        #[cfg(feature = "stats_counters")]
+        crate::stats::NUM_REGISTERED_FINALIZERS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        // The finalizer is only handed a thin pointer to the block, so the
+        // slice length is smuggled through as the client data.
+        unsafe extern "C" fn fshim<T>(obj: *mut u8, len: *mut u8) {
+            #[cfg(feature = "stats_profiling")]
+            let start = std::time::Instant::now();
+
+            let ty = std::any::type_name::<[T]>();
+            if !crate::alloc::catch_finalizer_panic(ty, || {
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(obj as *mut T, len as usize))
+            }) {
+                return;
+            }
+
+            #[cfg(feature = "stats_counters")]
+            crate::stats::NUM_FINALIZERS_RUN.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            #[cfg(feature = "stats_profiling")]
+            crate::stats::record_finalizer(ty, start.elapsed());
+        }
+
+        unsafe {
+            GcAlloc::register_finalizer(
+                &ALLOCATOR,
+                self as *mut _ as *mut u8,
+                Some(fshim::<T>),
+                self.0.len() as *mut u8,
+            )
+        }
+    }
+}
+
 impl<T: ?Sized + Send> Deref for Gc<T> {
     type Target = T;
 
+    #[inline]
+    #[cfg_attr(feature = "debug_checks", track_caller)]
     fn deref(&self) -> &Self::Target {
+        #[cfg(feature = "teardown_checks")]
+        if is_torn_down() {
+            deref_after_teardown();
+        }
+        #[cfg(feature = "debug_checks")]
+        check_ptr(self.ptr.0.as_ptr());
         unsafe { &*(self.ptr.0.as_ptr() as *const T) }
     }
 }
 
+/// Kept out of line so that `deref` inlines to a load, a branch, and a
+/// pointer cast.
+#[cfg(feature = "teardown_checks")]
+#[cold]
+#[inline(never)]
+fn deref_after_teardown() -> ! {
+    panic!("dereferenced a Gc after the heap was torn down")
+}
+
+/// Panic unless `ptr` points to a value wholly inside a block in the GC
+/// heap. This catches `Gc`s made from bogus or corrupted pointers, though not
+/// those to blocks which have been freed and reused.
+#[cfg(feature = "debug_checks")]
+#[track_caller]
+fn check_ptr<T: ?Sized>(ptr: *const T) {
+    let addr = ptr as *const u8 as usize;
+    // Only the pointer's metadata is read to find the size.
+    let size = unsafe { std::mem::size_of_val(&*ptr) };
+    let ok = match crate::GcAllocator::base(addr as *const u8) {
+        Some(base) => {
+            let base = base.as_ptr() as usize;
+            let end = base + unsafe { crate::GcAllocator::block_size(base as *const u8) };
+            addr + size <= end
+        }
+        None => false,
+    };
+    if !ok {
+        panic!(
+            "Gc<{}> at {:#x} doesn't point to a {} byte value in the GC heap",
+            std::any::type_name::<T>(),
+            addr,
+            size
+        );
+    }
+}
+
 /// `Copy` and `Clone` are implemented manually because a reference to `Gc<T>`
 /// should be copyable regardless of `T`. It differs subtly from `#[derive(Copy,
 /// Clone)]` in that the latter only makes `Gc<T>` copyable if `T` is.
 impl<T: ?Sized + Send> Copy for Gc<T> {}
 
 impl<T: ?Sized + Send> Clone for Gc<T> {
+    #[inline]
     fn clone(&self) -> Self {
         *self
     }
@@ -296,6 +895,56 @@ impl<T: ?Sized> Clone for GcPointer<T> {
         *self
     }
 }
+/// Two `Gc`s are equal if their values are equal, i.e. `==` compares values
+/// structurally, not by identity. If `T: Eq + IdentityEq`, pointers to the
+/// same allocation are known to be equal without comparing the values, which
+/// makes comparing e.g. interned symbols cheap. This mirrors `Rc`: types whose
+/// equality isn't reflexive (e.g. `f64`, where `NaN != NaN`) don't implement
+/// `Eq`, and so are always compared by value. Use `Gc::ptr_eq` to compare
+/// identities.
+impl<T: ?Sized + PartialEq + Send> PartialEq for Gc<T> {
+    #[inline]
+    fn eq(&self, other: &Gc<T>) -> bool {
+        GcEqIdent::eq(self, other)
+    }
+}
+
+impl<T: ?Sized + Eq + Send> Eq for Gc<T> {}
+
+/// Types whose `Gc`s may skip comparing their values when both point to the
+/// same object (see `Gc`'s `PartialEq` impl). Types with interior mutability
+/// (i.e. which contain an `UnsafeCell`, like `Cell`, `RefCell`, `Mutex` and
+/// `GcCell`) don't implement it, so that comparing them always runs their
+/// `PartialEq` impl, e.g. for `RefCell` to panic if it is mutably borrowed.
+/// Other types can opt out with `impl !IdentityEq for MyType {}`.
+pub auto trait IdentityEq {}
+
+impl<T: ?Sized> !IdentityEq for UnsafeCell<T> {}
+
+/// `Eq` types which implement `IdentityEq`, as a bound for specialization.
+#[rustc_unsafe_specialization_marker]
+trait MarkerEq: PartialEq<Self> {}
+
+impl<T: ?Sized + Eq + IdentityEq> MarkerEq for T {}
+
+trait GcEqIdent<T: ?Sized + PartialEq + Send> {
+    fn eq(&self, other: &Gc<T>) -> bool;
+}
+
+impl<T: ?Sized + PartialEq + Send> GcEqIdent<T> for Gc<T> {
+    #[inline]
+    default fn eq(&self, other: &Gc<T>) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + MarkerEq + Send> GcEqIdent<T> for Gc<T> {
+    #[inline]
+    fn eq(&self, other: &Gc<T>) -> bool {
+        Gc::ptr_eq(self, other) || **self == **other
+    }
+}
+
 impl<T: ?Sized + Hash + Send> Hash for Gc<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         (**self).hash(state);
@@ -307,6 +956,17 @@ mod test {
     use super::*;
     use std::mem::size_of;
 
+    #[test]
+    fn test_allocates_atomically() {
+        assert!(allocates_atomically::<u8>());
+        assert!(allocates_atomically::<f64>());
+        assert!(allocates_atomically::<(u64, f64)>());
+        assert!(allocates_atomically::<(i32, (bool, char), [u16; 4])>());
+        assert!(!allocates_atomically::<Gc<u64>>());
+        assert!(!allocates_atomically::<(u64, Gc<u64>)>());
+        assert!(!allocates_atomically::<String>());
+    }
+
     #[test]
     #[should_panic]
     fn test_too_small() {
@@ -323,6 +983,45 @@ mod test {
         Gc::<S>::new_from_layout(Layout::from_size_align(size_of::<S>(), 1).unwrap());
     }
 
+    #[test]
+    fn test_new_zeroed() {
+        let gc = unsafe { Gc::<u64>::new_zeroed().assume_init() };
+        assert_eq!(*gc, 0);
+
+        let slice = unsafe { Gc::<[u64]>::new_slice_zeroed(8).assume_init() };
+        assert_eq!(&*slice, &[0; 8]);
+    }
+
+    #[test]
+    fn test_uncollectable() {
+        let gc = Gc::new_uncollectable(1usize);
+        assert!(Gc::is_uncollectable(&gc));
+        assert!(Gc::release(gc));
+        assert!(!Gc::is_uncollectable(&gc));
+        assert!(!Gc::release(gc));
+        assert!(!Gc::release(Gc::new(1usize)));
+    }
+
+    #[test]
+    fn test_eq() {
+        let a = Gc::new(String::from("a"));
+        assert_eq!(a, a);
+        assert_eq!(a, Gc::new(String::from("a")));
+        assert_ne!(a, Gc::new(String::from("b")));
+
+        let nan = Gc::new(f64::NAN);
+        assert_ne!(nan, nan);
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn test_eq_interior_mutability() {
+        // Comparing a `RefCell` with itself still borrows it.
+        let a = Gc::new(std::cell::RefCell::new(1));
+        let _b = a.borrow_mut();
+        let _ = a == a;
+    }
+
     #[test]
     fn test_dispatchable() {
         struct S1 {
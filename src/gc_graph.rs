@@ -0,0 +1,157 @@
+//! Trees and graphs whose nodes point at each other freely.
+//!
+//! With `Rc`, a child can only refer to its parent through a `Weak`, and a
+//! general graph with cycles leaks unless the cycles are broken by hand.
+//! Nodes here hold ordinary `Gc` pointers in every direction, and a tree or
+//! graph is collected as a whole once nothing outside it refers to it.
+use std::{
+    cell::{Cell, Ref, RefCell},
+    collections::{HashSet, VecDeque},
+};
+
+use crate::Gc;
+
+/// A node in a tree, with a pointer to its parent as well as its children.
+pub struct GcTreeNode<T: Send> {
+    value: T,
+    parent: Cell<Option<Gc<GcTreeNode<T>>>>,
+    children: RefCell<Vec<Gc<GcTreeNode<T>>>>,
+}
+
+impl<T: Send> GcTreeNode<T> {
+    pub fn new(value: T) -> Gc<Self> {
+        Gc::new(GcTreeNode {
+            value,
+            parent: Cell::new(None),
+            children: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn parent(&self) -> Option<Gc<Self>> {
+        self.parent.get()
+    }
+
+    pub fn children(&self) -> Ref<'_, [Gc<Self>]> {
+        Ref::map(self.children.borrow(), |c| c.as_slice())
+    }
+
+    /// Append `child` to `this`'s children, first detaching it from its
+    /// current parent, if any.
+    pub fn add_child(this: Gc<Self>, child: Gc<Self>) {
+        GcTreeNode::detach(child);
+        child.parent.set(Some(this));
+        this.children.borrow_mut().push(child);
+    }
+
+    /// Remove `this` from its parent's children.
+    pub fn detach(this: Gc<Self>) {
+        if let Some(parent) = this.parent.take() {
+            parent
+                .children
+                .borrow_mut()
+                .retain(|c| !Gc::ptr_eq(c, &this));
+        }
+    }
+
+    /// Iterate over `this`'s parent, grandparent, and so on up to the root.
+    pub fn ancestors(this: Gc<Self>) -> impl Iterator<Item = Gc<Self>> {
+        std::iter::successors(this.parent(), |n| n.parent())
+    }
+
+    /// Iterate over `this` and its descendants, in pre-order.
+    pub fn descendants(this: Gc<Self>) -> impl Iterator<Item = Gc<Self>> {
+        let mut stack = vec![this];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children().iter().rev());
+            Some(node)
+        })
+    }
+}
+
+/// A node in a directed graph, whose outgoing edges are labelled with an `E`.
+pub struct GcNode<T: Send, E: Send = ()> {
+    value: T,
+    edges: RefCell<Vec<(E, Gc<GcNode<T, E>>)>>,
+}
+
+impl<T: Send, E: Send> GcNode<T, E> {
+    pub fn new(value: T) -> Gc<Self> {
+        Gc::new(GcNode {
+            value,
+            edges: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Add an edge from `self` to `to`. Edges may form cycles, including
+    /// self-loops.
+    pub fn connect(&self, to: Gc<Self>, label: E) {
+        self.edges.borrow_mut().push((label, to));
+    }
+
+    /// Remove every edge from `self` to `to`.
+    pub fn disconnect(&self, to: Gc<Self>) {
+        self.edges.borrow_mut().retain(|(_, n)| !Gc::ptr_eq(n, &to));
+    }
+
+    pub fn edges(&self) -> Ref<'_, [(E, Gc<Self>)]> {
+        Ref::map(self.edges.borrow(), |e| e.as_slice())
+    }
+
+    pub fn successors(&self) -> Vec<Gc<Self>> {
+        self.edges.borrow().iter().map(|(_, n)| *n).collect()
+    }
+
+    /// Iterate over the nodes reachable from `start` (including `start`), in
+    /// depth-first pre-order. Each node is visited once, even if the graph
+    /// has cycles.
+    pub fn dfs(start: Gc<Self>) -> impl Iterator<Item = Gc<Self>> {
+        let mut visited = Visited::default();
+        let mut stack = vec![start];
+        std::iter::from_fn(move || loop {
+            let node = stack.pop()?;
+            if visited.insert(node) {
+                stack.extend(node.successors().into_iter().rev());
+                return Some(node);
+            }
+        })
+    }
+
+    /// Iterate over the nodes reachable from `start` (including `start`), in
+    /// breadth-first order. Each node is visited once, even if the graph has
+    /// cycles.
+    pub fn bfs(start: Gc<Self>) -> impl Iterator<Item = Gc<Self>> {
+        let mut visited = Visited::default();
+        visited.insert(start);
+        let mut queue = VecDeque::from(vec![start]);
+        std::iter::from_fn(move || {
+            let node = queue.pop_front()?;
+            for succ in node.successors() {
+                if visited.insert(succ) {
+                    queue.push_back(succ);
+                }
+            }
+            Some(node)
+        })
+    }
+}
+
+/// The nodes seen by a traversal, by address. The set itself is scanned by
+/// the collector, so a visited node can't be freed, and its address reused,
+/// before the traversal ends.
+#[derive(Default)]
+struct Visited(HashSet<usize>);
+
+impl Visited {
+    fn insert<T: Send, E: Send>(&mut self, node: Gc<GcNode<T, E>>) -> bool {
+        self.0.insert(Gc::into_raw(node) as usize)
+    }
+}
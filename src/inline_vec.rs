@@ -0,0 +1,125 @@
+//! Small vectors which need only one allocation.
+//!
+//! A `Gc<Vec<T>>` is two blocks: the `Vec` and its buffer. For small,
+//! bounded collections such as argument lists, `GcInlineVec` keeps the
+//! length and up to `N` elements in a single block instead.
+use std::{cell::Cell, fmt, mem::MaybeUninit};
+
+use crate::Gc;
+
+/// A vector of at most `N` elements, stored inline.
+///
+/// The block is scanned conservatively. Slots past the end are kept zeroed,
+/// so elements which have been popped don't keep anything alive.
+pub struct GcInlineVec<T: Copy + Send, const N: usize> {
+    len: Cell<usize>,
+    slots: [Cell<MaybeUninit<T>>; N],
+}
+
+impl<T: Copy + Send, const N: usize> GcInlineVec<T, N> {
+    /// Create an empty vector.
+    pub fn new() -> Gc<Self> {
+        // All zeroes is a valid empty vector, and writing it in place
+        // avoids copying stale words from the stack into the block.
+        unsafe { Gc::<Self>::new_zeroed().assume_init() }
+    }
+
+    /// Create a vector holding the elements of `values`.
+    ///
+    /// # Panics
+    ///
+    /// If `values` has more than `N` elements.
+    pub fn from_slice(values: &[T]) -> Gc<Self> {
+        assert!(
+            values.len() <= N,
+            "{} values don't fit in a GcInlineVec of capacity {}",
+            values.len(),
+            N
+        );
+        let v = GcInlineVec::new();
+        for &value in values {
+            let _ = v.push(value);
+        }
+        v
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// Append `value`, or return it if the vector is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let len = self.len();
+        if len == N {
+            return Err(value);
+        }
+        self.slots[len].set(MaybeUninit::new(value));
+        self.len.set(len + 1);
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let len = self.len().checked_sub(1)?;
+        let value = unsafe { self.slots[len].replace(MaybeUninit::zeroed()).assume_init() };
+        self.len.set(len);
+        Some(value)
+    }
+
+    pub fn get(&self, index: usize) -> Option<T> {
+        if index < self.len() {
+            Some(unsafe { self.slots[index].get().assume_init() })
+        } else {
+            None
+        }
+    }
+
+    /// Replace the element at `index` with `value`.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn set(&self, index: usize, value: T) {
+        assert!(
+            index < self.len(),
+            "index {} out of bounds for GcInlineVec of length {}",
+            index,
+            self.len()
+        );
+        self.slots[index].set(MaybeUninit::new(value));
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots[..self.len()] {
+            slot.set(MaybeUninit::zeroed());
+        }
+        self.len.set(0);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.slots[..self.len()]
+            .iter()
+            .map(|s| unsafe { s.get().assume_init() })
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().collect()
+    }
+}
+
+impl<T: Copy + Send + fmt::Debug, const N: usize> fmt::Debug for GcInlineVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,137 @@
+//! Experimental support for running several independent contexts (e.g. script
+//! engines) inside one process.
+//!
+//! All isolates still share the single Boehm heap: an `Isolate` only
+//! namespaces the bookkeeping which sits on top of it. Each isolate owns a
+//! set of roots, keeps its own allocation statistics, and can be torn down
+//! in bulk, at which point everything only reachable from its roots becomes
+//! eligible for finalization.
+use std::{
+    any::Any,
+    mem::size_of_val,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{Gc, GcAllocator};
+
+static NEXT_ISOLATE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A process-unique identifier for an `Isolate`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IsolateId(usize);
+
+/// A snapshot of the allocations made through a single `Isolate`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct IsolateStats {
+    pub num_allocations: usize,
+    pub bytes_allocated: usize, // In bytes
+    pub num_roots: usize,
+}
+
+pub struct Isolate {
+    id: IsolateId,
+    roots: Mutex<Vec<Gc<dyn Any + Send>>>,
+    num_allocations: AtomicUsize,
+    bytes_allocated: AtomicUsize,
+}
+
+impl Isolate {
+    pub fn new() -> Self {
+        Isolate {
+            id: IsolateId(NEXT_ISOLATE_ID.fetch_add(1, Ordering::Relaxed)),
+            roots: Mutex::new(Vec::new()),
+            num_allocations: AtomicUsize::new(0),
+            bytes_allocated: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn id(&self) -> IsolateId {
+        self.id
+    }
+
+    /// Allocate `v` on the GC heap, accounting for it in this isolate's
+    /// statistics.
+    pub fn allocate<T: Send>(&self, v: T) -> Gc<T> {
+        let gc = Gc::new(v);
+        self.num_allocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes_allocated
+            .fetch_add(size_of_val(&*gc), Ordering::Relaxed);
+        gc
+    }
+
+    /// Keep `root` alive for as long as this isolate exists.
+    ///
+    /// The root table lives in memory allocated by `GcAllocator`, which the
+    /// collector scans, so no further registration is needed.
+    pub fn add_root(&self, root: Gc<dyn Any + Send>) {
+        self.roots.lock().unwrap().push(root);
+    }
+
+    /// Stop treating `root` as a root of this isolate. Returns `false` if it
+    /// was never added.
+    pub fn remove_root(&self, root: Gc<dyn Any + Send>) -> bool {
+        let mut roots = self.roots.lock().unwrap();
+        match roots.iter().position(|r| Gc::ptr_eq(r, &root)) {
+            Some(i) => {
+                roots.swap_remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn stats(&self) -> IsolateStats {
+        IsolateStats {
+            num_allocations: self.num_allocations.load(Ordering::Relaxed),
+            bytes_allocated: self.bytes_allocated.load(Ordering::Relaxed),
+            num_roots: self.roots.lock().unwrap().len(),
+        }
+    }
+
+    /// Drop all of this isolate's roots and force a collection, so that
+    /// objects which were only reachable from the isolate are finalized in
+    /// bulk. Objects still referenced from elsewhere (e.g. another isolate)
+    /// are unaffected.
+    pub fn teardown(self) {
+        drop(self.roots.into_inner().unwrap());
+        GcAllocator::force_gc();
+    }
+}
+
+impl Default for Isolate {
+    fn default() -> Self {
+        Isolate::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_isolate_stats() {
+        let i1 = Isolate::new();
+        let i2 = Isolate::new();
+        assert_ne!(i1.id(), i2.id());
+
+        let a = i1.allocate(1u64);
+        i1.allocate(2u32);
+        i1.add_root(a);
+        assert_eq!(
+            i1.stats(),
+            IsolateStats {
+                num_allocations: 2,
+                bytes_allocated: 12,
+                num_roots: 1,
+            }
+        );
+        assert_eq!(i2.stats(), IsolateStats::default());
+
+        assert!(i1.remove_root(a));
+        assert!(!i1.remove_root(a));
+        i1.teardown();
+    }
+}
@@ -5,6 +5,8 @@
 #![feature(alloc_layout_extra)]
 #![feature(arbitrary_self_types)]
 #![feature(dispatch_from_dyn)]
+#![feature(dropck_eyepatch)]
+#![feature(freeze)]
 #![feature(nonnull_slice_from_raw_parts)]
 #![feature(raw_vec_internals)]
 #![feature(const_fn)]
@@ -12,21 +14,95 @@
 #![feature(unsize)]
 #![feature(maybe_uninit_ref)]
 #![feature(negative_impls)]
+#![feature(auto_traits)]
+#![feature(min_specialization)]
+#![feature(rustc_attrs)]
 #![allow(incomplete_features)]
 #![allow(where_clauses_object_safety)]
 #[cfg(not(all(target_pointer_width = "64", target_arch = "x86_64")))]
 compile_error!("Requires x86_64 with 64 bit pointer width.");
+#[cfg(all(
+    any(
+        feature = "crash_handler",
+        feature = "debug_checks",
+        feature = "gc_flamegraph"
+    ),
+    not(feature = "standalone")
+))]
+compile_error!("crash_handler, debug_checks and gc_flamegraph need the standalone feature.");
 
+pub mod alloc;
+pub mod arena;
+pub mod atomic;
+pub mod bytes;
+#[cfg(feature = "standalone")]
+pub mod cache;
+pub mod callback;
+pub mod cell;
+mod cold;
+pub mod config;
+pub mod constant_pool;
+pub mod cow;
+#[cfg(feature = "crash_handler")]
+pub mod crash;
+#[cfg(feature = "standalone")]
+pub mod debug;
+pub mod display;
+#[cfg(feature = "standalone")]
+pub mod dynamic;
+#[cfg(feature = "standalone")]
+pub mod emitter;
+pub mod error;
+pub mod executor;
+#[cfg(feature = "standalone")]
+pub mod finalize;
+#[cfg(feature = "gc_flamegraph")]
+pub mod flamegraph;
+pub mod frozen;
 pub mod gc;
-#[cfg(feature = "gc_stats")]
+pub mod gc_graph;
+pub mod inline_vec;
+pub mod isolate;
+pub mod mailbox;
+mod observer;
+pub mod persistent;
+mod pin;
+#[cfg(feature = "standalone")]
+pub mod pool;
+pub mod prelude;
+#[cfg(feature = "standalone")]
+mod pressure;
+pub mod quota;
+pub mod rc_cycle_probe;
+mod readonly;
+pub mod scope;
+#[cfg(not(feature = "standalone"))]
+pub mod stackmap;
+#[cfg(feature = "stats_counters")]
 pub mod stats;
+pub mod string;
+pub mod tagged;
+#[cfg(feature = "standalone")]
+pub mod thread;
+pub mod transient;
+pub mod value;
+pub mod vec;
+#[cfg(feature = "standalone")]
+pub mod weak;
 
 #[cfg(feature = "standalone")]
-pub use allocator::GcAllocator;
+pub use allocator::{CollectorConfig, GcAllocator, GcEvent, GcStats};
 
+// rustgc's `std::alloc::GcAllocator` only has the collector's core API, so
+// the modules above which need more of it are only built standalone.
 #[cfg(not(feature = "standalone"))]
 pub use std::alloc::GcAllocator;
 
+pub use config::GcConfig;
+#[cfg(feature = "standalone")]
+pub use finalize::run_finalizers;
 pub use gc::Gc;
+#[cfg(feature = "standalone")]
+pub use weak::{GcWeak, WeakGc};
 
 pub static ALLOCATOR: GcAllocator = GcAllocator;
@@ -0,0 +1,204 @@
+//! Passing messages from finalizers to the mutator.
+//!
+//! A finalizer runs at an arbitrary point in some thread's execution, often
+//! in the middle of an allocation, so it must not take locks which the
+//! mutator may hold or touch runtime state which may be half-updated. What
+//! it usually needs is to tell the runtime that something can be cleaned up
+//! (e.g. "external handle 42 can be closed"). A `FinalizerMailbox` carries
+//! such messages: finalizers `post` to it without locking or allocating, and
+//! the runtime `drain`s it at points where it is safe to act on them.
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+struct Slot<T> {
+    /// The position this slot may next be written at (if equal to the tail)
+    /// or read at (if one more than the head).
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A bounded queue of messages from finalizers. Every slot is allocated up
+/// front, so posting never allocates, and never blocks.
+///
+/// The slots are allocated by the global allocator, so when that is
+/// `GcAllocator`, messages may hold `Gc`s, which stay alive until drained.
+pub struct FinalizerMailbox<T: Send> {
+    slots: Box<[Slot<T>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    overflowed: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for FinalizerMailbox<T> {}
+unsafe impl<T: Send> Sync for FinalizerMailbox<T> {}
+
+impl<T: Send> FinalizerMailbox<T> {
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "a FinalizerMailbox needs at least one slot");
+        FinalizerMailbox {
+            slots: (0..capacity)
+                .map(|i| Slot {
+                    seq: AtomicUsize::new(i),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overflowed: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Queue `msg`, or return it if the mailbox is full. This never locks
+    /// or allocates, so is safe to call from a finalizer.
+    pub fn post(&self, msg: T) -> Result<(), T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % self.slots.len()];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match (seq.wrapping_sub(pos) as isize).signum() {
+                0 => match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(msg) };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                },
+                // The slot still holds a message from a lap ago.
+                -1 => {
+                    self.overflowed.fetch_add(1, Ordering::Relaxed);
+                    return Err(msg);
+                }
+                // Another poster took the slot first.
+                _ => pos = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Remove the oldest message, if any.
+    pub fn try_recv(&self) -> Option<T> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % self.slots.len()];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match (seq.wrapping_sub(pos.wrapping_add(1)) as isize).signum() {
+                0 => match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let msg = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.seq
+                            .store(pos.wrapping_add(self.slots.len()), Ordering::Release);
+                        return Some(msg);
+                    }
+                    Err(current) => pos = current,
+                },
+                // Nothing has been posted to the slot yet.
+                -1 => return None,
+                _ => pos = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Call `f` with each queued message, oldest first, returning how many
+    /// there were. Messages posted while draining may or may not be seen.
+    pub fn drain(&self, mut f: impl FnMut(T)) -> usize {
+        let mut n = 0;
+        while let Some(msg) = self.try_recv() {
+            f(msg);
+            n += 1;
+        }
+        n
+    }
+
+    /// The number of messages which couldn't be posted because the mailbox
+    /// was full. A non-zero count means the mailbox is drained too rarely,
+    /// or is too small.
+    pub fn overflowed(&self) -> usize {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Send> Drop for FinalizerMailbox<T> {
+    fn drop(&mut self) {
+        self.drain(drop);
+    }
+}
+
+impl<T: Send> fmt::Debug for FinalizerMailbox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FinalizerMailbox")
+            .field("capacity", &self.capacity())
+            .field("overflowed", &self.overflowed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn posts_in_order_and_overflows() {
+        let mailbox = FinalizerMailbox::with_capacity(4);
+        for lap in 0..3 {
+            for i in 0..4 {
+                assert_eq!(mailbox.post(lap * 4 + i), Ok(()));
+            }
+            assert_eq!(mailbox.post(99), Err(99));
+            let mut got = Vec::new();
+            assert_eq!(mailbox.drain(|m| got.push(m)), 4);
+            assert_eq!(got, (lap * 4..lap * 4 + 4).collect::<Vec<_>>());
+        }
+        assert_eq!(mailbox.overflowed(), 3);
+        assert!(mailbox.try_recv().is_none());
+    }
+
+    #[test]
+    fn concurrent_posters() {
+        let mailbox = Arc::new(FinalizerMailbox::with_capacity(64));
+        let posters = (0..4)
+            .map(|t| {
+                let mailbox = Arc::clone(&mailbox);
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        let mut msg = t * 1000 + i;
+                        while let Err(m) = mailbox.post(msg) {
+                            msg = m;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut got = Vec::new();
+        while got.len() < 4000 {
+            mailbox.drain(|m| got.push(m));
+        }
+        for p in posters {
+            p.join().unwrap();
+        }
+        got.sort_unstable();
+        assert_eq!(got, (0..4000).collect::<Vec<_>>());
+    }
+}
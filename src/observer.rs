@@ -0,0 +1,55 @@
+//! Reporting allocations to the embedder. See `GcConfig::alloc_observer`.
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// The number of bytes a thread allocates before they are reported.
+pub(crate) const BATCH_BYTES: usize = 64 * 1024;
+
+type Observer = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// Checked before the thread-local count is touched, so that allocation
+/// costs one relaxed load when there is no observer.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static OBSERVER: RwLock<Option<Observer>> = RwLock::new(None);
+
+thread_local! {
+    static UNREPORTED: Cell<usize> = Cell::new(0);
+}
+
+pub(crate) fn set(observer: Option<Observer>) {
+    ENABLED.store(observer.is_some(), Ordering::Relaxed);
+    *OBSERVER.write().unwrap() = observer;
+}
+
+/// Account for `bytes` allocated by the current thread.
+#[inline]
+pub(crate) fn record(bytes: usize) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    // The thread's count may already be gone if it is exiting.
+    let _ = UNREPORTED.try_with(|u| {
+        let total = u.get() + bytes;
+        if total < BATCH_BYTES {
+            u.set(total);
+        } else {
+            // Reset first, as the observer may itself allocate.
+            u.set(0);
+            report(total);
+        }
+    });
+}
+
+#[cold]
+fn report(bytes: usize) {
+    // The observer is cloned out so that it may replace itself.
+    let observer = OBSERVER.read().unwrap().clone();
+    if let Some(observer) = observer {
+        observer(bytes);
+    }
+}
@@ -0,0 +1,18 @@
+//! Persistent (immutable, structurally shared) collections.
+//!
+//! Updating a persistent collection returns a new version and leaves the old
+//! one untouched. Both versions share every node the update didn't need to
+//! copy. Nodes are GC allocated, so versions are `Copy` and can be kept,
+//! dropped, or stored in cyclic structures freely: unreachable nodes are
+//! reclaimed by the collector.
+mod map;
+mod vec;
+
+pub use map::GcPersistentMap;
+pub use vec::GcPersistentVec;
+
+/// The number of hash (or index) bits consumed by each level of a trie.
+const BITS: u32 = 5;
+/// The number of children of each trie node.
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
@@ -0,0 +1,366 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    iter::FromIterator,
+    slice,
+};
+
+use super::{BITS, MASK};
+use crate::Gc;
+
+/// A persistent hash map, stored as a hash array mapped trie (HAMT).
+///
+/// Each branch node stores only the children it has, indexed by a 32 bit
+/// bitmap, so sparse levels stay small. `insert` and `remove` copy only the
+/// nodes on the path to the key they change.
+pub struct GcPersistentMap<K: Hash + Eq + Clone + Send, V: Clone + Send> {
+    root: Option<Gc<Node<K, V>>>,
+    len: usize,
+}
+
+#[derive(Clone)]
+enum Node<K: Clone + Send, V: Clone + Send> {
+    /// `bitmap` has a bit set for each 5 bit hash fragment present at this
+    /// level, and `entries` holds them in fragment order.
+    Branch {
+        bitmap: u32,
+        entries: Vec<Entry<K, V>>,
+    },
+    /// Keys whose hashes are equal.
+    Collision { hash: u64, entries: Vec<(K, V)> },
+}
+
+#[derive(Clone)]
+enum Entry<K: Clone + Send, V: Clone + Send> {
+    Leaf { hash: u64, key: K, value: V },
+    Node(Gc<Node<K, V>>),
+}
+
+fn hash_of<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn fragment(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) as usize & MASK)
+}
+
+impl<K: Hash + Eq + Clone + Send, V: Clone + Send> GcPersistentMap<K, V> {
+    pub fn new() -> Self {
+        GcPersistentMap { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let hash = hash_of(key);
+        let mut node = self.root.as_deref()?;
+        let mut shift = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, entries } => {
+                    let bit = fragment(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match &entries[(bitmap & (bit - 1)).count_ones() as usize] {
+                        Entry::Leaf { key: k, value, .. } => {
+                            return if k == key { Some(value) } else { None };
+                        }
+                        Entry::Node(child) => node = child,
+                    }
+                    shift += BITS;
+                }
+                Node::Collision { entries, .. } => {
+                    return entries.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+                }
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns a new version with `key` mapped to `value`.
+    pub fn insert(&self, key: K, value: V) -> Self {
+        let hash = hash_of(&key);
+        let (root, added) = match self.root.as_deref() {
+            Some(root) => insert(root, 0, hash, key, value),
+            None => {
+                let root = Node::Branch {
+                    bitmap: fragment(hash, 0),
+                    entries: vec![Entry::Leaf { hash, key, value }],
+                };
+                (Gc::new(root), true)
+            }
+        };
+        GcPersistentMap {
+            root: Some(root),
+            len: self.len + added as usize,
+        }
+    }
+
+    /// Returns a new version without `key`. If `key` isn't present, this
+    /// returns a copy of `self`.
+    pub fn remove(&self, key: &K) -> Self {
+        let root = match self.root.as_deref() {
+            Some(root) => root,
+            None => return *self,
+        };
+        match remove(root, 0, hash_of(key), key) {
+            Some(root) => GcPersistentMap {
+                root,
+                len: self.len - 1,
+            },
+            None => *self,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            stack: self.root.as_deref().map(Node::iter).into_iter().collect(),
+        }
+    }
+}
+
+/// Returns a copy of `node` with `key` mapped to `value`, and whether `key`
+/// is new.
+fn insert<K: Eq + Clone + Send, V: Clone + Send>(
+    node: &Node<K, V>,
+    shift: u32,
+    hash: u64,
+    key: K,
+    value: V,
+) -> (Gc<Node<K, V>>, bool) {
+    match node {
+        Node::Branch { bitmap, entries } => {
+            let bit = fragment(hash, shift);
+            let idx = (bitmap & (bit - 1)).count_ones() as usize;
+            let mut entries = entries.clone();
+            let mut added = true;
+            if bitmap & bit == 0 {
+                entries.insert(idx, Entry::Leaf { hash, key, value });
+            } else {
+                entries[idx] = match &entries[idx] {
+                    Entry::Leaf { key: k, .. } if *k == key => {
+                        added = false;
+                        Entry::Leaf { hash, key, value }
+                    }
+                    Entry::Leaf {
+                        hash: h,
+                        key: k,
+                        value: v,
+                    } => Entry::Node(merge(
+                        shift + BITS,
+                        (*h, k.clone(), v.clone()),
+                        (hash, key, value),
+                    )),
+                    Entry::Node(child) => {
+                        let (child, a) = insert(child, shift + BITS, hash, key, value);
+                        added = a;
+                        Entry::Node(child)
+                    }
+                };
+            }
+            let node = Node::Branch {
+                bitmap: bitmap | bit,
+                entries,
+            };
+            (Gc::new(node), added)
+        }
+        Node::Collision { hash: h, entries } if *h == hash => {
+            let mut entries = entries.clone();
+            let added = match entries.iter().position(|(k, _)| *k == key) {
+                Some(i) => {
+                    entries[i] = (key, value);
+                    false
+                }
+                None => {
+                    entries.push((key, value));
+                    true
+                }
+            };
+            (Gc::new(Node::Collision { hash, entries }), added)
+        }
+        Node::Collision { hash: h, .. } => {
+            // The new key's hash differs, so the collision node moves down a
+            // level below a branch which can tell them apart.
+            let branch = Node::Branch {
+                bitmap: fragment(*h, shift),
+                entries: vec![Entry::Node(Gc::new(node.clone()))],
+            };
+            insert(&branch, shift, hash, key, value)
+        }
+    }
+}
+
+/// Create a node holding two leaves whose hashes agree below `shift`.
+fn merge<K: Clone + Send, V: Clone + Send>(
+    shift: u32,
+    (h1, k1, v1): (u64, K, V),
+    (h2, k2, v2): (u64, K, V),
+) -> Gc<Node<K, V>> {
+    if h1 == h2 {
+        return Gc::new(Node::Collision {
+            hash: h1,
+            entries: vec![(k1, v1), (k2, v2)],
+        });
+    }
+    let (b1, b2) = (fragment(h1, shift), fragment(h2, shift));
+    let entries = if b1 == b2 {
+        vec![Entry::Node(merge(shift + BITS, (h1, k1, v1), (h2, k2, v2)))]
+    } else {
+        let l1 = Entry::Leaf {
+            hash: h1,
+            key: k1,
+            value: v1,
+        };
+        let l2 = Entry::Leaf {
+            hash: h2,
+            key: k2,
+            value: v2,
+        };
+        if b1 < b2 {
+            vec![l1, l2]
+        } else {
+            vec![l2, l1]
+        }
+    };
+    Gc::new(Node::Branch {
+        bitmap: b1 | b2,
+        entries,
+    })
+}
+
+/// Returns `None` if `key` isn't in `node`. Otherwise, returns a copy of
+/// `node` without `key`, or `Some(None)` if that would be empty.
+fn remove<K: Eq + Clone + Send, V: Clone + Send>(
+    node: &Node<K, V>,
+    shift: u32,
+    hash: u64,
+    key: &K,
+) -> Option<Option<Gc<Node<K, V>>>> {
+    match node {
+        Node::Branch { bitmap, entries } => {
+            let bit = fragment(hash, shift);
+            if bitmap & bit == 0 {
+                return None;
+            }
+            let idx = (bitmap & (bit - 1)).count_ones() as usize;
+            let child = match &entries[idx] {
+                Entry::Leaf { key: k, .. } if k == key => None,
+                Entry::Leaf { .. } => return None,
+                Entry::Node(child) => remove(child, shift + BITS, hash, key)?,
+            };
+            let mut entries = entries.clone();
+            let mut bitmap = *bitmap;
+            match child {
+                Some(child) => entries[idx] = Entry::Node(child),
+                None => {
+                    entries.remove(idx);
+                    bitmap &= !bit;
+                }
+            }
+            if entries.is_empty() {
+                return Some(None);
+            }
+            Some(Some(Gc::new(Node::Branch { bitmap, entries })))
+        }
+        Node::Collision { hash, entries } => {
+            let i = entries.iter().position(|(k, _)| k == key)?;
+            let mut entries = entries.clone();
+            entries.remove(i);
+            if entries.is_empty() {
+                return Some(None);
+            }
+            let hash = *hash;
+            Some(Some(Gc::new(Node::Collision { hash, entries })))
+        }
+    }
+}
+
+impl<K: Clone + Send, V: Clone + Send> Node<K, V> {
+    fn iter(&self) -> NodeIter<'_, K, V> {
+        match self {
+            Node::Branch { entries, .. } => NodeIter::Branch(entries.iter()),
+            Node::Collision { entries, .. } => NodeIter::Collision(entries.iter()),
+        }
+    }
+}
+
+enum NodeIter<'a, K: Clone + Send, V: Clone + Send> {
+    Branch(slice::Iter<'a, Entry<K, V>>),
+    Collision(slice::Iter<'a, (K, V)>),
+}
+
+/// An iterator over the entries of a `GcPersistentMap`, in an unspecified
+/// order.
+pub struct Iter<'a, K: Clone + Send, V: Clone + Send> {
+    stack: Vec<NodeIter<'a, K, V>>,
+}
+
+impl<'a, K: Clone + Send, V: Clone + Send> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let next = match self.stack.last_mut()? {
+                NodeIter::Branch(entries) => match entries.next() {
+                    Some(Entry::Leaf { key, value, .. }) => return Some((key, value)),
+                    Some(Entry::Node(child)) => Some(child.iter()),
+                    None => None,
+                },
+                NodeIter::Collision(entries) => match entries.next() {
+                    Some((key, value)) => return Some((key, value)),
+                    None => None,
+                },
+            };
+            match next {
+                Some(iter) => self.stack.push(iter),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + Send, V: Clone + Send> Default for GcPersistentMap<K, V> {
+    fn default() -> Self {
+        GcPersistentMap::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone + Send, V: Clone + Send> Copy for GcPersistentMap<K, V> {}
+
+impl<K: Hash + Eq + Clone + Send, V: Clone + Send> Clone for GcPersistentMap<K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K: Hash + Eq + Clone + Send, V: Clone + Send> FromIterator<(K, V)> for GcPersistentMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(GcPersistentMap::new(), |m, (k, v)| m.insert(k, v))
+    }
+}
+
+impl<K, V> fmt::Debug for GcPersistentMap<K, V>
+where
+    K: Hash + Eq + Clone + Send + fmt::Debug,
+    V: Clone + Send + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
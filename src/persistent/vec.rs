@@ -0,0 +1,155 @@
+use std::{fmt, iter::FromIterator, ops::Index};
+
+use super::{BITS, MASK, WIDTH};
+use crate::Gc;
+
+/// A persistent vector, stored as a 32-way trie.
+///
+/// `get`, `set`, and `push` take O(log32 n) time, and `set` and `push` copy
+/// only the nodes on the path to the element they change.
+pub struct GcPersistentVec<T: Clone + Send> {
+    root: Option<Gc<Node<T>>>,
+    len: usize,
+    /// The shift of the root node: 0 if it's a leaf.
+    shift: u32,
+}
+
+#[derive(Clone)]
+enum Node<T: Clone + Send> {
+    Branch([Option<Gc<Node<T>>>; WIDTH]),
+    Leaf([Option<T>; WIDTH]),
+}
+
+impl<T: Clone + Send> GcPersistentVec<T> {
+    pub fn new() -> Self {
+        GcPersistentVec {
+            root: None,
+            len: 0,
+            shift: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx >= self.len {
+            return None;
+        }
+        let mut node = self.root.as_deref()?;
+        let mut shift = self.shift;
+        loop {
+            match node {
+                Node::Branch(children) => {
+                    node = children[(idx >> shift) & MASK].as_deref()?;
+                    shift -= BITS;
+                }
+                Node::Leaf(elems) => return elems[idx & MASK].as_ref(),
+            }
+        }
+    }
+
+    /// Returns a new version with the element at `idx` replaced by `v`.
+    ///
+    /// # Panics
+    ///
+    /// If `idx` is out of bounds.
+    pub fn set(&self, idx: usize, v: T) -> Self {
+        assert!(
+            idx < self.len,
+            "index {} out of bounds for length {}",
+            idx,
+            self.len
+        );
+        GcPersistentVec {
+            root: Some(assoc(self.root.as_deref(), self.shift, idx, v)),
+            ..*self
+        }
+    }
+
+    /// Returns a new version with `v` appended.
+    pub fn push(&self, v: T) -> Self {
+        let mut root = self.root;
+        let mut shift = self.shift;
+        if self.len == 1 << (shift + BITS) {
+            // The trie is full, so it grows a level.
+            let mut children = [None; WIDTH];
+            children[0] = root;
+            root = Some(Gc::new(Node::Branch(children)));
+            shift += BITS;
+        }
+        GcPersistentVec {
+            root: Some(assoc(root.as_deref(), shift, self.len, v)),
+            len: self.len + 1,
+            shift,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        (0..self.len).map(move |i| self.get(i).unwrap())
+    }
+}
+
+/// Returns a copy of `node` (or a new node, if `None`) with the element at
+/// `idx` set to `v`.
+fn assoc<T: Clone + Send>(node: Option<&Node<T>>, shift: u32, idx: usize, v: T) -> Gc<Node<T>> {
+    let slot = (idx >> shift) & MASK;
+    if shift == 0 {
+        let mut elems = match node {
+            Some(Node::Leaf(elems)) => elems.clone(),
+            _ => Default::default(),
+        };
+        elems[slot] = Some(v);
+        Gc::new(Node::Leaf(elems))
+    } else {
+        let mut children = match node {
+            Some(Node::Branch(children)) => *children,
+            _ => [None; WIDTH],
+        };
+        children[slot] = Some(assoc(children[slot].as_deref(), shift - BITS, idx, v));
+        Gc::new(Node::Branch(children))
+    }
+}
+
+impl<T: Clone + Send> Default for GcPersistentVec<T> {
+    fn default() -> Self {
+        GcPersistentVec::new()
+    }
+}
+
+impl<T: Clone + Send> Copy for GcPersistentVec<T> {}
+
+impl<T: Clone + Send> Clone for GcPersistentVec<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Clone + Send> Index<usize> for GcPersistentVec<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        match self.get(idx) {
+            Some(v) => v,
+            None => panic!("index {} out of bounds for length {}", idx, self.len),
+        }
+    }
+}
+
+impl<T: Clone + Send> FromIterator<T> for GcPersistentVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(GcPersistentVec::new(), |v, x| v.push(x))
+    }
+}
+
+impl<T: Clone + Send + fmt::Debug> fmt::Debug for GcPersistentVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
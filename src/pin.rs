@@ -0,0 +1,48 @@
+//! Keeping GC objects alive independently of any reference to them.
+//!
+//! Boehm can't change the kind of a block once allocated, so an object can't
+//! be made uncollectable and later handed back to the collector. Instead, a
+//! pinned object keeps its normal (collectable) block, and a pointer to it is
+//! stored in a one word slot allocated with `GC_malloc_uncollectable`. The
+//! collector scans uncollectable blocks, so the object (and everything it
+//! references) stays alive until the slot is freed.
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    collections::BTreeMap,
+    sync::Mutex,
+};
+
+use crate::ALLOCATOR;
+
+/// Maps pinned objects to their slots. Both are stored bitwise-negated so
+/// that the table itself doesn't look like it points to either.
+static PINNED: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Pin the object at `obj`, returning the address of its slot. Pinning an
+/// already pinned object returns its existing slot.
+pub(crate) fn pin(obj: *const u8) -> *mut *const u8 {
+    let mut pinned = PINNED.lock().unwrap();
+    if let Some(slot) = pinned.get(&!(obj as usize)) {
+        return !*slot as *mut *const u8;
+    }
+    let slot = unsafe { ALLOCATOR.alloc(Layout::new::<*const u8>()) as *mut *const u8 };
+    unsafe { slot.write(obj) };
+    pinned.insert(!(obj as usize), !(slot as usize));
+    slot
+}
+
+/// Unpin the object at `obj`, allowing it to be collected once unreachable.
+/// Returns false if it wasn't pinned.
+pub(crate) fn unpin(obj: *const u8) -> bool {
+    match PINNED.lock().unwrap().remove(&!(obj as usize)) {
+        Some(slot) => {
+            unsafe { ALLOCATOR.dealloc(!slot as *mut u8, Layout::new::<*const u8>()) };
+            true
+        }
+        None => false,
+    }
+}
+
+pub(crate) fn is_pinned(obj: *const u8) -> bool {
+    PINNED.lock().unwrap().contains_key(&!(obj as usize))
+}
@@ -0,0 +1,159 @@
+//! Pools of reusable GC objects.
+use std::{
+    cmp,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{alloc::GcAlloc, pressure, Gc, GcAllocator, ALLOCATOR};
+
+/// A pool of `Gc<T>` values which are recycled by the collector.
+///
+/// Values handed out by `get` are ordinary `Gc<T>`s. Rather than being
+/// dropped once unreachable, the collector hands them back to the pool
+/// (having reset them), and later calls to `get` reuse them. This avoids
+/// allocation for hot, short-lived objects such as interpreter call frames.
+///
+/// The pool keeps at most `max_free` unused values. Whenever the collector
+/// has had to grow the heap, the pool halves its limit and releases the
+/// values it no longer has room for. After collections which didn't need to
+/// grow the heap, it doubles its limit again, up to the one it was created
+/// with.
+///
+/// Values are recycled in the same order as those with ordered finalizers
+/// (see `Gc::new_with_ordered_finalizer`): everything a value points to is
+/// kept alive until it has been recycled. So, as there, a value which is
+/// part of a cycle through other pooled values is never recycled (or freed).
+pub struct GcPool<T: Send + 'static> {
+    inner: Arc<PoolInner<T>>,
+    configured_max_free: usize,
+    seen_collections: AtomicUsize,
+    seen_resizes: AtomicUsize,
+}
+
+struct PoolInner<T: Send> {
+    /// Always has a capacity of at least `max_free`, so that pushing to it
+    /// (which happens in finalizers) never allocates.
+    free: Mutex<Vec<Gc<T>>>,
+    max_free: AtomicUsize,
+    init: fn() -> T,
+    reset: fn(&mut T),
+}
+
+impl<T: Send + 'static> GcPool<T> {
+    /// Create a pool which keeps up to `max_free` unused values. New values
+    /// are created with `init`, and recycled values are passed to `reset`
+    /// before being reused.
+    pub fn new(max_free: usize, init: fn() -> T, reset: fn(&mut T)) -> Self {
+        GcPool {
+            inner: Arc::new(PoolInner {
+                free: Mutex::new(Vec::with_capacity(max_free)),
+                max_free: AtomicUsize::new(max_free),
+                init,
+                reset,
+            }),
+            configured_max_free: max_free,
+            seen_collections: AtomicUsize::new(GcAllocator::num_collections()),
+            seen_resizes: AtomicUsize::new(pressure::heap_resizes()),
+        }
+    }
+
+    /// Get a value from the pool, creating a new one if none are free.
+    pub fn get(&self) -> Gc<T> {
+        let resizes = pressure::heap_resizes();
+        let collections = GcAllocator::num_collections();
+        let grew = self.seen_resizes.swap(resizes, Ordering::Relaxed) != resizes;
+        let collected = self.seen_collections.swap(collections, Ordering::Relaxed) != collections;
+        if grew {
+            self.shrink();
+        } else if collected {
+            // The free list's capacity is never reduced, so pushing to it
+            // still won't allocate.
+            let max_free = self.inner.max_free.load(Ordering::Relaxed);
+            let max_free = cmp::min((max_free * 2).max(1), self.configured_max_free);
+            self.inner.max_free.store(max_free, Ordering::Relaxed);
+        }
+
+        let recycled = self.inner.free.lock().unwrap().pop();
+        let gc = recycled.unwrap_or_else(|| Gc::new((self.inner.init)()));
+        // Finalizers are one-shot, so a recycled value needs registering again.
+        // This replaces the finalizer `Gc::new` registers for a new value. It
+        // is ordered, so that whatever the value points to is still alive
+        // when it is reset and reused.
+        let pool = Arc::into_raw(Arc::clone(&self.inner));
+        unsafe {
+            GcAlloc::register_ordered_finalizer(
+                &ALLOCATOR,
+                Gc::into_raw(gc) as *mut u8,
+                Some(recycle::<T>),
+                pool as *mut u8,
+            );
+        }
+        gc
+    }
+
+    /// The number of unused values the pool may currently keep.
+    pub fn max_free(&self) -> usize {
+        self.inner.max_free.load(Ordering::Relaxed)
+    }
+
+    /// The number of unused values currently held by the pool.
+    pub fn num_free(&self) -> usize {
+        self.inner.free.lock().unwrap().len()
+    }
+
+    /// Halve the number of unused values the pool may keep, releasing any it
+    /// no longer has room for to the collector.
+    pub fn shrink(&self) {
+        let max_free = self.inner.max_free.load(Ordering::Relaxed) / 2;
+        self.inner.max_free.store(max_free, Ordering::Relaxed);
+        // Registering a finalizer may allocate, so the lock is only held to
+        // pop each value.
+        loop {
+            let gc = {
+                let mut free = self.inner.free.lock().unwrap();
+                if free.len() <= max_free {
+                    break;
+                }
+                free.pop().unwrap()
+            };
+            release(gc);
+        }
+    }
+}
+
+impl<T: Send> Drop for PoolInner<T> {
+    fn drop(&mut self) {
+        for gc in self.free.get_mut().unwrap().drain(..) {
+            release(gc);
+        }
+    }
+}
+
+/// Hand a value on the free list back to the collector. Its finalizer was
+/// used up by `recycle`, so it needs registering again for the value to be
+/// dropped once unreachable.
+fn release<T: Send>(mut gc: Gc<T>) {
+    gc.register_finalizer();
+}
+
+/// The finalizer registered on values handed out by a pool. If the pool has
+/// room, the value is resurrected by putting it back on the free list.
+/// Otherwise, it is dropped as normal.
+unsafe extern "C" fn recycle<T: Send + 'static>(obj: *mut u8, pool: *mut u8) {
+    crate::alloc::catch_finalizer_panic(std::any::type_name::<T>(), || {
+        let pool = Arc::from_raw(pool as *const PoolInner<T>);
+        let value = obj as *mut T;
+        // `reset` may allocate, so it must not be called with the lock held.
+        (pool.reset)(&mut *value);
+        let mut free = pool.free.lock().unwrap();
+        if free.len() < pool.max_free.load(Ordering::Relaxed) {
+            free.push(Gc::from_raw(value));
+        } else {
+            drop(free);
+            std::ptr::drop_in_place(value);
+        }
+    });
+}
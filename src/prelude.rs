@@ -0,0 +1,29 @@
+//! The types most runtimes built on libgc need, for glob importing with
+//! `use libgc::prelude::*`.
+pub use crate::{
+    arena::GcTypedArena,
+    atomic::AtomicGc,
+    bytes::GcBytes,
+    cell::GcCell,
+    cow::GcCow,
+    display::{GcDisplay, GcStringBuilder},
+    error::GcError,
+    gc::{collect, gc_init, Gc},
+    inline_vec::GcInlineVec,
+    mailbox::FinalizerMailbox,
+    persistent::{GcPersistentMap, GcPersistentVec},
+    quota::{Quota, QuotaExceeded},
+    scope::GcScopeChain,
+    string::GcString,
+    tagged::TaggedGc,
+    value::GcValue,
+    vec::GcVec,
+    GcAllocator, GcConfig,
+};
+
+#[cfg(feature = "stats_profiling")]
+pub use crate::stats::FinalizerStats;
+#[cfg(feature = "stats_counters")]
+pub use crate::stats::Report;
+#[cfg(feature = "standalone")]
+pub use crate::{cache::GcLruCache, emitter::GcEventEmitter, pool::GcPool, weak::GcWeak};
@@ -0,0 +1,27 @@
+//! Detecting memory pressure.
+//!
+//! The collector only grows the heap once collecting hasn't freed enough
+//! memory, so heap growth is a cheap signal that caches and pools should give
+//! memory back.
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Once,
+};
+
+use crate::GcAllocator;
+
+static HEAP_RESIZES: AtomicUsize = AtomicUsize::new(0);
+static HOOK_HEAP_RESIZE: Once = Once::new();
+
+unsafe extern "C" fn on_heap_resize(_: usize) {
+    HEAP_RESIZES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of times the collector has grown the heap since this was first
+/// called. Callers compare this against the value they last saw.
+pub(crate) fn heap_resizes() -> usize {
+    HOOK_HEAP_RESIZE.call_once(|| unsafe {
+        GcAllocator::set_on_heap_resize(Some(on_heap_resize));
+    });
+    HEAP_RESIZES.load(Ordering::Relaxed)
+}
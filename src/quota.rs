@@ -0,0 +1,139 @@
+//! Per-thread limits on how much a thread may allocate on the GC heap.
+//!
+//! This allows e.g. sandboxed script threads to be stopped from exhausting
+//! the heap shared with the rest of the process. Quotas only account for
+//! `Gc` allocations made by the thread itself, and are counted from when the
+//! quota was set (or last reset), not against the live heap size.
+use std::{cell::Cell, error::Error, fmt};
+
+thread_local! {
+    static QUOTA: Cell<Option<usize>> = Cell::new(None);
+    static ALLOCATED: Cell<usize> = Cell::new(0);
+    static PANIC_ON_EXCEEDED: Cell<bool> = Cell::new(false);
+}
+
+pub struct Quota;
+
+impl Quota {
+    /// Limit the current thread to allocating `bytes` from now on. Once the
+    /// limit is reached, `Gc::try_new` fails.
+    pub fn set_for_current_thread(bytes: usize) {
+        QUOTA.with(|q| q.set(Some(bytes)));
+        Quota::reset_current_thread();
+    }
+
+    pub fn clear_for_current_thread() {
+        QUOTA.with(|q| q.set(None));
+    }
+
+    /// Start counting the current thread's allocations from zero again.
+    pub fn reset_current_thread() {
+        ALLOCATED.with(|a| a.set(0));
+    }
+
+    /// When enabled, `Gc::new` panics (rather than only `Gc::try_new`
+    /// failing) once the current thread's quota is exceeded.
+    pub fn panic_on_exceeded(enable: bool) {
+        PANIC_ON_EXCEEDED.with(|p| p.set(enable));
+    }
+
+    /// The number of bytes allocated by the current thread since its quota
+    /// was set or reset.
+    pub fn allocated_by_current_thread() -> usize {
+        ALLOCATED.with(|a| a.get())
+    }
+
+    /// Returns `None` if the current thread has no quota.
+    pub fn remaining_for_current_thread() -> Option<usize> {
+        QUOTA
+            .with(|q| q.get())
+            .map(|q| q.saturating_sub(Quota::allocated_by_current_thread()))
+    }
+}
+
+/// Returned by `Gc::try_new` when an allocation would exceed the current
+/// thread's quota.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub quota: usize,
+    pub allocated: usize,
+    pub requested: usize,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "allocating {} bytes would exceed this thread's GC quota of {} bytes ({} already allocated)",
+            self.requested, self.quota, self.allocated
+        )
+    }
+}
+
+impl Error for QuotaExceeded {}
+
+/// Account for an allocation of `bytes`, failing (without counting it) if the
+/// current thread's quota would be exceeded.
+pub(crate) fn try_charge(bytes: usize) -> Result<(), QuotaExceeded> {
+    if let Some(quota) = QUOTA.with(|q| q.get()) {
+        let allocated = Quota::allocated_by_current_thread();
+        if allocated.saturating_add(bytes) > quota {
+            return Err(QuotaExceeded {
+                quota,
+                allocated,
+                requested: bytes,
+            });
+        }
+        ALLOCATED.with(|a| a.set(allocated + bytes));
+    }
+    Ok(())
+}
+
+/// Account for an allocation of `bytes` which can not fail. This panics if
+/// the quota is exceeded and `Quota::panic_on_exceeded` is enabled.
+pub(crate) fn charge(bytes: usize) {
+    if QUOTA.with(|q| q.get()).is_none() {
+        return;
+    }
+    if let Err(e) = try_charge(bytes) {
+        if PANIC_ON_EXCEEDED.with(|p| p.get()) {
+            panic!("{}", e);
+        }
+        ALLOCATED.with(|a| a.set(e.allocated.saturating_add(bytes)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Gc;
+
+    #[test]
+    fn test_quota() {
+        Quota::set_for_current_thread(16);
+        assert!(Gc::try_new(1u64).is_ok());
+        assert!(Gc::try_new(2u64).is_ok());
+        assert_eq!(
+            Gc::try_new(3u64).unwrap_err(),
+            QuotaExceeded {
+                quota: 16,
+                allocated: 16,
+                requested: 8,
+            }
+        );
+        assert_eq!(Quota::remaining_for_current_thread(), Some(0));
+
+        Quota::reset_current_thread();
+        assert!(Gc::try_new(4u64).is_ok());
+        Quota::clear_for_current_thread();
+        assert_eq!(Quota::remaining_for_current_thread(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_quota_panics() {
+        Quota::set_for_current_thread(4);
+        Quota::panic_on_exceeded(true);
+        Gc::new(1u64);
+    }
+}
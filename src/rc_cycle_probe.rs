@@ -0,0 +1,228 @@
+//! Estimate how much memory `Rc` cycles leak, to judge whether moving to
+//! `Gc` is worthwhile.
+//!
+//! Replace `use std::rc::Rc` with `use libgc::rc_cycle_probe::Rc` in the code
+//! under test, run a workload, drop everything it owned, then call
+//! `report`. Any value which was allocated but never dropped is either still
+//! reachable from somewhere (e.g. a thread local) or part of a leaked cycle,
+//! which `Gc` would reclaim. Counts are kept per thread, since `Rc` can't be
+//! shared between threads.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    mem::size_of,
+    ops::Deref,
+    rc::{self, Rc as StdRc},
+};
+
+thread_local! {
+    static COUNTS: RefCell<HashMap<&'static str, TypeCounts>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Copy, Clone, Default)]
+struct TypeCounts {
+    allocated: usize,
+    dropped: usize,
+    size: usize,
+}
+
+/// A value allocated by `Rc::new`, which records when it is dropped.
+struct Probed<T> {
+    value: T,
+}
+
+impl<T> Drop for Probed<T> {
+    fn drop(&mut self) {
+        // The thread's counts may already have been destroyed if this drop
+        // runs during thread exit.
+        let _ = COUNTS.try_with(|c| {
+            if let Some(c) = c.borrow_mut().get_mut(std::any::type_name::<T>()) {
+                c.dropped += 1;
+            }
+        });
+    }
+}
+
+/// A drop-in replacement for `std::rc::Rc` which counts allocations and
+/// drops of each type.
+pub struct Rc<T>(StdRc<Probed<T>>);
+
+impl<T> Rc<T> {
+    pub fn new(value: T) -> Self {
+        COUNTS.with(|c| {
+            let mut c = c.borrow_mut();
+            let counts = c.entry(std::any::type_name::<T>()).or_default();
+            counts.allocated += 1;
+            counts.size = size_of::<T>();
+        });
+        Rc(StdRc::new(Probed { value }))
+    }
+
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        Weak(StdRc::downgrade(&this.0))
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        StdRc::strong_count(&this.0)
+    }
+
+    pub fn weak_count(this: &Self) -> usize {
+        StdRc::weak_count(&this.0)
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        StdRc::ptr_eq(&this.0, &other.0)
+    }
+}
+
+impl<T> Clone for Rc<T> {
+    fn clone(&self) -> Self {
+        Rc(self.0.clone())
+    }
+}
+
+impl<T> Deref for Rc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Rc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// The counterpart of `std::rc::Weak` for the probing `Rc`.
+pub struct Weak<T>(rc::Weak<Probed<T>>);
+
+impl<T> Weak<T> {
+    pub fn upgrade(&self) -> Option<Rc<T>> {
+        self.0.upgrade().map(Rc)
+    }
+}
+
+impl<T> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        Weak(self.0.clone())
+    }
+}
+
+/// The allocations and drops of one type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeReport {
+    pub name: &'static str,
+    pub allocated: usize,
+    pub dropped: usize,
+    /// The size of one value, not including anything it owns.
+    pub size: usize,
+}
+
+impl TypeReport {
+    /// The number of values which were never dropped.
+    pub fn leaked(&self) -> usize {
+        self.allocated - self.dropped
+    }
+
+    pub fn leaked_bytes(&self) -> usize {
+        self.leaked() * self.size
+    }
+}
+
+/// The allocations and drops of every type seen, largest leak first.
+#[derive(Clone, Debug, Default)]
+pub struct ProbeReport {
+    pub types: Vec<TypeReport>,
+}
+
+impl ProbeReport {
+    /// The memory `Gc` could reclaim, assuming every value not dropped is
+    /// part of a cycle. This is a lower bound, since memory owned by the
+    /// leaked values (e.g. the contents of a `Vec`) isn't counted.
+    pub fn leaked_bytes(&self) -> usize {
+        self.types.iter().map(TypeReport::leaked_bytes).sum()
+    }
+}
+
+impl fmt::Display for ProbeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "allocated  dropped  leaked  leaked bytes  type")?;
+        for t in &self.types {
+            writeln!(
+                f,
+                "{:>9}  {:>7}  {:>6}  {:>12}  {}",
+                t.allocated,
+                t.dropped,
+                t.leaked(),
+                t.leaked_bytes(),
+                t.name
+            )?;
+        }
+        write!(f, "{} bytes leaked in total", self.leaked_bytes())
+    }
+}
+
+/// Report on every probing `Rc` allocated by the current thread since it
+/// started (or `reset` was last called).
+pub fn report() -> ProbeReport {
+    let mut types = COUNTS.with(|c| {
+        c.borrow()
+            .iter()
+            .map(|(&name, c)| TypeReport {
+                name,
+                allocated: c.allocated,
+                dropped: c.dropped,
+                size: c.size,
+            })
+            .collect::<Vec<_>>()
+    });
+    types.sort_by(|a, b| {
+        b.leaked_bytes()
+            .cmp(&a.leaked_bytes())
+            .then(a.name.cmp(b.name))
+    });
+    ProbeReport { types }
+}
+
+/// Forget the current thread's counts. Values allocated before the reset
+/// aren't counted when dropped afterwards.
+pub fn reset() {
+    COUNTS.with(|c| c.borrow_mut().clear());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct Node {
+        next: RefCell<Option<Rc<Node>>>,
+    }
+
+    #[test]
+    fn counts_leaked_cycles() {
+        reset();
+        for _ in 0..3 {
+            let a = Rc::new(Node {
+                next: RefCell::new(None),
+            });
+            let b = Rc::new(Node {
+                next: RefCell::new(Some(a.clone())),
+            });
+            *a.next.borrow_mut() = Some(b);
+        }
+        drop(Rc::new(1u64));
+
+        let report = report();
+        assert_eq!(report.types.len(), 2);
+        let nodes = &report.types[0];
+        assert!(nodes.name.ends_with("Node"));
+        assert_eq!((nodes.allocated, nodes.dropped, nodes.leaked()), (6, 0, 6));
+        let ints = &report.types[1];
+        assert_eq!((ints.name, ints.leaked()), ("u64", 0));
+        assert_eq!(report.leaked_bytes(), 6 * size_of::<Node>());
+    }
+}
@@ -0,0 +1,45 @@
+//! A read-only region for large immutable tables. See
+//! `Gc::into_shared_readonly`.
+//!
+//! The region is mapped directly with `mmap`, rather than obtained from the
+//! collector, so the collector neither scans it nor ever frees it. Once a
+//! value has been copied in, its pages are made read-only, so that stray
+//! writes fault instead of silently corrupting data shared between threads.
+use std::{
+    alloc::{handle_alloc_error, Layout},
+    ptr,
+};
+
+/// Copy the `layout.size()` bytes at `src` to fresh read-only pages,
+/// returning the copy. Each value gets pages of its own, so this is meant
+/// for a few large values, not many small ones.
+///
+/// # Safety
+///
+/// `src` must be valid for reads of `layout.size()` bytes.
+pub(crate) unsafe fn copy(src: *const u8, layout: Layout) -> *const u8 {
+    if layout.size() == 0 {
+        return layout.align() as *const u8;
+    }
+    let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+    assert!(
+        layout.align() <= page,
+        "read-only values can't be aligned beyond the page size"
+    );
+    let len = (layout.size() + page - 1) & !(page - 1);
+    let base = libc::mmap(
+        ptr::null_mut(),
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    if base == libc::MAP_FAILED {
+        handle_alloc_error(layout);
+    }
+    ptr::copy_nonoverlapping(src, base as *mut u8, layout.size());
+    let rc = libc::mprotect(base, len, libc::PROT_READ);
+    assert_eq!(rc, 0, "couldn't make the read-only region read-only");
+    base as *const u8
+}
@@ -0,0 +1,104 @@
+//! Lexically nested variable environments, as used by interpreters.
+//!
+//! Each scope holds a fixed number of variable slots and a pointer to the
+//! scope it is nested in. A closure typically captures the scope it was
+//! created in, and is itself stored in one of that scope's slots: with `Gc`
+//! such cycles need no special handling.
+use std::cell::Cell;
+
+use crate::Gc;
+
+/// A scope in a chain of nested scopes. Variables are addressed by `depth`
+/// (the number of parents to follow, with 0 meaning this scope) and by
+/// `index` within that scope, as resolved by a compiler ahead of time.
+pub struct GcScopeChain<T: Copy + Send> {
+    parent: Option<Gc<GcScopeChain<T>>>,
+    slots: Box<[Cell<T>]>,
+}
+
+impl<T: Copy + Send> GcScopeChain<T> {
+    /// Create an outermost scope whose slots are initialized to `slots`.
+    pub fn new_global(slots: Vec<T>) -> Gc<Self> {
+        GcScopeChain::new(None, slots)
+    }
+
+    /// Create a scope nested in `this`, whose slots are initialized to
+    /// `slots`.
+    pub fn push(this: Gc<Self>, slots: Vec<T>) -> Gc<Self> {
+        GcScopeChain::new(Some(this), slots)
+    }
+
+    fn new(parent: Option<Gc<Self>>, slots: Vec<T>) -> Gc<Self> {
+        Gc::new(GcScopeChain {
+            parent,
+            slots: slots.into_iter().map(Cell::new).collect(),
+        })
+    }
+
+    pub fn parent(&self) -> Option<Gc<Self>> {
+        self.parent
+    }
+
+    /// The number of slots in this scope, not including its parents.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// The number of parents this scope has.
+    pub fn depth(&self) -> usize {
+        std::iter::successors(self.parent, |s| s.parent).count()
+    }
+
+    /// The scope `depth` parents up from `this`, or `None` if the chain
+    /// isn't that long.
+    pub fn ancestor(this: Gc<Self>, depth: usize) -> Option<Gc<Self>> {
+        GcScopeChain::scopes(this).nth(depth)
+    }
+
+    /// Returns the value of the variable at `index` in the scope `depth`
+    /// parents up.
+    ///
+    /// # Panics
+    ///
+    /// If the chain has fewer than `depth` parents, or that scope has no slot
+    /// `index`.
+    pub fn get(&self, depth: usize, index: usize) -> T {
+        self.slot(depth, index).get()
+    }
+
+    /// Sets the variable at `index` in the scope `depth` parents up to
+    /// `value`.
+    ///
+    /// # Panics
+    ///
+    /// As `get`.
+    pub fn set(&self, depth: usize, index: usize, value: T) {
+        self.slot(depth, index).set(value)
+    }
+
+    fn slot(&self, depth: usize, index: usize) -> &Cell<T> {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = match &scope.parent {
+                Some(p) => &**p,
+                None => panic!("scope chain has no scope at depth {}", depth),
+            };
+        }
+        &scope.slots[index]
+    }
+
+    /// Iterate over the values of this scope's slots, not including its
+    /// parents'.
+    pub fn slots(&self) -> impl Iterator<Item = T> + '_ {
+        self.slots.iter().map(Cell::get)
+    }
+
+    /// Iterate over `this`, its parent, and so on out to the outermost scope.
+    pub fn scopes(this: Gc<Self>) -> impl Iterator<Item = Gc<Self>> {
+        std::iter::successors(Some(this), |s| s.parent)
+    }
+}
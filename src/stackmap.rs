@@ -0,0 +1,108 @@
+//! Compiler-emitted stack maps, for precise stack scanning in rustgc builds.
+//!
+//! Code generated by rustgc registers, for each range of return addresses, the
+//! offsets (from the frame's base pointer) of the stack slots which hold live
+//! `Gc` pointers at those addresses. A stack walker can then visit exactly
+//! those slots in registered frames, rather than conservatively treating
+//! every word of the frame as a potential pointer.
+//!
+//! **This is only partly implemented.** It provides the registry and lookup,
+//! but nothing consumes them yet: the collector still scans every frame
+//! conservatively, including registered ones. Skipping registered frames
+//! needs the collector used by rustgc to let its stack scan be replaced,
+//! which bdwgc has no hook for; `GC_set_push_other_roots` can only add roots,
+//! not remove the frames it scans itself.
+use std::{collections::BTreeMap, sync::RwLock};
+
+/// Maps the start of each registered pc range to its end and slot offsets.
+static STACK_MAPS: RwLock<BTreeMap<usize, (usize, Box<[isize]>)>> = RwLock::new(BTreeMap::new());
+
+pub struct StackMapRegistry;
+
+impl StackMapRegistry {
+    /// Register `slots`, the frame pointer relative offsets of the live `Gc`
+    /// slots, for return addresses in `start..end`. This replaces any map
+    /// previously registered at `start`.
+    ///
+    /// # Panics
+    ///
+    /// If `start..end` is empty or overlaps another registered range.
+    pub fn register(start: usize, end: usize, slots: &[isize]) {
+        assert!(start < end, "empty pc range {:#x}..{:#x}", start, end);
+        let mut maps = STACK_MAPS.write().unwrap();
+        let overlaps_prev = maps
+            .range(..start)
+            .next_back()
+            .map_or(false, |(_, (prev_end, _))| *prev_end > start);
+        let overlaps_next = maps.range(start + 1..end).next().is_some();
+        assert!(
+            !overlaps_prev && !overlaps_next,
+            "pc range {:#x}..{:#x} overlaps a registered stack map",
+            start,
+            end
+        );
+        maps.insert(start, (end, slots.into()));
+    }
+
+    /// Unregister the map starting at `start`, e.g. when the code it
+    /// describes is unloaded. Returns false if there was none.
+    pub fn unregister(start: usize) -> bool {
+        STACK_MAPS.write().unwrap().remove(&start).is_some()
+    }
+
+    /// Returns true if the frame whose return address is `pc` has a
+    /// registered map. Such a frame need not be scanned conservatively,
+    /// though for now it still is (see the module documentation).
+    pub fn is_registered(pc: usize) -> bool {
+        StackMapRegistry::with_slots(pc, |_| ()).is_some()
+    }
+
+    /// Call `f` with the address of each live `Gc` slot in the frame whose
+    /// return address is `pc` and whose base pointer is `fp`. Returns false,
+    /// without calling `f`, if the frame has no registered map.
+    pub fn for_each_root<F: FnMut(*const usize)>(pc: usize, fp: *const u8, mut f: F) -> bool {
+        StackMapRegistry::with_slots(pc, |slots| {
+            for off in slots {
+                f(fp.wrapping_offset(*off) as *const usize);
+            }
+        })
+        .is_some()
+    }
+
+    fn with_slots<R>(pc: usize, f: impl FnOnce(&[isize]) -> R) -> Option<R> {
+        let maps = STACK_MAPS.read().unwrap();
+        match maps.range(..=pc).next_back() {
+            Some((_, (end, slots))) if pc < *end => Some(f(slots)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        StackMapRegistry::register(0x1000, 0x1100, &[-8, -24]);
+        StackMapRegistry::register(0x1100, 0x1200, &[]);
+        assert!(!StackMapRegistry::is_registered(0xfff));
+        assert!(StackMapRegistry::is_registered(0x10ff));
+
+        let frame = [0u8; 32];
+        let fp = frame.as_ptr().wrapping_add(32);
+        let mut roots = Vec::new();
+        assert!(StackMapRegistry::for_each_root(0x1080, fp, |r| roots.push(r)));
+        assert_eq!(
+            roots,
+            [
+                fp.wrapping_sub(8) as *const usize,
+                fp.wrapping_sub(24) as *const usize
+            ]
+        );
+
+        assert!(StackMapRegistry::unregister(0x1000));
+        assert!(!StackMapRegistry::for_each_root(0x1080, fp, |_| panic!()));
+        assert!(StackMapRegistry::unregister(0x1100));
+    }
+}
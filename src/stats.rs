@@ -1,3 +1,238 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+//! Statistics about allocation, finalization, and collection.
+//!
+//! With the default `stats_counters` feature, libgc keeps cheap counts of
+//! allocations and finalizers. `stats_profiling` additionally times each
+//! finalizer and allows collections to be recorded as a timeline. `report`
+//! gathers whichever are enabled. `sampled_type_histogram` breaks the live
+//! heap down by type, once sampling has been turned on.
+#[cfg(feature = "stats_profiling")]
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+use std::{
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+// These need collector hooks which rustgc's allocator doesn't have.
+#[cfg(feature = "standalone")]
+mod sampling;
+#[cfg(all(feature = "stats_profiling", feature = "standalone"))]
+mod timeline;
+#[cfg(feature = "standalone")]
+mod watchdog;
+
+#[cfg(feature = "standalone")]
+pub(crate) use sampling::record as record_sample;
+#[cfg(feature = "standalone")]
+pub use sampling::{sample_types, sampled_type_histogram, TypeSample};
+#[cfg(all(feature = "stats_profiling", feature = "crash_handler"))]
+pub(crate) use timeline::recording as recording_timeline;
+#[cfg(all(feature = "stats_profiling", feature = "standalone"))]
+pub use timeline::{start_timeline, stop_timeline};
+#[cfg(feature = "standalone")]
+pub use watchdog::Watchdog;
 
 pub static NUM_REGISTERED_FINALIZERS: AtomicUsize = AtomicUsize::new(0);
+pub static NUM_FINALIZERS_RUN: AtomicUsize = AtomicUsize::new(0);
+/// The number of finalizers which panicked. These aren't included in
+/// `NUM_FINALIZERS_RUN`.
+pub static NUM_FINALIZERS_PANICKED: AtomicUsize = AtomicUsize::new(0);
+/// The number of finalizers which have started running, whether or not they
+/// have finished.
+pub(crate) static NUM_FINALIZERS_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of bytes allocated by one thread, which only that thread
+/// writes to, so that counting doesn't contend between threads. Counts are
+/// never freed: when a thread exits, its count is taken over by the next
+/// thread to start, and the sum of all of them stays the total.
+#[repr(align(64))]
+struct ThreadBytes {
+    bytes: AtomicUsize,
+    in_use: AtomicBool,
+    next: *const ThreadBytes,
+}
+
+/// The head of the list of all `ThreadBytes`, which is only ever pushed to.
+static THREAD_BYTES: AtomicPtr<ThreadBytes> = AtomicPtr::new(ptr::null_mut());
+/// Bytes allocated by threads which are exiting, and have given up their
+/// count.
+static EXITING_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+struct ThreadBytesGuard(&'static ThreadBytes);
+
+impl Drop for ThreadBytesGuard {
+    fn drop(&mut self) {
+        self.0.in_use.store(false, Ordering::Release);
+    }
+}
+
+thread_local! {
+    static BYTES: ThreadBytesGuard = ThreadBytesGuard(claim_thread_bytes());
+}
+
+fn claim_thread_bytes() -> &'static ThreadBytes {
+    let mut p = THREAD_BYTES.load(Ordering::Acquire);
+    while let Some(tb) = unsafe { p.as_ref() } {
+        if tb
+            .in_use
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return tb;
+        }
+        p = tb.next as *mut ThreadBytes;
+    }
+    let tb = Box::leak(Box::new(ThreadBytes {
+        bytes: AtomicUsize::new(0),
+        in_use: AtomicBool::new(true),
+        next: ptr::null(),
+    }));
+    let mut head = THREAD_BYTES.load(Ordering::Relaxed);
+    loop {
+        tb.next = head;
+        match THREAD_BYTES.compare_exchange_weak(head, tb, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => return tb,
+            Err(h) => head = h,
+        }
+    }
+}
+
+/// Count `bytes` allocated by `Gc::new` and friends on this thread.
+#[inline]
+pub(crate) fn record_allocation(bytes: usize) {
+    let counted = BYTES.try_with(|b| {
+        let count = &b.0.bytes;
+        count.store(count.load(Ordering::Relaxed) + bytes, Ordering::Relaxed);
+    });
+    if counted.is_err() {
+        EXITING_BYTES.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// The number of bytes allocated by `Gc::new` and friends, on all threads.
+/// This doesn't lock or allocate, so can be called from a signal handler.
+pub fn bytes_allocated() -> usize {
+    let mut total = EXITING_BYTES.load(Ordering::Relaxed);
+    let mut p = THREAD_BYTES.load(Ordering::Acquire);
+    while let Some(tb) = unsafe { p.as_ref() } {
+        total += tb.bytes.load(Ordering::Relaxed);
+        p = tb.next as *mut ThreadBytes;
+    }
+    total
+}
+
+/// A snapshot of all the statistics being kept, as returned by `report`.
+#[derive(Debug)]
+pub struct Report {
+    pub registered_finalizers: usize,
+    pub finalizers_run: usize,
+    pub finalizers_panicked: usize,
+    pub bytes_allocated: usize,
+    /// See `pending_finalizers`.
+    #[cfg(feature = "standalone")]
+    pub pending_finalizers: usize,
+    /// The bytes allocated by `Gc::new_cold` in the cold region. These are
+    /// also included in `bytes_allocated`.
+    pub cold_bytes_allocated: usize,
+    /// See `finalizer_report`.
+    #[cfg(feature = "stats_profiling")]
+    pub finalizers: Vec<(&'static str, FinalizerStats)>,
+    /// The collector's own statistics.
+    #[cfg(all(feature = "stats_profiling", feature = "standalone"))]
+    pub collector: crate::GcStats,
+}
+
+pub fn report() -> Report {
+    Report {
+        registered_finalizers: NUM_REGISTERED_FINALIZERS.load(Ordering::Relaxed),
+        finalizers_run: NUM_FINALIZERS_RUN.load(Ordering::Relaxed),
+        finalizers_panicked: NUM_FINALIZERS_PANICKED.load(Ordering::Relaxed),
+        bytes_allocated: bytes_allocated(),
+        #[cfg(feature = "standalone")]
+        pending_finalizers: pending_finalizers(),
+        cold_bytes_allocated: crate::cold::bytes_allocated(),
+        #[cfg(feature = "stats_profiling")]
+        finalizers: finalizer_report(),
+        #[cfg(all(feature = "stats_profiling", feature = "standalone"))]
+        collector: crate::GcAllocator::get_stats(),
+    }
+}
+
+/// The number of objects found unreachable whose finalizers haven't yet
+/// started running. With `GcConfig::deterministic_test_mode` (or
+/// finalize-on-demand otherwise enabled) these wait for
+/// `GcAllocator::invoke_finalizers`, so a steadily growing backlog means
+/// nothing is calling it often enough; see `Watchdog`.
+///
+/// Only finalizers registered through libgc are counted as they run, so
+/// finalizers registered directly with `GcAllocator` stay in the count.
+#[cfg(feature = "standalone")]
+pub fn pending_finalizers() -> usize {
+    if !crate::GcAllocator::has_queued_finalizers() {
+        return 0;
+    }
+    crate::GcAllocator::finalizers_queued()
+        .saturating_sub(NUM_FINALIZERS_STARTED.load(Ordering::Relaxed))
+}
+
+/// Finalizer statistics for a single type.
+#[cfg(feature = "stats_profiling")]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FinalizerStats {
+    pub num_run: usize,
+    pub total_time: Duration,
+}
+
+#[cfg(feature = "stats_profiling")]
+static FINALIZER_STATS: Mutex<BTreeMap<&'static str, FinalizerStats>> = Mutex::new(BTreeMap::new());
+
+/// Record that a finalizer for the type named `ty` took `elapsed` to run.
+#[cfg(feature = "stats_profiling")]
+pub(crate) fn record_finalizer(ty: &'static str, elapsed: Duration) {
+    // A finalizer can run on a thread which is already holding the lock (e.g.
+    // if building a report triggers a collection). Dropping the sample is
+    // preferable to deadlocking.
+    if let Ok(mut stats) = FINALIZER_STATS.try_lock() {
+        let entry = stats.entry(ty).or_default();
+        entry.num_run += 1;
+        entry.total_time += elapsed;
+    }
+}
+
+/// Returns the number of finalizers run, and the total time spent inside
+/// them, for each type which has been finalized so far. The report is sorted
+/// so that the types with the most expensive finalizers come first.
+#[cfg(feature = "stats_profiling")]
+pub fn finalizer_report() -> Vec<(&'static str, FinalizerStats)> {
+    let mut report = FINALIZER_STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(ty, s)| (*ty, *s))
+        .collect::<Vec<_>>();
+    report.sort_by(|a, b| b.1.total_time.cmp(&a.1.total_time));
+    report
+}
+
+#[cfg(all(test, feature = "stats_profiling"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_finalizer_report() {
+        record_finalizer("Slow", Duration::from_millis(2));
+        record_finalizer("Slow", Duration::from_millis(3));
+        record_finalizer("Fast", Duration::from_millis(1));
+
+        let report = finalizer_report();
+        let slow = report.iter().position(|(ty, _)| *ty == "Slow").unwrap();
+        let fast = report.iter().position(|(ty, _)| *ty == "Fast").unwrap();
+        assert!(slow < fast);
+        assert_eq!(
+            report[slow].1,
+            FinalizerStats {
+                num_run: 2,
+                total_time: Duration::from_millis(5),
+            }
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Approximate heap composition by type, from sampled allocations.
+//!
+//! libgc keeps no per-object type information, so the heap can't be broken
+//! down by type after the fact. Instead, once `sample_types` is called,
+//! every Nth `Gc::new` on each thread records the allocated type, along with
+//! a weak link to the object. The histogram counts the samples whose objects
+//! are still alive, scaled up by N. When sampling is off, an allocation costs
+//! one extra relaxed load.
+use std::{
+    alloc::Layout,
+    cell::Cell,
+    collections::BTreeMap,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{alloc::GcAlloc, GcAllocator, ALLOCATOR};
+
+/// Every how many allocations one is sampled, or 0 if sampling is off.
+static INTERVAL: AtomicUsize = AtomicUsize::new(0);
+static SAMPLES: Mutex<Vec<Sample>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static COUNTDOWN: Cell<usize> = Cell::new(0);
+}
+
+struct Sample {
+    ty: &'static str,
+    size: usize,
+    /// The number of allocations this sample stands for.
+    weight: usize,
+    /// Holds the object's address, hidden, until the collector clears it.
+    link: NonNull<usize>,
+}
+
+unsafe impl Send for Sample {}
+
+impl Sample {
+    fn is_live(&self) -> bool {
+        unsafe { self.link.as_ptr().read_volatile() != 0 }
+    }
+}
+
+/// The estimated number and total size of the live objects of a type.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TypeSample {
+    pub objects: usize,
+    pub bytes: usize,
+}
+
+/// Sample one in every `interval` allocations by `Gc::new` on each thread.
+/// An interval of 0 stops sampling, and forgets the samples taken so far.
+/// Smaller intervals give more accurate histograms, at the cost of a weak
+/// link (see `GcWeak`) for every sampled object.
+pub fn sample_types(interval: usize) {
+    INTERVAL.store(interval, Ordering::Relaxed);
+    if interval == 0 {
+        SAMPLES.lock().unwrap().clear();
+    }
+}
+
+/// Account for a newly allocated `ty` at `obj`.
+#[inline]
+pub(crate) fn record(ty: &'static str, obj: *const u8, size: usize) {
+    let interval = INTERVAL.load(Ordering::Relaxed);
+    if interval == 0 {
+        return;
+    }
+    // The thread's countdown may already be gone if it is exiting.
+    let _ = COUNTDOWN.try_with(|c| match c.get() {
+        0 => {
+            c.set(interval - 1);
+            sample(ty, obj, size, interval);
+        }
+        n => c.set(n - 1),
+    });
+}
+
+#[cold]
+fn sample(ty: &'static str, obj: *const u8, size: usize, weight: usize) {
+    // Only blocks from the collector can have disappearing links, so objects
+    // from other `GcAlloc`s (e.g. arenas) aren't sampled.
+    if GcAllocator::base(obj).map(NonNull::as_ptr) != Some(obj as *mut u8) {
+        return;
+    }
+    // A finalizer may allocate on a thread which is already holding the
+    // lock. Dropping the sample is preferable to deadlocking.
+    let mut samples = match SAMPLES.try_lock() {
+        Ok(samples) => samples,
+        Err(_) => return,
+    };
+    let link = match GcAlloc::alloc_atomic(&ALLOCATOR, Layout::new::<usize>()) {
+        Ok(link) => link.cast::<usize>(),
+        Err(_) => return,
+    };
+    unsafe {
+        // As in `GcWeak`, the address is hidden so that the link doesn't
+        // keep the object alive.
+        link.as_ptr().write(!(obj as usize));
+        GcAllocator::register_disappearing_link(link.as_ptr() as *mut *mut u8, obj);
+    }
+    // Forget dead objects before the samples are reallocated, so that they
+    // only grow with the live heap.
+    if samples.len() == samples.capacity() {
+        samples.retain(Sample::is_live);
+    }
+    samples.push(Sample {
+        ty,
+        size,
+        weight,
+        link,
+    });
+}
+
+/// Returns the estimated number and size of the live objects of each type
+/// allocated while sampling was on, largest total size first. The estimate
+/// is as good as of the last collection: objects which have become
+/// unreachable since are still counted.
+pub fn sampled_type_histogram() -> Vec<(&'static str, TypeSample)> {
+    let mut by_type = BTreeMap::<&'static str, TypeSample>::new();
+    let mut samples = SAMPLES.lock().unwrap();
+    samples.retain(Sample::is_live);
+    for s in samples.iter() {
+        let entry = by_type.entry(s.ty).or_default();
+        entry.objects += s.weight;
+        entry.bytes += s.weight * s.size;
+    }
+    drop(samples);
+    let mut histogram = by_type.into_iter().collect::<Vec<_>>();
+    histogram.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes));
+    histogram
+}
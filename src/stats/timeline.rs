@@ -0,0 +1,145 @@
+//! Recording collector activity as a Chrome trace (viewable in
+//! `about://tracing` or Perfetto).
+//!
+//! Events are reported by the collector with the allocation lock held, so
+//! they can't be written out as they happen: allocating would deadlock.
+//! Instead, they are stored in a buffer which is allocated up-front, and
+//! written to a file when recording stops.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use super::{bytes_allocated, NUM_FINALIZERS_RUN};
+use crate::{GcAllocator, GcEvent};
+
+/// The maximum number of events recorded. Later events are dropped.
+const CAPACITY: usize = 1 << 16;
+
+#[derive(Copy, Clone)]
+struct Event {
+    kind: GcEvent,
+    ts: u64, // In microseconds.
+    allocated: usize,
+    finalized: usize,
+}
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+static EVENTS: AtomicPtr<Event> = AtomicPtr::new(ptr::null_mut());
+static NUM_EVENTS: AtomicUsize = AtomicUsize::new(0);
+static PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+unsafe extern "C" fn on_event(kind: GcEvent) {
+    #[cfg(feature = "crash_handler")]
+    crate::crash::record_event(kind);
+
+    let events = EVENTS.load(Ordering::Acquire);
+    let i = NUM_EVENTS.fetch_add(1, Ordering::Relaxed);
+    if events.is_null() || i >= CAPACITY {
+        return;
+    }
+    events.add(i).write(Event {
+        kind,
+        ts: EPOCH.get().unwrap().elapsed().as_micros() as u64,
+        allocated: bytes_allocated(),
+        finalized: NUM_FINALIZERS_RUN.load(Ordering::Relaxed),
+    });
+}
+
+/// Start recording collections, their mark and sweep phases, and samples of
+/// the number of bytes allocated and finalizers run, to a Chrome trace file
+/// at `path`. The file is written by `stop_timeline`.
+pub fn start_timeline<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    // Fail early rather than after the program has run.
+    File::create(path.as_ref())?;
+    stop_recording();
+    EPOCH.get_or_init(Instant::now);
+    let events = Vec::<Event>::with_capacity(CAPACITY).leak().as_mut_ptr();
+    NUM_EVENTS.store(0, Ordering::Relaxed);
+    EVENTS.store(events, Ordering::Release);
+    *PATH.lock().unwrap() = Some(path.as_ref().to_owned());
+    unsafe { GcAllocator::set_on_collection_event(Some(on_event)) };
+    Ok(())
+}
+
+/// Stop recording and write the timeline started by `start_timeline`. Does
+/// nothing if no timeline is being recorded.
+pub fn stop_timeline() -> io::Result<()> {
+    let path = PATH.lock().unwrap().take();
+    let events = stop_recording();
+    match path {
+        Some(path) => write_trace(&path, &events),
+        None => Ok(()),
+    }
+}
+
+/// Returns true if a timeline is being recorded.
+#[cfg(feature = "crash_handler")]
+pub(crate) fn recording() -> bool {
+    !EVENTS.load(Ordering::Relaxed).is_null()
+}
+
+/// Unhook the collector callback and take ownership of the events recorded.
+fn stop_recording() -> Vec<Event> {
+    // Once this returns, the collector can't be inside `on_event`: it is only
+    // called with the allocation lock held, which this takes.
+    #[cfg(not(feature = "crash_handler"))]
+    let callback = None;
+    #[cfg(feature = "crash_handler")]
+    let callback = crate::crash::event_callback();
+    unsafe { GcAllocator::set_on_collection_event(callback) };
+    let events = EVENTS.swap(ptr::null_mut(), Ordering::Acquire);
+    if events.is_null() {
+        return Vec::new();
+    }
+    let len = NUM_EVENTS.load(Ordering::Relaxed).min(CAPACITY);
+    unsafe { Vec::from_raw_parts(events, len, CAPACITY) }
+}
+
+fn write_trace(path: &Path, events: &[Event]) -> io::Result<()> {
+    let mut f = BufWriter::new(File::create(path)?);
+    let pid = std::process::id();
+    write!(f, "{{\"traceEvents\":[")?;
+    let mut first = true;
+    for e in events {
+        let (name, ph) = match e.kind {
+            GcEvent::Start => ("collection", "B"),
+            GcEvent::End => ("collection", "E"),
+            GcEvent::MarkStart => ("mark", "B"),
+            GcEvent::MarkEnd => ("mark", "E"),
+            GcEvent::ReclaimStart => ("sweep", "B"),
+            GcEvent::ReclaimEnd => ("sweep", "E"),
+            GcEvent::PreStopWorld => ("stop world", "B"),
+            GcEvent::PostStopWorld => ("stop world", "E"),
+            GcEvent::PreStartWorld => ("start world", "B"),
+            GcEvent::PostStartWorld => ("start world", "E"),
+            GcEvent::ThreadSuspended | GcEvent::ThreadUnsuspended => continue,
+        };
+        if !first {
+            write!(f, ",")?;
+        }
+        first = false;
+        write!(
+            f,
+            "{{\"name\":\"{}\",\"ph\":\"{}\",\"ts\":{},\"pid\":{},\"tid\":0}}",
+            name, ph, e.ts, pid
+        )?;
+        if e.kind == GcEvent::Start {
+            write!(
+                f,
+                ",{{\"name\":\"heap\",\"ph\":\"C\",\"ts\":{},\"pid\":{},\"tid\":0,\
+                 \"args\":{{\"bytes allocated\":{},\"finalizers run\":{}}}}}",
+                e.ts, pid, e.allocated, e.finalized
+            )?;
+        }
+    }
+    writeln!(f, "]}}")?;
+    f.flush()
+}
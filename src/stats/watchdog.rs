@@ -0,0 +1,92 @@
+//! Warning when finalizers aren't keeping up.
+//!
+//! A finalization backlog grows silently: nothing fails, memory just isn't
+//! reclaimed. The watchdog polls `pending_finalizers` on a background thread
+//! and reports when it crosses a threshold.
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::pending_finalizers;
+use crate::GcAllocator;
+
+/// Watches the finalization backlog until dropped.
+pub struct Watchdog {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Every `interval`, check the number of pending finalizers, and print a
+    /// warning to stderr when it exceeds `threshold`.
+    pub fn start(threshold: usize, interval: Duration) -> Watchdog {
+        Watchdog::start_with(threshold, interval, |pending| {
+            eprintln!(
+                "libgc: {} finalizers are waiting to run; is anything calling \
+                 GcAllocator::invoke_finalizers?",
+                pending
+            )
+        })
+    }
+
+    /// As `start`, but calls `on_exceeded` with the number of pending
+    /// finalizers instead of printing a warning. It is called once each time
+    /// the backlog rises above `threshold`, not on every check while it stays
+    /// there.
+    pub fn start_with<F>(threshold: usize, interval: Duration, on_exceeded: F) -> Watchdog
+    where
+        F: Fn(usize) + Send + 'static,
+    {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = Arc::clone(&stop);
+        GcAllocator::allow_register_threads();
+        let thread = thread::spawn(move || {
+            // `on_exceeded` may allocate, so the thread must be known to the
+            // collector. Nothing it allocates lives above this frame.
+            let mut base = 0usize;
+            let mut stack_base = &mut base as *mut usize as *mut u8;
+            let registered =
+                unsafe { GcAllocator::register_thread(&mut stack_base as *mut _ as *mut u8) };
+            watch(&thread_stop, threshold, interval, on_exceeded);
+            if registered {
+                unsafe { GcAllocator::unregister_thread() };
+            }
+        });
+        Watchdog {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+fn watch(
+    stop: &(Mutex<bool>, Condvar),
+    threshold: usize,
+    interval: Duration,
+    on_exceeded: impl Fn(usize),
+) {
+    let (stopped, cvar) = stop;
+    let mut exceeded = false;
+    let mut stopped = stopped.lock().unwrap();
+    while !*stopped {
+        let pending = pending_finalizers();
+        if pending > threshold && !exceeded {
+            on_exceeded(pending);
+        }
+        exceeded = pending > threshold;
+        stopped = cvar.wait_timeout(stopped, interval).unwrap().0;
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (stopped, cvar) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        cvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
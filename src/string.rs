@@ -0,0 +1,157 @@
+//! Immutable strings on the GC heap.
+//!
+//! Strings never contain pointers, so their bytes are allocated with
+//! `GcAlloc::alloc_atomic`: the collector never scans them while marking,
+//! which matters for string-heavy heaps. `GcString` is a `Gc<str>` with the
+//! conversions and trait impls a runtime's string type usually needs.
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+use crate::{display::GcStringBuilder, Gc};
+
+/// A string whose bytes are in a GC block which is never scanned. Like a
+/// `Gc`, it is `Copy`, and copying it never copies the bytes.
+#[derive(Copy, Clone)]
+pub struct GcString(Gc<str>);
+
+impl GcString {
+    /// An empty string.
+    pub fn new() -> Self {
+        GcString::from("")
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The string as a `Gc<str>`, which is the same block.
+    pub fn into_gc(self) -> Gc<str> {
+        self.0
+    }
+
+    /// Whether `a` and `b` are the same block, rather than equal strings.
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Gc::ptr_eq(&a.0, &b.0)
+    }
+}
+
+impl Gc<str> {
+    /// Copy `s` into a block which is never scanned.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        Gc::from(s)
+    }
+}
+
+impl Default for GcString {
+    fn default() -> Self {
+        GcString::new()
+    }
+}
+
+impl From<&str> for GcString {
+    fn from(s: &str) -> Self {
+        GcString(Gc::from_str(s))
+    }
+}
+
+/// The bytes are copied, as the `String`'s buffer isn't on the GC heap.
+impl From<String> for GcString {
+    fn from(s: String) -> Self {
+        GcString::from(s.as_str())
+    }
+}
+
+impl From<Gc<str>> for GcString {
+    fn from(s: Gc<str>) -> Self {
+        GcString(s)
+    }
+}
+
+/// The builder's buffer becomes the string, without a copy.
+impl From<GcStringBuilder> for GcString {
+    fn from(sb: GcStringBuilder) -> Self {
+        GcString(sb.finish())
+    }
+}
+
+impl From<GcString> for String {
+    fn from(s: GcString) -> Self {
+        String::from(s.as_str())
+    }
+}
+
+impl Deref for GcString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for GcString {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl Borrow<str> for GcString {
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+
+impl PartialEq for GcString {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for GcString {}
+
+impl PartialEq<str> for GcString {
+    fn eq(&self, other: &str) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<&str> for GcString {
+    fn eq(&self, other: &&str) -> bool {
+        **self == **other
+    }
+}
+
+impl PartialOrd for GcString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for GcString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl Hash for GcString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl fmt::Debug for GcString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl fmt::Display for GcString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,129 @@
+//! `Gc`s with tags in their low bits.
+//!
+//! VMs often keep a few bits of type information in the low bits of object
+//! pointers. The collector is built to ignore the bits cleared by
+//! `GcAllocator::POINTER_MASK` when it looks at a candidate pointer, so a
+//! tagged pointer still keeps its object alive. `TaggedGc` checks that its
+//! tags fit in those bits, at compile time, rather than relying on a
+//! hand-rolled transmute which would silently break if the mask changed.
+use std::{fmt, marker::PhantomData, ptr::NonNull};
+
+use crate::Gc;
+#[cfg(feature = "standalone")]
+use crate::GcAllocator;
+
+/// The number of low bits of a pointer which the collector ignores, and
+/// which are therefore free for tags.
+#[cfg(feature = "standalone")]
+pub const TAG_BITS: u32 = (!GcAllocator::POINTER_MASK).count_ones();
+
+/// rustgc's collector is built by the same `build.rs`, which only masks off
+/// the alignment bits of a word.
+#[cfg(not(feature = "standalone"))]
+pub const TAG_BITS: u32 = std::mem::align_of::<usize>().trailing_zeros();
+
+/// A `Gc<T>` with a `BITS`-bit tag stored in its low bits. It is a single
+/// word, and is scanned like a `Gc`.
+///
+/// Using a `TaggedGc` whose `BITS` is greater than `TAG_BITS` fails to
+/// compile.
+pub struct TaggedGc<T: Send, const BITS: u32> {
+    bits: NonNull<u8>,
+    _phantom: PhantomData<Gc<T>>,
+}
+
+unsafe impl<T: Send, const BITS: u32> Send for TaggedGc<T, BITS> {}
+unsafe impl<T: Send + Sync, const BITS: u32> Sync for TaggedGc<T, BITS> {}
+
+impl<T: Send, const BITS: u32> TaggedGc<T, BITS> {
+    const MASK: usize = {
+        assert!(
+            BITS <= TAG_BITS,
+            "tags must fit in the bits the collector ignores"
+        );
+        (1 << BITS) - 1
+    };
+
+    /// # Panics
+    ///
+    /// If `tag` doesn't fit in `BITS` bits, or `gc` doesn't point to the
+    /// start of a block (e.g. it is an interior pointer from `Gc::from_raw`)
+    /// and so may have some of those bits set already.
+    pub fn new(gc: Gc<T>, tag: usize) -> Self {
+        let addr = Gc::into_raw(gc) as usize;
+        assert_eq!(addr & Self::MASK, 0, "the Gc isn't aligned for tagging");
+        let mut tagged = TaggedGc {
+            bits: unsafe { NonNull::new_unchecked(addr as *mut u8) },
+            _phantom: PhantomData,
+        };
+        tagged.set_tag(tag);
+        tagged
+    }
+
+    pub fn tag(&self) -> usize {
+        self.bits.as_ptr() as usize & Self::MASK
+    }
+
+    /// # Panics
+    ///
+    /// If `tag` doesn't fit in `BITS` bits.
+    pub fn set_tag(&mut self, tag: usize) {
+        assert!(
+            tag <= Self::MASK,
+            "tag {} doesn't fit in {} bits",
+            tag,
+            BITS
+        );
+        let addr = (self.bits.as_ptr() as usize & !Self::MASK) | tag;
+        self.bits = unsafe { NonNull::new_unchecked(addr as *mut u8) };
+    }
+
+    /// The `Gc` without its tag.
+    pub fn untagged(&self) -> Gc<T> {
+        Gc::from_raw((self.bits.as_ptr() as usize & !Self::MASK) as *const T)
+    }
+
+    /// Whether `a` and `b` point to the same object, whatever their tags.
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Gc::ptr_eq(&a.untagged(), &b.untagged())
+    }
+}
+
+/// The `Gc` with a tag of 0.
+impl<T: Send, const BITS: u32> From<Gc<T>> for TaggedGc<T, BITS> {
+    fn from(gc: Gc<T>) -> Self {
+        TaggedGc::new(gc, 0)
+    }
+}
+
+impl<T: Send, const BITS: u32> From<TaggedGc<T, BITS>> for Gc<T> {
+    fn from(tagged: TaggedGc<T, BITS>) -> Self {
+        tagged.untagged()
+    }
+}
+
+impl<T: Send, const BITS: u32> Copy for TaggedGc<T, BITS> {}
+
+impl<T: Send, const BITS: u32> Clone for TaggedGc<T, BITS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+/// Equal if both the object and the tag are the same.
+impl<T: Send, const BITS: u32> PartialEq for TaggedGc<T, BITS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits
+    }
+}
+
+impl<T: Send, const BITS: u32> Eq for TaggedGc<T, BITS> {}
+
+impl<T: Send, const BITS: u32> fmt::Debug for TaggedGc<T, BITS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedGc")
+            .field("ptr", &Gc::as_ptr(&self.untagged()))
+            .field("tag", &self.tag())
+            .finish()
+    }
+}
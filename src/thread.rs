@@ -0,0 +1,191 @@
+//! Registering threads with the collector.
+//!
+//! The collector only scans the stacks of threads it knows about, and a
+//! thread which allocates without being registered can crash it. Threads
+//! spawned with `spawn` are registered for their whole lifetime, and a
+//! `GcThreadGuard` registers the current thread for as long as it is held.
+//!
+//! Threads created by foreign libraries (e.g. audio callbacks, or a C
+//! library's thread pool) call into Rust without ever being registered, so
+//! FFI entry points which may be reached on such threads should start by
+//! calling `ensure_registered`.
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    any::Any,
+    cell::Cell,
+    marker::PhantomData,
+    ptr,
+    thread::{self, JoinHandle},
+};
+
+use crate::{Gc, GcAllocator, ALLOCATOR};
+
+thread_local! {
+    static REGISTERED: Cell<bool> = Cell::new(false);
+    static GUARD: Unregister = Unregister;
+}
+
+/// Unregisters the thread when it exits, if `ensure_registered` registered
+/// it.
+struct Unregister;
+
+impl Drop for Unregister {
+    fn drop(&mut self) {
+        unsafe { GcAllocator::unregister_thread() };
+        let _ = REGISTERED.try_with(|r| r.set(false));
+    }
+}
+
+/// Register the current thread with the collector if it isn't already. This
+/// is cheap once the thread is registered. A thread registered here is
+/// unregistered when it exits.
+///
+/// `GcAllocator::allow_register_threads` must have been called (by a thread
+/// which is already registered, e.g. the main thread) before this is first
+/// called on another thread.
+///
+/// # Panics
+///
+/// If the collector fails to register the thread.
+#[inline]
+pub fn ensure_registered() {
+    if !REGISTERED.with(Cell::get) {
+        register();
+    }
+}
+
+#[cold]
+fn register() {
+    if !GcAllocator::thread_registered() {
+        assert!(
+            GcAllocator::register_current_thread(),
+            "failed to register thread with the collector"
+        );
+        GUARD.with(|_| ());
+    }
+    REGISTERED.with(|r| r.set(true));
+}
+
+/// Registers the current thread with the collector until dropped, using the
+/// collector's own estimate of where the thread's stack begins. If the
+/// thread is already registered, the guard does nothing.
+pub struct GcThreadGuard {
+    registered: bool,
+    /// The guard unregisters the thread which created it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl GcThreadGuard {
+    /// `GcAllocator::allow_register_threads` must have been called (by a
+    /// thread which is already registered) first, as `spawn` does.
+    ///
+    /// # Panics
+    ///
+    /// If the collector hasn't been initialized, or fails to register the
+    /// thread.
+    pub fn new() -> GcThreadGuard {
+        assert_initialized();
+        let registered = !GcAllocator::thread_registered();
+        if registered {
+            assert!(
+                GcAllocator::register_current_thread(),
+                "failed to register thread with the collector"
+            );
+        }
+        GcThreadGuard {
+            registered,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl GcThreadGuard {
+    /// Unregister the thread, as dropping the guard does, but first make
+    /// `roots` roots of the collector. A thread which is about to exit can
+    /// use this for objects which it has passed to other threads through
+    /// memory the collector doesn't scan (e.g. a channel whose buffer was
+    /// malloced), and which would otherwise only be kept alive by its stack.
+    ///
+    /// The roots are kept alive until the returned `Handoff` is dropped,
+    /// which the receiving thread should do once it holds references of its
+    /// own.
+    pub fn handoff(self, roots: &[Gc<dyn Any + Send>]) -> Handoff {
+        let layout = Layout::array::<*const u8>(roots.len()).unwrap();
+        let mut block = ptr::null_mut();
+        if layout.size() > 0 {
+            // The block is uncollectable, so it is scanned until freed.
+            block = unsafe { ALLOCATOR.alloc(layout) as *mut *const u8 };
+            assert!(!block.is_null(), "out of memory");
+            for (i, root) in roots.iter().enumerate() {
+                unsafe { block.add(i).write(Gc::as_ptr(root) as *const u8) };
+            }
+        }
+        drop(self);
+        Handoff { block, layout }
+    }
+}
+
+/// Roots handed off by `GcThreadGuard::handoff`, which are kept alive until
+/// this is dropped.
+#[must_use = "the roots are released as soon as the Handoff is dropped"]
+pub struct Handoff {
+    /// An uncollectable block holding the roots, or null if there are none.
+    block: *mut *const u8,
+    layout: Layout,
+}
+
+unsafe impl Send for Handoff {}
+unsafe impl Sync for Handoff {}
+
+impl Drop for Handoff {
+    fn drop(&mut self) {
+        if !self.block.is_null() {
+            unsafe { ALLOCATOR.dealloc(self.block as *mut u8, self.layout) };
+        }
+    }
+}
+
+impl Default for GcThreadGuard {
+    fn default() -> Self {
+        GcThreadGuard::new()
+    }
+}
+
+impl Drop for GcThreadGuard {
+    fn drop(&mut self) {
+        if self.registered {
+            unsafe { GcAllocator::unregister_thread() };
+            let _ = REGISTERED.try_with(|r| r.set(false));
+        }
+    }
+}
+
+/// Spawn a thread, as `std::thread::spawn` does, which is registered with
+/// the collector while it runs `f`. The value `f` returns is only kept alive
+/// by the collector once it has been handed to the `JoinHandle`, so it
+/// shouldn't be the only reference to a `Gc`.
+///
+/// # Panics
+///
+/// If the collector hasn't been initialized. The new thread panics if the
+/// collector fails to register it.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    assert_initialized();
+    GcAllocator::allow_register_threads();
+    thread::spawn(move || {
+        let _guard = GcThreadGuard::new();
+        f()
+    })
+}
+
+fn assert_initialized() {
+    assert!(
+        GcAllocator::is_initialized(),
+        "the collector must be initialized (e.g. with GcAllocator::init, or by \
+         allocating a Gc on the main thread) before other threads are registered"
+    );
+}
@@ -0,0 +1,334 @@
+//! Short-lived formatted strings, e.g. for error messages.
+//!
+//! A runtime's error paths format a lot of strings which are looked at once
+//! and thrown away. `format!` mallocs each of them, and formatting into a
+//! `GcString` leaves each of them as garbage for the collector. `gc_format!`
+//! instead writes into a per-thread arena, whose chunks are allocated outside
+//! the GC heap and freed when the thread exits, as nothing the collector
+//! scans points to them. The strings live until the `scope` they were
+//! formatted in ends, at which point their space is reused.
+//!
+//! Runtimes usually open a scope at their safepoints, e.g. around each
+//! iteration of the interpreter loop:
+//!
+//! ```ignore
+//! libgc::transient::scope(|s| {
+//!     let msg = gc_format!(s, "expected {} arguments, got {}", want, got);
+//!     report(msg);
+//! });
+//! ```
+//!
+//! A string which must outlive its scope can be copied into a `GcString`.
+use std::{
+    alloc::{self, Layout},
+    cell::{Cell, RefCell},
+    cmp,
+    fmt::{self, Write},
+    marker::PhantomData,
+    ptr, slice, str,
+};
+
+/// The size of a thread's first chunk. A chunk which fills up is replaced by
+/// one twice the size.
+const FIRST_CHUNK_BYTES: usize = 4096;
+/// Chunks larger than this are freed when the outermost scope ends, so that
+/// one huge message doesn't pin memory.
+const MAX_KEPT_CHUNK_BYTES: usize = 64 * 1024;
+
+#[derive(Copy, Clone)]
+struct Arena {
+    chunk: *mut u8,
+    cap: usize,
+    used: usize,
+    /// The number of scopes open on this thread.
+    depth: usize,
+    /// The depth of the outermost scope which has had a string written to it
+    /// while a deeper scope was open, e.g. by a `Display` impl which opened
+    /// one. Scopes deeper than it can't recycle their space, as it may be
+    /// interleaved with that string.
+    pinned: usize,
+}
+
+struct ThreadArena {
+    arena: Cell<Arena>,
+    /// Chunks which have been replaced by a bigger one, but may still hold
+    /// strings of open scopes. They are freed when the outermost scope ends.
+    retired: RefCell<Vec<(*mut u8, usize)>>,
+}
+
+impl ThreadArena {
+    fn get(&self) -> Arena {
+        self.arena.get()
+    }
+
+    fn set(&self, arena: Arena) {
+        self.arena.set(arena)
+    }
+
+    fn free_retired(&self) {
+        for (chunk, cap) in self.retired.borrow_mut().drain(..) {
+            unsafe { free_chunk(chunk, cap) };
+        }
+    }
+}
+
+impl Drop for ThreadArena {
+    fn drop(&mut self) {
+        self.free_retired();
+        let arena = self.arena.get();
+        if !arena.chunk.is_null() {
+            unsafe { free_chunk(arena.chunk, arena.cap) };
+        }
+    }
+}
+
+thread_local! {
+    static ARENA: ThreadArena = ThreadArena {
+        arena: Cell::new(Arena {
+            chunk: ptr::null_mut(),
+            cap: 0,
+            used: 0,
+            depth: 0,
+            pinned: usize::MAX,
+        }),
+        retired: RefCell::new(Vec::new()),
+    };
+}
+
+/// Chunks aren't allocated on the GC heap: they are only referenced from
+/// thread-locals, which the collector doesn't scan.
+fn alloc_chunk(cap: usize) -> *mut u8 {
+    let layout = Layout::array::<u8>(cap).unwrap();
+    let chunk = unsafe { alloc::alloc(layout) };
+    if chunk.is_null() {
+        alloc::handle_alloc_error(layout);
+    }
+    chunk
+}
+
+unsafe fn free_chunk(chunk: *mut u8, cap: usize) {
+    alloc::dealloc(chunk, Layout::array::<u8>(cap).unwrap());
+}
+
+/// A region of the calling thread's arena, as passed to `scope`'s closure.
+/// Strings formatted with `gc_format!` borrow it, so can't outlive it.
+pub struct TransientScope {
+    depth: usize,
+    /// Where the arena was up to when the scope was opened.
+    chunk: *mut u8,
+    mark: usize,
+    /// Scopes belong to their thread's arena.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Open a scope for `gc_format!` and call `f` with it. Everything formatted
+/// in the scope is recycled when `f` returns (or panics).
+///
+/// Scopes nest, but strings can only be formatted in the innermost one:
+/// otherwise an inner scope would recycle them when it ended.
+pub fn scope<F, R>(f: F) -> R
+where
+    F: for<'s> FnOnce(&'s TransientScope) -> R,
+{
+    let arena = ARENA.with(|a| {
+        let mut arena = a.get();
+        arena.depth += 1;
+        a.set(arena);
+        arena
+    });
+    let scope = TransientScope {
+        depth: arena.depth,
+        chunk: arena.chunk,
+        mark: arena.used,
+        _not_send: PhantomData,
+    };
+    f(&scope)
+}
+
+impl TransientScope {
+    /// Format `args` into the arena. Usually called through `gc_format!`.
+    ///
+    /// # Panics
+    ///
+    /// If `self` isn't the innermost open scope, or a `Display` impl returns
+    /// an error.
+    pub fn format<'s>(&'s self, args: fmt::Arguments<'_>) -> &'s str {
+        assert_eq!(
+            ARENA.with(|a| a.get().depth),
+            self.depth,
+            "gc_format! must be given the innermost TransientScope"
+        );
+        let mut w = Writer {
+            depth: self.depth,
+            start: ptr::null_mut(),
+            len: 0,
+        };
+        w.write_fmt(args)
+            .expect("a Display implementation returned an error");
+        if w.len == 0 {
+            return "";
+        }
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(w.start, w.len)) }
+    }
+}
+
+impl Drop for TransientScope {
+    fn drop(&mut self) {
+        // The thread's arena may already be gone if it is exiting.
+        let _ = ARENA.try_with(|a| {
+            let mut arena = a.get();
+            arena.depth = self.depth - 1;
+            if arena.pinned >= self.depth {
+                // A chunk allocated since the scope was opened only holds
+                // the scope's own strings, so can be reused from the start.
+                arena.used = if arena.chunk == self.chunk {
+                    self.mark
+                } else {
+                    0
+                };
+            }
+            if arena.pinned >= arena.depth {
+                arena.pinned = usize::MAX;
+            }
+            if arena.depth == 0 {
+                // No strings are left, so no chunk is still in use.
+                a.free_retired();
+                if arena.cap > MAX_KEPT_CHUNK_BYTES {
+                    unsafe { free_chunk(arena.chunk, arena.cap) };
+                    arena.chunk = ptr::null_mut();
+                    arena.cap = 0;
+                }
+            }
+            a.set(arena);
+        });
+    }
+}
+
+/// Appends a string to the arena, one `write_str` at a time.
+struct Writer {
+    /// The depth of the scope being formatted into.
+    depth: usize,
+    start: *mut u8,
+    len: usize,
+}
+
+impl Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.is_empty() {
+            return Ok(());
+        }
+        let mut arena = ARENA.with(ThreadArena::get);
+        if arena.depth > self.depth {
+            arena.pinned = cmp::min(arena.pinned, self.depth);
+        }
+        // A `Display` impl may itself have opened a scope and formatted
+        // strings since the last write, in which case this string no longer
+        // ends where the arena's free space starts, and must be moved.
+        let mut in_place = self.len > 0
+            && arena.chunk.wrapping_add(arena.used) == self.start.wrapping_add(self.len);
+        let needed = if in_place {
+            s.len()
+        } else {
+            self.len + s.len()
+        };
+        if arena.cap - arena.used < needed {
+            // Open scopes may still have strings in the old chunk, so it is
+            // kept until the outermost scope ends.
+            if !arena.chunk.is_null() {
+                ARENA.with(|a| a.retired.borrow_mut().push((arena.chunk, arena.cap)));
+            }
+            let cap = cmp::max(
+                FIRST_CHUNK_BYTES,
+                cmp::max(arena.cap * 2, self.len + s.len()),
+            );
+            arena.chunk = alloc_chunk(cap);
+            arena.cap = cap;
+            arena.used = 0;
+            in_place = false;
+        }
+        unsafe {
+            if !in_place {
+                let dst = arena.chunk.add(arena.used);
+                if self.len > 0 {
+                    ptr::copy_nonoverlapping(self.start, dst, self.len);
+                }
+                self.start = dst;
+                arena.used += self.len;
+            }
+            ptr::copy_nonoverlapping(s.as_ptr(), arena.chunk.add(arena.used), s.len());
+        }
+        arena.used += s.len();
+        self.len += s.len();
+        ARENA.with(|a| a.set(arena));
+        Ok(())
+    }
+}
+
+/// Format a string into a `TransientScope`, e.g. `gc_format!(s, "{}: {}",
+/// name, err)`. Returns a `&str` which lives as long as the scope.
+#[macro_export]
+macro_rules! gc_format {
+    ($scope:expr, $($arg:tt)*) => {
+        $crate::transient::TransientScope::format($scope, format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        scope(|s| {
+            let a = gc_format!(s, "{} + {}", 1, 2);
+            let b = gc_format!(s, "{:?}", "b");
+            assert_eq!(a, "1 + 2");
+            assert_eq!(b, "\"b\"");
+            assert_eq!(gc_format!(s, "{}", ""), "");
+        });
+    }
+
+    #[test]
+    fn test_recycled() {
+        let first = scope(|s| gc_format!(s, "{}", 1).as_ptr() as usize);
+        let second = scope(|s| gc_format!(s, "{}", 2).as_ptr() as usize);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_nested() {
+        scope(|outer| {
+            let a = gc_format!(outer, "{}", "outer");
+            scope(|inner| {
+                gc_format!(inner, "{}", "x".repeat(3 * FIRST_CHUNK_BYTES));
+            });
+            let b = gc_format!(outer, "{}", "again");
+            assert_eq!((a, b), ("outer", "again"));
+        });
+    }
+
+    #[test]
+    fn test_display_opens_scope() {
+        struct Nested;
+
+        impl fmt::Display for Nested {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a")?;
+                scope(|s| f.write_str(gc_format!(s, "{}", "b".repeat(FIRST_CHUNK_BYTES))))?;
+                f.write_str("c")
+            }
+        }
+
+        scope(|s| {
+            let n = gc_format!(s, "{}{}", Nested, Nested);
+            let expected = format!("a{}c", "b".repeat(FIRST_CHUNK_BYTES)).repeat(2);
+            assert_eq!(n, expected);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "innermost")]
+    fn test_outer_scope_panics() {
+        scope(|outer| scope(|_| gc_format!(outer, "{}", 1).len()));
+    }
+}
@@ -0,0 +1,128 @@
+use std::any::Any;
+
+use crate::Gc;
+
+/// A dynamically typed value, as found in the interpreters of dynamic
+/// languages.
+///
+/// Immediate values (nil, booleans, integers, and floats) are stored inline
+/// in the enum, so only values which genuinely need to live on the heap cost
+/// a GC allocation. Heap values are stored as `Gc<dyn Any + Send>` and can be
+/// recovered with `as_gc`.
+#[derive(Copy, Clone, Debug)]
+pub enum GcValue {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Object(Gc<dyn Any + Send>),
+}
+
+impl GcValue {
+    /// Allocate `v` on the GC heap and return a value referring to it.
+    pub fn object<T: Any + Send>(v: T) -> Self {
+        GcValue::Object(Gc::new(v))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, GcValue::Nil)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            GcValue::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            GcValue::Int(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            GcValue::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    /// Returns the heap value as a `Gc<T>` if this is an object of type `T`.
+    pub fn as_gc<T: Any + Send>(&self) -> Option<Gc<T>> {
+        match self {
+            GcValue::Object(o) => o.downcast::<T>().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Default for GcValue {
+    fn default() -> Self {
+        GcValue::Nil
+    }
+}
+
+/// Immediate values are compared by value, objects by identity.
+impl PartialEq for GcValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (GcValue::Nil, GcValue::Nil) => true,
+            (GcValue::Bool(a), GcValue::Bool(b)) => a == b,
+            (GcValue::Int(a), GcValue::Int(b)) => a == b,
+            (GcValue::Float(a), GcValue::Float(b)) => a == b,
+            (GcValue::Object(a), GcValue::Object(b)) => Gc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl From<bool> for GcValue {
+    fn from(b: bool) -> Self {
+        GcValue::Bool(b)
+    }
+}
+
+impl From<i64> for GcValue {
+    fn from(i: i64) -> Self {
+        GcValue::Int(i)
+    }
+}
+
+impl From<f64> for GcValue {
+    fn from(f: f64) -> Self {
+        GcValue::Float(f)
+    }
+}
+
+impl<T: Any + Send> From<Gc<T>> for GcValue {
+    fn from(gc: Gc<T>) -> Self {
+        GcValue::Object(gc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        let vals = [
+            GcValue::Nil,
+            GcValue::from(true),
+            GcValue::from(42),
+            GcValue::from(1.5),
+            GcValue::object(String::from("s")),
+        ];
+        assert!(vals[0].is_nil());
+        assert_eq!(vals[1].as_bool(), Some(true));
+        assert_eq!(vals[2].as_int(), Some(42));
+        assert_eq!(vals[2].as_float(), None);
+        assert_eq!(vals[3].as_float(), Some(1.5));
+        assert_eq!(*vals[4].as_gc::<String>().unwrap(), "s");
+        assert!(vals[4].as_gc::<u8>().is_none());
+        assert_eq!(vals[4], vals[4]);
+        assert_ne!(vals[4], GcValue::object(String::from("s")));
+    }
+}
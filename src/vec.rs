@@ -0,0 +1,251 @@
+//! Growable vectors whose buffers are on the GC heap.
+//!
+//! A `Vec` inside a `Gc` gets its buffer from the global allocator. Unless
+//! that is `GcAllocator`, the collector can't see the `Gc`s in the buffer,
+//! and even if it is, the buffer is never freed by the collector, only when
+//! the `Vec` is dropped by a finalizer. `GcVec`'s buffers are ordinary GC
+//! blocks: a buffer which has been outgrown is simply left for the collector
+//! to reclaim, and a full vector can become a `Gc<[T]>` without copying.
+use std::{
+    alloc::Layout,
+    fmt,
+    iter::FromIterator,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut, Range},
+    ptr::{self, NonNull},
+    slice,
+};
+
+use crate::{alloc, Gc, ALLOCATOR};
+
+/// A growable vector whose buffer is allocated by the collector.
+///
+/// The buffer is only scanned if `T` may contain GC pointers (i.e. isn't
+/// `NoGcPointers`), and then conservatively: libgc has no type layouts to
+/// build a precise descriptor from. Slots which are vacated by `pop` and
+/// friends are zeroed, so that removed elements aren't kept alive.
+///
+/// Like `Vec`, dropping a `GcVec` drops its elements. The buffer must be
+/// reachable by the collector for as long as the `GcVec` is used, which it
+/// is when the `GcVec` is on the stack, in a `Gc`, or in memory from
+/// `GcAllocator`.
+pub struct GcVec<T> {
+    buf: NonNull<T>,
+    len: usize,
+    cap: usize,
+}
+
+unsafe impl<T: Send> Send for GcVec<T> {}
+unsafe impl<T: Sync> Sync for GcVec<T> {}
+
+impl<T> GcVec<T> {
+    pub fn new() -> Self {
+        GcVec {
+            buf: NonNull::dangling(),
+            len: 0,
+            cap: 0,
+        }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        let mut v = GcVec::new();
+        v.reserve(cap);
+        v
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Ensure there is room for at least `additional` more elements.
+    ///
+    /// # Panics
+    ///
+    /// If the new capacity would overflow `isize::MAX` bytes.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.len.checked_add(additional).expect("capacity overflow");
+        if needed <= self.cap {
+            return;
+        }
+        let cap = needed.max(self.cap * 2).max(4);
+        let layout = Layout::array::<T>(cap).expect("capacity overflow");
+        let buf = alloc::alloc_for::<T, _>(&ALLOCATOR, layout)
+            .unwrap()
+            .cast::<T>();
+        unsafe { ptr::copy_nonoverlapping(self.buf.as_ptr(), buf.as_ptr(), self.len) };
+        // The old buffer is left for the collector, but it may stay
+        // reachable through stale pointers for a while, so it mustn't keep
+        // the moved elements alive as well.
+        self.zero(0..self.len);
+        self.buf = buf;
+        self.cap = cap;
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.len == self.cap {
+            self.reserve(1);
+        }
+        unsafe { self.buf.as_ptr().add(self.len).write(value) };
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+        let value = unsafe { self.buf.as_ptr().add(self.len).read() };
+        self.zero(self.len..self.len + 1);
+        Some(value)
+    }
+
+    /// Drop the elements from `len` onwards, if there are any.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let old_len = self.len;
+        // Shorten first, so that a panicking `Drop` impl leaves the vector
+        // valid (leaking the rest) rather than dropping elements twice.
+        self.len = len;
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                self.buf.as_ptr().add(len),
+                old_len - len,
+            ))
+        };
+        self.zero(len..old_len);
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.buf.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.buf.as_ptr(), self.len) }
+    }
+
+    /// Zero the slots in `range` if the buffer is scanned.
+    fn zero(&mut self, range: Range<usize>) {
+        if alloc::needs_scanning::<T>() && self.cap > 0 {
+            unsafe { ptr::write_bytes(self.buf.as_ptr().add(range.start), 0, range.len()) };
+        }
+    }
+}
+
+impl<T: Send> GcVec<T> {
+    /// Return the elements as a `Gc<[T]>`. This doesn't copy: the vector's
+    /// buffer becomes the slice, and its elements are dropped once it is
+    /// unreachable.
+    pub fn into_gc_slice(self) -> Gc<[T]> {
+        if self.cap == 0 {
+            return unsafe { Gc::<[T]>::new_slice_uninit(0).assume_init() };
+        }
+        let ptr = ptr::slice_from_raw_parts(self.buf.as_ptr() as *const MaybeUninit<T>, self.len);
+        std::mem::forget(self);
+        unsafe { Gc::from_raw(ptr).assume_init() }
+    }
+}
+
+impl<T: Send> From<GcVec<T>> for Gc<[T]> {
+    fn from(v: GcVec<T>) -> Self {
+        v.into_gc_slice()
+    }
+}
+
+impl<T> Drop for GcVec<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.as_mut_slice()) };
+    }
+}
+
+impl<T> Deref for GcVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T> DerefMut for GcVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T> Default for GcVec<T> {
+    fn default() -> Self {
+        GcVec::new()
+    }
+}
+
+impl<T: Clone> Clone for GcVec<T> {
+    fn clone(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T> Extend<T> for GcVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T> FromIterator<T> for GcVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut v = GcVec::new();
+        v.extend(iter);
+        v
+    }
+}
+
+impl<T: Clone> From<&[T]> for GcVec<T> {
+    fn from(s: &[T]) -> Self {
+        s.iter().cloned().collect()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a GcVec<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut GcVec<T> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T: PartialEq> PartialEq for GcVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq> Eq for GcVec<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for GcVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
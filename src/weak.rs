@@ -0,0 +1,83 @@
+//! Weak references to GC objects.
+use std::{alloc::Layout, fmt, marker::PhantomData, ptr::NonNull};
+
+use crate::{alloc::GcAlloc, Gc, GcAllocator, ALLOCATOR};
+
+/// A reference to a GC object which doesn't keep it alive.
+///
+/// `GcWeak` is backed by the collector's disappearing links: the address of
+/// the object is stored in a small GC block which the collector never scans,
+/// and which it clears as soon as it finds the object unreachable (before the
+/// object is finalized). `upgrade` returns `None` from then on.
+///
+/// Like `Gc`, `GcWeak` is `Copy`. The link block is collected once no
+/// `GcWeak` refers to it.
+pub struct GcWeak<T: Send> {
+    link: NonNull<usize>,
+    _phantom: PhantomData<T>,
+}
+
+/// An alternative name for `GcWeak`, matching `std::rc::Weak`.
+pub type WeakGc<T> = GcWeak<T>;
+
+unsafe impl<T: Send + Sync> Sync for GcWeak<T> {}
+unsafe impl<T: Send> Send for GcWeak<T> {}
+
+impl<T: Send> GcWeak<T> {
+    pub(crate) fn new(gc: Gc<T>) -> Self {
+        let obj = Gc::into_raw(gc) as *const u8;
+        let link = GcAlloc::alloc_atomic(&ALLOCATOR, Layout::new::<usize>())
+            .unwrap()
+            .cast::<usize>();
+        unsafe {
+            // The link block isn't scanned, but hiding the pointer means it
+            // doesn't keep the object alive even if the block kind differs
+            // under rustgc.
+            link.as_ptr().write(!(obj as usize));
+            GcAllocator::register_disappearing_link(link.as_ptr() as *mut *mut u8, obj);
+        }
+        GcWeak {
+            link,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns a `Gc` to the object, or `None` if it has been collected.
+    pub fn upgrade(&self) -> Option<Gc<T>> {
+        let link = self.link.as_ptr();
+        // The collector may clear the link at any time outside of the
+        // allocation lock, so it must be read, and the pointer revealed so
+        // that this frame keeps the object alive, with the lock held.
+        let obj = GcAllocator::with_alloc_lock(|| match unsafe { link.read_volatile() } {
+            0 => None,
+            hidden => Some(!hidden as *const T),
+        });
+        obj.map(Gc::from_raw)
+    }
+
+    /// Returns true if the object has been collected.
+    pub fn is_dead(&self) -> bool {
+        self.upgrade().is_none()
+    }
+}
+
+impl<T: Send> Gc<T> {
+    /// Create a weak reference to this object.
+    pub fn downgrade(this: &Self) -> GcWeak<T> {
+        GcWeak::new(*this)
+    }
+}
+
+impl<T: Send> Copy for GcWeak<T> {}
+
+impl<T: Send> Clone for GcWeak<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Send> fmt::Debug for GcWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(GcWeak)")
+    }
+}
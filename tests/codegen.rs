@@ -0,0 +1,118 @@
+//! Checks that the operations on `Gc` which interpreters do in their inner
+//! loops compile to the same code as on a `&T` once inlined into another
+//! crate. Each function below is compiled with optimizations against the
+//! libgc rlib this test was built with, and its LLVM IR is checked.
+use std::{env, fs, path::PathBuf, process};
+
+/// The functions to check, and a fragment of IR which each must contain.
+/// None of them may call anything.
+const FUNCTIONS: &[(&str, &str, &str)] = &[
+    (
+        "gc_deref",
+        "pub fn gc_deref(gc: &Gc<usize>) -> usize { **gc }",
+        "load",
+    ),
+    (
+        "gc_clone",
+        "pub fn gc_clone(gc: &Gc<usize>) -> Gc<usize> { gc.clone() }",
+        "load",
+    ),
+    (
+        "gc_ptr_eq",
+        "pub fn gc_ptr_eq(a: &Gc<usize>, b: &Gc<usize>) -> bool { Gc::ptr_eq(a, b) }",
+        "icmp eq",
+    ),
+];
+
+/// The directory holding the rlibs this test was linked against.
+fn deps_dir() -> PathBuf {
+    env::current_exe().unwrap().parent().unwrap().to_owned()
+}
+
+/// The most recently built libgc rlib, which is the one this test was built
+/// against: there may be others, built with different features.
+fn libgc_rlib() -> PathBuf {
+    fs::read_dir(deps_dir())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| {
+            let name = p.file_name().unwrap().to_str().unwrap();
+            name.starts_with("liblibgc-") && name.ends_with(".rlib")
+        })
+        .max_by_key(|p| p.metadata().unwrap().modified().unwrap())
+        .expect("no libgc rlib: build the library first")
+}
+
+/// The body of the function `name` in the LLVM IR `ir`.
+fn body<'a>(ir: &'a str, name: &str) -> &'a str {
+    let start = ir
+        .find(&format!("@{}(", name))
+        .unwrap_or_else(|| panic!("{} isn't in the IR", name));
+    let len = ir[start..].find("\n}").unwrap();
+    &ir[start..start + len]
+}
+
+fn main() {
+    // These features add checks to every dereference.
+    if cfg!(feature = "debug_checks") || cfg!(feature = "teardown_checks") {
+        eprintln!("skipping codegen checks: built with extra dereference checks");
+        return;
+    }
+
+    let dir = env::temp_dir().join(format!("libgc-codegen-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let src = dir.join("hot.rs");
+    let mut code = String::from("use libgc::Gc;\n");
+    for (_, f, _) in FUNCTIONS {
+        code.push_str(&format!("#[no_mangle]\n{}\n", f));
+    }
+    fs::write(&src, code).unwrap();
+
+    let ir_path = dir.join("hot.ll");
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let out = process::Command::new(rustc)
+        .args(&[
+            "--edition",
+            "2018",
+            "--crate-type",
+            "lib",
+            "--emit",
+            "llvm-ir",
+        ])
+        .args(&["-C", "opt-level=3", "-C", "debuginfo=0"])
+        .arg("-L")
+        .arg(format!("dependency={}", deps_dir().display()))
+        .arg("--extern")
+        .arg(format!("libgc={}", libgc_rlib().display()))
+        .arg("-o")
+        .arg(&ir_path)
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let ir = fs::read_to_string(&ir_path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    for (name, _, expected) in FUNCTIONS {
+        eprint!("test {} ... ", name);
+        let body = body(&ir, name);
+        assert!(
+            body.contains(expected),
+            "{} doesn't contain `{}`:\n{}",
+            name,
+            expected,
+            body
+        );
+        let calls = body
+            .lines()
+            .filter(|l| l.contains("call ") || l.contains("invoke "))
+            .filter(|l| !l.contains("@llvm."))
+            .collect::<Vec<_>>();
+        assert!(calls.is_empty(), "{} isn't inlined:\n{}", name, body);
+        eprintln!("ok");
+    }
+}
@@ -0,0 +1,2003 @@
+//! Tests which can be run with a stock rustc, using
+//! `LIBGC_SKIP_GC_TESTS=1 cargo test --features standalone`. Tests which need
+//! compiler support live in `gc_tests/` and are run by lang_tester with the
+//! compiler named by `RUSTGC`; without it, that suite fails unless
+//! `LIBGC_SKIP_GC_TESTS` is set.
+//!
+//! This uses a custom harness: libtest runs each test on a fresh thread which
+//! isn't registered with the collector, so values held on its stack are not
+//! treated as roots. Here, tests run in order on the main thread, and any
+//! threads they spawn register themselves explicitly.
+#![feature(allocator_api)]
+
+use std::{
+    alloc::{Allocator, Layout},
+    any::Any,
+    cell::Cell,
+    hint::black_box,
+    mem::{self, MaybeUninit},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use libgc::{
+    alloc,
+    arena::GcTypedArena,
+    bytes::GcBytes,
+    cache::GcLruCache,
+    callback::CallbackPin,
+    cell::GcCell,
+    config::{CollectionMode, FinalizerPanic},
+    display::GcStringBuilder,
+    dynamic::{DynObject, LayoutInfo},
+    emitter::GcEventEmitter,
+    error::GcError,
+    finalize::FinalizerThread,
+    gc,
+    gc_graph::{GcNode, GcTreeNode},
+    inline_vec::GcInlineVec,
+    mailbox::FinalizerMailbox,
+    persistent::{GcPersistentMap, GcPersistentVec},
+    pool::GcPool,
+    scope::GcScopeChain,
+    string::GcString,
+    tagged::TaggedGc,
+    vec::GcVec,
+    Gc, GcAllocator, GcConfig,
+};
+
+#[global_allocator]
+static ALLOCATOR: GcAllocator = GcAllocator;
+
+static TESTS: &[(&str, fn())] = &[
+    ("new_and_deref", new_and_deref),
+    ("effective_config", effective_config),
+    ("finalizers_run", finalizers_run),
+    ("finalizer_batching", finalizer_batching),
+    ("deterministic_test_mode", deterministic_test_mode),
+    ("finalizer_panics", finalizer_panics),
+    ("finalizer_with_data", finalizer_with_data),
+    ("heap_only_reference_survives", heap_only_reference_survives),
+    ("grow_keeps_finalizer", grow_keeps_finalizer),
+    ("grow_keeps_ordered_finalizer", grow_keeps_ordered_finalizer),
+    ("downcast", downcast),
+    ("errors", errors),
+    #[cfg(feature = "debug_checks")]
+    ("debug_checks", debug_checks),
+    ("cold_objects", cold_objects),
+    ("pool_recycles", pool_recycles),
+    ("pool_drops_released_values", pool_drops_released_values),
+    ("pool_keeps_referents_alive", pool_keeps_referents_alive),
+    ("typed_arena", typed_arena),
+    ("persistent_collections", persistent_collections),
+    ("display_into", display_into),
+    ("weak_and_lru_cache", weak_and_lru_cache),
+    ("caches_recover_capacity", caches_recover_capacity),
+    ("event_emitter", event_emitter),
+    ("graphs", graphs),
+    ("scope_chains", scope_chains),
+    ("inline_vec", inline_vec),
+    ("alloc_observer", alloc_observer),
+    ("disable_finalizers", disable_finalizers),
+    ("ordered_finalizers", ordered_finalizers),
+    ("finalizer_mailbox", finalizer_mailbox),
+    ("finalizer_thread", finalizer_thread),
+    ("callback_pin", callback_pin),
+    ("unscanned_strings", unscanned_strings),
+    ("gc_string", gc_string),
+    ("transient_strings", transient_strings),
+    ("tagged_gcs", tagged_gcs),
+    ("slices", slices),
+    ("gc_bytes", gc_bytes),
+    ("gc_vec", gc_vec),
+    ("gc_cell", gc_cell),
+    (
+        "gc_cell_finalized_while_borrowed",
+        gc_cell_finalized_while_borrowed,
+    ),
+    ("new_cyclic", new_cyclic),
+    ("dynamic_objects", dynamic_objects),
+    ("scan_block", scan_block),
+    ("emergency_dump", emergency_dump),
+    ("verify_heap", verify_heap),
+    ("root_snapshots", root_snapshots),
+    ("false_retention", false_retention),
+    ("freeze", freeze),
+    ("shared_readonly", shared_readonly),
+    ("executor", executor),
+    ("random_dynamic_layouts", random_dynamic_layouts),
+    ("threads_are_scanned", threads_are_scanned),
+    ("atomic_gc", atomic_gc),
+    ("foreign_thread", foreign_thread),
+    ("thread_guards", thread_guards),
+    ("thread_handoff", thread_handoff),
+    ("world_stopped", world_stopped),
+    ("heap_size_tuning", heap_size_tuning),
+    ("fork", fork),
+    #[cfg(feature = "crash_handler")]
+    ("crash_handler", crash_handler),
+    #[cfg(feature = "stats_counters")]
+    ("stats", stats),
+    #[cfg(feature = "stats_counters")]
+    ("pending_finalizers", pending_finalizers),
+    #[cfg(feature = "stats_counters")]
+    ("sampled_types", sampled_types),
+    #[cfg(feature = "stats_profiling")]
+    ("timeline", timeline),
+    // The collector can't leave incremental mode, so this runs after
+    // everything else which collects.
+    ("collection_modes", collection_modes),
+    // This must come last, as no `Gc` can be used once the heap is torn down.
+    ("teardown", teardown),
+];
+
+fn main() {
+    GcConfig::fork_friendly(true);
+    GcAllocator::init();
+    for (name, test) in TESTS {
+        eprint!("test {} ... ", name);
+        test();
+        eprintln!("ok");
+    }
+}
+
+/// Run `f` on a new thread which is registered with the collector for the
+/// duration of `f`.
+fn spawn_registered<F, T>(f: F) -> thread::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    GcAllocator::allow_register_threads();
+    thread::spawn(move || {
+        // Nothing this thread allocates lives above this frame, so the address
+        // of a local is a good enough stack base. The collector expects a
+        // pointer to a `GC_stack_base`, whose only field on x86_64 is the base.
+        let mut base = 0usize;
+        let mut stack_base = &mut base as *mut usize as *mut u8;
+        assert!(unsafe { GcAllocator::register_thread(&mut stack_base as *mut _ as *mut u8) });
+        let ret = f();
+        assert!(unsafe { GcAllocator::unregister_thread() });
+        ret
+    })
+}
+
+fn new_and_deref() {
+    let gc = Gc::new(vec![1, 2, 3]);
+    let copy = gc;
+    assert_eq!(*gc, vec![1, 2, 3]);
+    assert!(Gc::ptr_eq(&gc, &copy));
+
+    // `as_ptr` is the `Gc`'s pointer, not the `Vec`'s.
+    assert_ne!(Gc::as_ptr(&gc) as *const u8, gc.as_ptr() as *const u8);
+    assert!(Gc::ptr_eq(&Gc::from_raw(Gc::as_ptr(&gc)), &gc));
+    assert!(!Gc::ptr_eq(&Gc::dangling(), &gc));
+    assert!(Gc::ptr_eq(&Gc::<Vec<i32>>::dangling(), &Gc::dangling()));
+}
+
+fn effective_config() {
+    let config = GcConfig::effective_config();
+    assert!(config.collector.heap_size > 0);
+    assert!(config.collector.markers >= 1);
+    assert!(config.features.contains(&"standalone"));
+    assert!(config.to_string().contains("features [standalone"));
+}
+
+static NUM_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+struct Counted(usize);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        NUM_DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[inline(never)]
+fn make_garbage(n: usize) {
+    for i in 0..n {
+        black_box(Gc::new(Counted(i)));
+    }
+}
+
+fn finalizers_run() {
+    make_garbage(1000);
+    GcAllocator::force_gc();
+    // The collector is conservative, so stale stack slots may keep a few
+    // objects alive. Some must have been finalized though.
+    assert!(NUM_DROPPED.load(Ordering::SeqCst) > 0);
+}
+
+fn finalizer_batching() {
+    GcConfig::finalizer_batching(true);
+    let before = NUM_DROPPED.load(Ordering::SeqCst);
+    make_garbage(10);
+    // Fewer objects than a batch, so none have been registered yet. Collecting
+    // with `gc::collect` flushes them first.
+    gc::collect();
+    assert!(NUM_DROPPED.load(Ordering::SeqCst) > before);
+
+    // Another thread's buffer is flushed at its first allocation after
+    // `gc::collect`.
+    let before = NUM_DROPPED.load(Ordering::SeqCst);
+    let (main_tx, thread_rx) = std::sync::mpsc::channel();
+    let (thread_tx, main_rx) = std::sync::mpsc::channel();
+    let t = spawn_registered(move || {
+        make_garbage(10);
+        thread_tx.send(()).unwrap();
+        thread_rx.recv().unwrap();
+        black_box(Gc::new(0));
+        thread_tx.send(()).unwrap();
+        // Stay alive, so that the flush isn't down to the thread exiting.
+        thread_rx.recv().unwrap();
+    });
+    main_rx.recv().unwrap();
+    gc::collect();
+    main_tx.send(()).unwrap();
+    main_rx.recv().unwrap();
+    gc::collect();
+    assert!(NUM_DROPPED.load(Ordering::SeqCst) > before);
+    main_tx.send(()).unwrap();
+    t.join().unwrap();
+
+    // Unregistering a finalizer whose registration is still buffered
+    // mustn't be undone when the buffer is flushed.
+    make_unregistered_garbage(10);
+    gc::collect();
+    assert_eq!(NUM_UNREGISTERED_DROPPED.load(Ordering::SeqCst), 0);
+    GcConfig::finalizer_batching(false);
+}
+
+static NUM_UNREGISTERED_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+struct Unregistered;
+
+impl Drop for Unregistered {
+    fn drop(&mut self) {
+        NUM_UNREGISTERED_DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[inline(never)]
+fn make_unregistered_garbage(n: usize) {
+    for _ in 0..n {
+        let mut gc = Gc::new(Unregistered);
+        gc.unregister_finalizer();
+        black_box(gc);
+    }
+}
+
+fn deterministic_test_mode() {
+    GcConfig::deterministic_test_mode(true);
+    let before = NUM_DROPPED.load(Ordering::SeqCst);
+    make_garbage(1000);
+    GcAllocator::force_gc();
+    assert_eq!(NUM_DROPPED.load(Ordering::SeqCst), before);
+    gc::collect();
+    assert!(NUM_DROPPED.load(Ordering::SeqCst) > before);
+    GcConfig::deterministic_test_mode(false);
+}
+
+struct PanicsOnDrop;
+
+impl Drop for PanicsOnDrop {
+    fn drop(&mut self) {
+        panic!("dropped");
+    }
+}
+
+#[inline(never)]
+fn make_panicking_garbage(n: usize) {
+    for _ in 0..n {
+        black_box(Gc::new(PanicsOnDrop));
+    }
+}
+
+fn finalizer_panics() {
+    GcConfig::on_finalizer_panic(FinalizerPanic::Collect);
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    make_panicking_garbage(100);
+    gc::collect();
+    std::panic::set_hook(hook);
+    // Stale stack slots may keep a few of the objects alive until a later
+    // test, so their panics mustn't abort.
+    GcConfig::on_finalizer_panic(FinalizerPanic::Log);
+
+    let panics = alloc::take_finalizer_panics();
+    assert!(!panics.is_empty());
+    assert!(panics[0].ty.ends_with("PanicsOnDrop"));
+    assert_eq!(panics[0].message, "dropped");
+}
+
+static FINALIZED_WITH: AtomicUsize = AtomicUsize::new(0);
+
+#[inline(never)]
+fn make_garbage_with_finalizers(n: usize) {
+    for i in 0..n {
+        let gc = Gc::new(i);
+        // The data is only reachable through the finalizer's registration.
+        Gc::register_finalizer_with(&gc, Gc::new(i * 2), |v, data| {
+            assert_eq!(*data, *v * 2);
+            FINALIZED_WITH.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+}
+
+fn finalizer_with_data() {
+    make_garbage_with_finalizers(100);
+    gc::collect();
+    assert!(FINALIZED_WITH.load(Ordering::SeqCst) > 0);
+}
+
+fn heap_only_reference_survives() {
+    // This doubles as a smoke test for targets whose collector build differs
+    // from the default (e.g. musl, windows-gnu): an object referenced only
+    // from inside another GC block must survive a collection.
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+    struct S;
+    impl Drop for S {
+        fn drop(&mut self) {
+            DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let outer = Gc::new(Gc::new(S));
+    GcAllocator::force_gc();
+    assert!(!DROPPED.load(Ordering::SeqCst));
+    black_box(outer);
+}
+
+static NUM_GROWN_FINALIZED: AtomicUsize = AtomicUsize::new(0);
+
+#[inline(never)]
+fn grow_with_finalizer() {
+    unsafe extern "C" fn finalize(_: *mut u8, _: *mut u8) {
+        NUM_GROWN_FINALIZED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let old = Layout::from_size_align(16, 8).unwrap();
+    let new = Layout::from_size_align(1 << 16, 8).unwrap();
+    let ptr = Allocator::allocate(&ALLOCATOR, old).unwrap().cast::<u8>();
+    unsafe {
+        ALLOCATOR.register_finalizer(
+            ptr.as_ptr(),
+            Some(finalize),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        );
+        // Growing this much always moves the block.
+        black_box(ALLOCATOR.grow(ptr, old, new).unwrap());
+    }
+}
+
+fn grow_keeps_finalizer() {
+    for _ in 0..100 {
+        grow_with_finalizer();
+    }
+    GcAllocator::force_gc();
+    assert!(NUM_GROWN_FINALIZED.load(Ordering::SeqCst) > 0);
+}
+
+static GROWN_CHILDREN_DROPPED_FIRST: AtomicUsize = AtomicUsize::new(0);
+
+struct GrownChild(AtomicBool);
+
+impl Drop for GrownChild {
+    fn drop(&mut self) {
+        if !self.0.load(Ordering::SeqCst) {
+            GROWN_CHILDREN_DROPPED_FIRST.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[inline(never)]
+fn grow_with_ordered_finalizer() {
+    unsafe extern "C" fn finalize(obj: *mut u8, _: *mut u8) {
+        let child = *(obj as *const Gc<GrownChild>);
+        child.0.store(true, Ordering::SeqCst);
+    }
+
+    let old = Layout::from_size_align(16, 8).unwrap();
+    let new = Layout::from_size_align(1 << 16, 8).unwrap();
+    let ptr = Allocator::allocate(&ALLOCATOR, old).unwrap().cast::<u8>();
+    unsafe {
+        let child = Gc::new_with_ordered_finalizer(GrownChild(AtomicBool::new(false)));
+        (ptr.as_ptr() as *mut Gc<GrownChild>).write(child);
+        ALLOCATOR.register_ordered_finalizer(ptr.as_ptr(), Some(finalize), ptr::null_mut());
+        black_box(ALLOCATOR.grow(ptr, old, new).unwrap());
+    }
+}
+
+fn grow_keeps_ordered_finalizer() {
+    for _ in 0..100 {
+        grow_with_ordered_finalizer();
+    }
+    for _ in 0..3 {
+        gc::collect();
+    }
+    // The grown blocks' finalizers are still ordered, so always run before
+    // those of the children they point to.
+    assert_eq!(GROWN_CHILDREN_DROPPED_FIRST.load(Ordering::SeqCst), 0);
+}
+
+fn downcast() {
+    let any: Gc<dyn Any + Send> = Gc::new(String::from("hello"));
+    assert!(any.downcast::<usize>().is_err());
+    assert_eq!(*any.downcast::<String>().unwrap(), "hello");
+
+    let heap = vec![
+        libgc::gc_dyn!(dyn Any + Send, 1usize),
+        Gc::<dyn Any + Send>::new_unsized(String::from("two")),
+    ];
+    assert_eq!(*heap[0].downcast::<usize>().unwrap(), 1);
+    assert_eq!(*heap[1].downcast::<String>().unwrap(), "two");
+}
+
+fn errors() {
+    fn parse(s: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        Ok(s.parse::<usize>().map_err(Gc::new)?)
+    }
+    assert_eq!(parse("1").unwrap(), 1);
+    assert!(parse("x")
+        .unwrap_err()
+        .to_string()
+        .contains("invalid digit"));
+
+    let err = GcError::from(Gc::new(std::fmt::Error));
+    let copy = err;
+    assert!(copy.downcast_ref::<std::fmt::Error>().is_some());
+    assert!(err.downcast_ref::<std::io::Error>().is_none());
+    assert_eq!(err.to_string(), std::fmt::Error.to_string());
+}
+
+#[cfg(feature = "debug_checks")]
+fn debug_checks() {
+    let gc = Gc::new([1u64, 2]);
+    assert_eq!(gc[1], 2);
+
+    let not_gc = 0u64;
+    let bogus = Gc::from_raw(&not_gc as *const u64);
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let result = std::panic::catch_unwind(|| *bogus);
+    std::panic::set_hook(hook);
+    assert!(result.is_err());
+}
+
+fn cold_objects() {
+    let cold = Gc::new_cold(Gc::new(vec![1, 2, 3]));
+    GcAllocator::force_gc();
+    assert_eq!(**cold, vec![1, 2, 3]);
+    #[cfg(feature = "stats_counters")]
+    let before = libgc::stats::report().cold_bytes_allocated;
+    #[cfg(feature = "stats_counters")]
+    assert!(before > 0);
+
+    // Once the region is full, cold objects are allocated as normal.
+    GcConfig::cold_region(0);
+    assert_eq!(*Gc::new_cold(vec![4]), vec![4]);
+    #[cfg(feature = "stats_counters")]
+    assert_eq!(libgc::stats::report().cold_bytes_allocated, before);
+    GcConfig::cold_region(usize::MAX);
+}
+
+#[inline(never)]
+fn use_pool(pool: &GcPool<Cell<usize>>, n: usize) {
+    for i in 0..n {
+        pool.get().set(i + 1);
+    }
+}
+
+fn pool_recycles() {
+    let pool = GcPool::new(16, || Cell::new(0), |c| c.set(0));
+    use_pool(&pool, 100);
+    gc::collect();
+    assert!(pool.num_free() > 0 && pool.num_free() <= 16);
+    assert_eq!(pool.get().get(), 0);
+
+    pool.shrink();
+    assert!(pool.num_free() <= 8);
+}
+
+static POOLED_CHILDREN_DROPPED_FIRST: AtomicUsize = AtomicUsize::new(0);
+
+fn reset_pooled(child: &mut Cell<Option<Gc<Child>>>) {
+    if let Some(child) = child.take() {
+        if child.0.load(Ordering::SeqCst) {
+            POOLED_CHILDREN_DROPPED_FIRST.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[inline(never)]
+fn use_pool_with_children(pool: &GcPool<Cell<Option<Gc<Child>>>>, n: usize) {
+    for _ in 0..n {
+        let child = Gc::new(Child(AtomicBool::new(false)));
+        pool.get().set(Some(child));
+    }
+}
+
+fn pool_keeps_referents_alive() {
+    // The values are reset while what they point to is still alive.
+    let pool = GcPool::new(16, || Cell::new(None), reset_pooled);
+    use_pool_with_children(&pool, 100);
+    for _ in 0..3 {
+        gc::collect();
+    }
+    assert!(pool.num_free() > 0);
+    assert_eq!(POOLED_CHILDREN_DROPPED_FIRST.load(Ordering::SeqCst), 0);
+}
+
+fn pool_drops_released_values() {
+    static NUM_DROPPED: AtomicUsize = AtomicUsize::new(0);
+    struct Dropped;
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            NUM_DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let pool = GcPool::new(16, || Dropped, |_| ());
+    for _ in 0..100 {
+        pool.get();
+    }
+    gc::collect();
+    let dropped = NUM_DROPPED.load(Ordering::SeqCst);
+    let free = pool.num_free();
+    assert!(free > 1);
+
+    // Values released by `shrink` were resurrected by the pool, but must
+    // still be dropped once the collector finds them again.
+    pool.shrink();
+    gc::collect();
+    assert!(NUM_DROPPED.load(Ordering::SeqCst) > dropped);
+
+    // As must those still on the free list when the pool goes away.
+    let dropped = NUM_DROPPED.load(Ordering::SeqCst);
+    drop(pool);
+    gc::collect();
+    gc::collect();
+    assert!(NUM_DROPPED.load(Ordering::SeqCst) > dropped);
+}
+
+struct AstNode<'a> {
+    name: Gc<String>,
+    parent: Option<&'a AstNode<'a>>,
+}
+
+fn typed_arena() {
+    static NUM_DROPPED: AtomicUsize = AtomicUsize::new(0);
+    struct Dropped;
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            NUM_DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let arena = GcTypedArena::new();
+    let root = arena.alloc(AstNode {
+        name: Gc::new(String::from("root")),
+        parent: None,
+    });
+    // Enough nodes to fill several chunks.
+    let leaves = (0..10_000)
+        .map(|i| {
+            arena.alloc(AstNode {
+                name: Gc::new(i.to_string()),
+                parent: Some(root),
+            })
+        })
+        .collect::<Vec<_>>();
+    gc::collect();
+    assert_eq!(arena.len(), 10_001);
+    assert_eq!(*leaves[1234].name, "1234");
+    assert_eq!(*leaves[9999].parent.unwrap().name, "root");
+
+    let dropped = GcTypedArena::new();
+    for _ in 0..100 {
+        dropped.alloc(Dropped);
+    }
+    drop(dropped);
+    assert_eq!(NUM_DROPPED.load(Ordering::SeqCst), 100);
+}
+
+fn persistent_collections() {
+    let v1 = (0..1000).collect::<GcPersistentVec<usize>>();
+    let v2 = v1.set(500, 0).push(1000);
+    let m1 = (0..1000)
+        .map(|i| (i, i * 2))
+        .collect::<GcPersistentMap<_, _>>();
+    let m2 = m1.insert(0, 1).remove(&999);
+    // Only the collections' roots are on the stack: everything else must
+    // survive by being reachable from them.
+    gc::collect();
+
+    assert_eq!((v1.len(), v1[500], v1.get(1000)), (1000, 500, None));
+    assert_eq!((v2.len(), v2[500], v2[1000]), (1001, 0, 1000));
+    assert!(v1.iter().copied().eq(0..1000));
+    assert_eq!(
+        (m1.len(), m1.get(&0), m1.get(&999)),
+        (1000, Some(&0), Some(&1998))
+    );
+    assert_eq!((m2.len(), m2.get(&0), m2.get(&999)), (999, Some(&1), None));
+    assert_eq!(m1.iter().map(|(k, _)| k).sum::<usize>(), (0..1000).sum());
+}
+
+fn display_into() {
+    let mut sb = GcStringBuilder::with_capacity(4);
+    for i in 0..100 {
+        Gc::new(i).display_into(&mut sb).unwrap();
+        gc::collect();
+    }
+    let s = sb.finish();
+    assert_eq!(s.len(), 190);
+    assert!(s.starts_with("0123456789101112"));
+    assert!(s.ends_with("979899"));
+
+    let empty = GcStringBuilder::new().finish();
+    assert_eq!(&*empty, "");
+}
+
+#[inline(never)]
+fn fill_cache(cache: &mut GcLruCache<usize, String>, n: usize) {
+    for i in 0..n {
+        cache.insert(i, Gc::new(i.to_string()));
+    }
+}
+
+fn weak_and_lru_cache() {
+    let strong = Gc::new(String::from("reachable"));
+    let weak: libgc::WeakGc<String> = Gc::downgrade(&strong);
+
+    let mut cache = GcLruCache::new(10);
+    fill_cache(&mut cache, 100);
+    gc::collect();
+
+    assert_eq!(*weak.upgrade().unwrap(), "reachable");
+    // The 10 most recently used values are held strongly, and some of the
+    // weakly held ones must have been collected.
+    assert!((90..100).all(|i| cache.get(&i).is_some()));
+    assert!((0..90).any(|i| cache.get(&i).is_none()));
+    assert!(cache.len() < 100);
+    black_box(strong);
+}
+
+/// Allocate enough live data that the collector has to grow the heap.
+#[inline(never)]
+fn grow_heap() -> Vec<Gc<[u8; 4096]>> {
+    (0..16 * 1024).map(|_| Gc::new([0; 4096])).collect()
+}
+
+fn caches_recover_capacity() {
+    let mut cache = GcLruCache::new(8);
+    fill_cache(&mut cache, 16);
+    let pool = GcPool::new(8, || Cell::new(0), |c| c.set(0));
+
+    let live = grow_heap();
+    cache.get(&0);
+    pool.get();
+    assert!(cache.capacity() < 8);
+    assert!(pool.max_free() < 8);
+    drop(live);
+
+    // Collections which don't grow the heap give the capacity back.
+    for _ in 0..4 {
+        gc::collect();
+        cache.get(&0);
+        pool.get();
+    }
+    assert_eq!(cache.capacity(), 8);
+    assert_eq!(pool.max_free(), 8);
+}
+
+struct Listener {
+    seen: AtomicUsize,
+}
+
+impl Listener {
+    fn on_event(&self, n: &usize) {
+        self.seen.fetch_add(*n, Ordering::SeqCst);
+    }
+}
+
+#[inline(never)]
+fn subscribe_garbage(emitter: &GcEventEmitter<usize>, n: usize) {
+    for _ in 0..n {
+        let listener = Gc::new(Listener {
+            seen: AtomicUsize::new(0),
+        });
+        emitter.subscribe(&listener, Listener::on_event);
+    }
+}
+
+fn event_emitter() {
+    let emitter = GcEventEmitter::new();
+    let live = Gc::new(Listener {
+        seen: AtomicUsize::new(0),
+    });
+    emitter.subscribe(&live, Listener::on_event);
+    subscribe_garbage(&emitter, 100);
+    assert_eq!(emitter.len(), 101);
+
+    gc::collect();
+    let called = emitter.emit(2);
+    assert!(called < 101);
+    assert_eq!(emitter.len(), called);
+    assert_eq!(live.seen.load(Ordering::SeqCst), 2);
+    black_box(live);
+}
+
+static NUM_NODES_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+struct CountedNode;
+
+impl Drop for CountedNode {
+    fn drop(&mut self) {
+        NUM_NODES_DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[inline(never)]
+fn make_cyclic_garbage(n: usize) {
+    for _ in 0..n {
+        let root = GcTreeNode::new(CountedNode);
+        GcTreeNode::add_child(root, GcTreeNode::new(CountedNode));
+        let (a, b) = (GcNode::new(CountedNode), GcNode::new(CountedNode));
+        a.connect(b, ());
+        b.connect(a, ());
+    }
+}
+
+fn graphs() {
+    let root = GcTreeNode::new(0);
+    let (a, b) = (GcTreeNode::new(1), GcTreeNode::new(2));
+    GcTreeNode::add_child(root, a);
+    GcTreeNode::add_child(root, b);
+    GcTreeNode::add_child(a, GcTreeNode::new(3));
+    let leaf = a.children()[0];
+    let ancestors = GcTreeNode::ancestors(leaf).map(|n| *n.value());
+    assert_eq!(ancestors.collect::<Vec<_>>(), [1, 0]);
+    let descendants = GcTreeNode::descendants(root).map(|n| *n.value());
+    assert_eq!(descendants.collect::<Vec<_>>(), [0, 1, 3, 2]);
+    GcTreeNode::add_child(b, a);
+    assert_eq!(root.children().len(), 1);
+    assert!(Gc::ptr_eq(&a.parent().unwrap(), &b));
+
+    let nodes = (0..4).map(GcNode::new).collect::<Vec<_>>();
+    for (from, to, w) in [(0, 1, 'a'), (0, 2, 'b'), (1, 3, 'c'), (3, 0, 'd')] {
+        nodes[from].connect(nodes[to], w);
+    }
+    let dfs = GcNode::dfs(nodes[0]).map(|n| *n.value());
+    assert_eq!(dfs.collect::<Vec<_>>(), [0, 1, 3, 2]);
+    let bfs = GcNode::bfs(nodes[0]).map(|n| *n.value());
+    assert_eq!(bfs.collect::<Vec<_>>(), [0, 1, 2, 3]);
+    assert_eq!(nodes[3].edges()[0].0, 'd');
+
+    make_cyclic_garbage(1000);
+    gc::collect();
+    assert!(NUM_NODES_DROPPED.load(Ordering::SeqCst) > 0);
+}
+
+#[inline(never)]
+fn pin_for_callback() -> *mut std::ffi::c_void {
+    CallbackPin::pin(Gc::new(Counted(usize::MAX)))
+}
+
+/// A closure value, which captures the scope it was created in.
+struct Closure {
+    env: Gc<GcScopeChain<Option<Gc<Closure>>>>,
+}
+
+#[inline(never)]
+fn make_recursive_closure() -> Gc<GcScopeChain<Option<Gc<Closure>>>> {
+    let global = GcScopeChain::new_global(vec![None]);
+    let local = GcScopeChain::push(global, vec![None, None]);
+    // The closure is stored in the scope it captures.
+    local.set(1, 0, Some(Gc::new(Closure { env: local })));
+    local
+}
+
+fn inline_vec() {
+    let v = GcInlineVec::<Gc<usize>, 4>::new();
+    assert!(v.is_empty());
+    assert_eq!(v.capacity(), 4);
+    for i in 0..4 {
+        v.push(Gc::new(i)).unwrap();
+    }
+    assert!(v.push(Gc::new(4)).is_err());
+    gc::collect();
+    assert_eq!(v.iter().map(|g| *g).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+    // The whole vector is one block, and popped slots are cleared.
+    assert_eq!(
+        unsafe { libgc::debug::scan_block(Gc::as_ptr(&v) as *const u8) }.len(),
+        4
+    );
+    assert_eq!(*v.pop().unwrap(), 3);
+    v.set(0, Gc::new(10));
+    assert_eq!(*v.get(0).unwrap(), 10);
+    assert!(v.get(3).is_none());
+    assert_eq!(
+        unsafe { libgc::debug::scan_block(Gc::as_ptr(&v) as *const u8) }.len(),
+        3
+    );
+
+    let args = GcInlineVec::<u8, 8>::from_slice(b"abc");
+    assert_eq!(format!("{:?}", args), "[97, 98, 99]");
+    args.clear();
+    assert_eq!(args.len(), 0);
+}
+
+fn alloc_observer() {
+    static OBSERVED: AtomicUsize = AtomicUsize::new(0);
+    GcConfig::alloc_observer(|bytes| {
+        OBSERVED.fetch_add(bytes, Ordering::SeqCst);
+    });
+    for _ in 0..1024 {
+        black_box(Gc::new([0u8; 1024]));
+    }
+    let observed = OBSERVED.load(Ordering::SeqCst);
+    assert!(observed <= 1024 * 1024);
+    assert!(observed >= 1024 * 1024 - 64 * 1024, "observed {}", observed);
+
+    GcConfig::clear_alloc_observer();
+    for _ in 0..1024 {
+        black_box(Gc::new([0u8; 1024]));
+    }
+    assert_eq!(OBSERVED.load(Ordering::SeqCst), observed);
+}
+
+fn disable_finalizers() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    struct S;
+    impl Drop for S {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    unsafe { GcConfig::disable_all_finalizers() };
+    assert!(GcConfig::effective_config().finalizers_disabled);
+    for _ in 0..1000 {
+        black_box(Gc::new(S));
+    }
+    for _ in 0..100 {
+        black_box((0..10).map(|_| S).collect::<GcVec<S>>().into_gc_slice());
+    }
+    GcConfig::enable_all_finalizers();
+    GcAllocator::force_gc();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+}
+
+static PARENTS_DROPPED: AtomicUsize = AtomicUsize::new(0);
+static CHILDREN_DROPPED_FIRST: AtomicUsize = AtomicUsize::new(0);
+
+struct Child(AtomicBool);
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+struct Parent(Gc<Child>);
+
+impl Drop for Parent {
+    fn drop(&mut self) {
+        PARENTS_DROPPED.fetch_add(1, Ordering::SeqCst);
+        if self.0 .0.load(Ordering::SeqCst) {
+            CHILDREN_DROPPED_FIRST.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[inline(never)]
+fn make_ordered_garbage(n: usize) {
+    for _ in 0..n {
+        let child = Gc::new(Child(AtomicBool::new(false)));
+        black_box(Gc::new_with_ordered_finalizer(Parent(child)));
+    }
+}
+
+fn ordered_finalizers() {
+    make_ordered_garbage(1000);
+    for _ in 0..3 {
+        gc::collect();
+    }
+    assert!(PARENTS_DROPPED.load(Ordering::SeqCst) > 0);
+    assert_eq!(CHILDREN_DROPPED_FIRST.load(Ordering::SeqCst), 0);
+
+    // A value which only points to itself is still finalized.
+    let before = PARENTS_DROPPED.load(Ordering::SeqCst);
+    make_self_referential_garbage(100);
+    gc::collect();
+    assert!(PARENTS_DROPPED.load(Ordering::SeqCst) > before);
+
+    // Batched registrations stay ordered.
+    GcConfig::finalizer_batching(true);
+    let before = PARENTS_DROPPED.load(Ordering::SeqCst);
+    make_ordered_garbage(1000);
+    for _ in 0..3 {
+        gc::collect();
+    }
+    GcConfig::finalizer_batching(false);
+    assert!(PARENTS_DROPPED.load(Ordering::SeqCst) > before);
+    assert_eq!(CHILDREN_DROPPED_FIRST.load(Ordering::SeqCst), 0);
+}
+
+#[inline(never)]
+fn make_self_referential_garbage(n: usize) {
+    struct Node(GcCell<Option<Gc<Node>>>);
+    impl Drop for Node {
+        fn drop(&mut self) {
+            PARENTS_DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    for _ in 0..n {
+        let node = Gc::new_with_ordered_finalizer(Node(GcCell::new(None)));
+        node.0.set(Some(node));
+    }
+}
+
+static HANDLES_CLOSED: OnceLock<FinalizerMailbox<usize>> = OnceLock::new();
+
+struct Handle(usize);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let _ = HANDLES_CLOSED.get().unwrap().post(self.0);
+    }
+}
+
+#[inline(never)]
+fn make_handles(n: usize) {
+    for i in 0..n {
+        black_box(Gc::new(Handle(i)));
+    }
+}
+
+fn finalizer_mailbox() {
+    let mailbox = HANDLES_CLOSED.get_or_init(|| FinalizerMailbox::with_capacity(1000));
+    make_handles(1000);
+    gc::collect();
+    let mut closed = Vec::new();
+    assert!(mailbox.drain(|h| closed.push(h)) > 0);
+    assert!(closed.iter().all(|&h| h < 1000));
+    assert_eq!(mailbox.overflowed(), 0);
+}
+
+static DROPPED_ON_FINALIZER_THREAD: AtomicUsize = AtomicUsize::new(0);
+static DROPPED_ELSEWHERE: AtomicUsize = AtomicUsize::new(0);
+
+struct WhereDropped;
+
+impl Drop for WhereDropped {
+    fn drop(&mut self) {
+        if thread::current().name() == Some("libgc-finalizer") {
+            DROPPED_ON_FINALIZER_THREAD.fetch_add(1, Ordering::SeqCst);
+        } else {
+            DROPPED_ELSEWHERE.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[inline(never)]
+fn make_where_dropped(n: usize) {
+    for _ in 0..n {
+        black_box(Gc::new(WhereDropped));
+    }
+}
+
+fn finalizer_thread() {
+    let finalizers = FinalizerThread::start();
+    make_where_dropped(1000);
+    gc::collect();
+    for _ in 0..100 {
+        if DROPPED_ON_FINALIZER_THREAD.load(Ordering::SeqCst) > 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(finalizers);
+    assert!(DROPPED_ON_FINALIZER_THREAD.load(Ordering::SeqCst) > 0);
+    assert_eq!(DROPPED_ELSEWHERE.load(Ordering::SeqCst), 0);
+    assert_eq!(libgc::run_finalizers(), 0);
+}
+
+fn scope_chains() {
+    let local = make_recursive_closure();
+    gc::collect();
+    assert_eq!(local.depth(), 1);
+    assert_eq!(local.len(), 2);
+    assert!(local.get(0, 0).is_none());
+    let closure = local.get(1, 0).unwrap();
+    assert!(Gc::ptr_eq(&closure.env, &local));
+
+    let inner = GcScopeChain::push(local, vec![Some(closure)]);
+    let scopes = GcScopeChain::scopes(inner).collect::<Vec<_>>();
+    assert_eq!(scopes.len(), 3);
+    assert!(Gc::ptr_eq(
+        &GcScopeChain::ancestor(inner, 1).unwrap(),
+        &local
+    ));
+    assert!(GcScopeChain::ancestor(inner, 3).is_none());
+    assert_eq!(inner.slots().filter(Option::is_some).count(), 1);
+}
+
+fn callback_pin() {
+    let data = pin_for_callback();
+    // `pin` can be called again for a second callback with the same data.
+    let again = CallbackPin::pin(unsafe { CallbackPin::get::<Counted>(data) });
+    assert_eq!(again, data);
+    assert_eq!(CallbackPin::outstanding(), [("standalone::Counted", 2)]);
+
+    unsafe { CallbackPin::unpin(data) };
+    make_garbage(1000);
+    gc::collect();
+    // Still pinned once, so the object must not have been collected.
+    assert_eq!(unsafe { CallbackPin::get::<Counted>(data) }.0, usize::MAX);
+    unsafe { CallbackPin::unpin(data) };
+    assert!(CallbackPin::outstanding().is_empty());
+}
+
+fn unscanned_strings() {
+    // Blocks which aren't scanned aren't zeroed by the collector, so reusing
+    // these must not leak their contents into the zeroed slices below.
+    for _ in 0..1000 {
+        black_box(Gc::<[u8]>::from(&[0xff; 64][..]));
+    }
+    gc::collect();
+    for _ in 0..1000 {
+        let zeroed = unsafe { Gc::<[u8]>::new_slice_zeroed(64).assume_init() };
+        assert!(zeroed.iter().all(|b| *b == 0));
+    }
+    let s = Gc::<str>::from("hello");
+    assert_eq!(&*s, "hello");
+    let strings = Gc::<[String]>::from(&[String::from("a"), String::from("b")][..]);
+    assert_eq!(strings[1], "b");
+}
+
+fn gc_string() {
+    let hello = GcString::from("hello");
+    let copy = hello;
+    gc::collect();
+    assert!(GcString::ptr_eq(&hello, &copy));
+    assert_eq!(hello, "hello");
+    assert_eq!(hello.len(), 5);
+    assert_eq!(GcString::from(String::from("hello")), hello);
+    assert_eq!(format!("{} {:?}", hello, hello), "hello \"hello\"");
+
+    let mut sb = GcStringBuilder::new();
+    sb.push_str("1-2");
+    assert_eq!(GcString::from(sb), "1-2");
+
+    let mut set = std::collections::HashSet::new();
+    set.insert(hello);
+    assert!(set.contains("hello"));
+    assert_eq!(&*Gc::<str>::from_str("x"), "x");
+    assert_eq!(String::from(hello), "hello");
+}
+
+fn transient_strings() {
+    libgc::transient::scope(|s| {
+        let a = libgc::gc_format!(s, "{}-{}", "a", 1);
+        make_garbage(1000);
+        gc::collect();
+        let b = libgc::gc_format!(s, "{}", "b".repeat(10_000));
+        gc::collect();
+        assert_eq!(a, "a-1");
+        assert_eq!(b.len(), 10_000);
+    });
+    // The arena's chunk survives collections between scopes, so reusing it
+    // doesn't overwrite objects allocated in the meantime.
+    let first = libgc::transient::scope(|s| libgc::gc_format!(s, "{}", 1).as_ptr() as usize);
+    gc::collect();
+    let objs = (0..1000).map(|_| Gc::new([0xAAu8; 64])).collect::<Vec<_>>();
+    let second =
+        libgc::transient::scope(|s| libgc::gc_format!(s, "{}", "c".repeat(1000)).as_ptr() as usize);
+    assert_eq!(first, second);
+    assert!(objs.iter().all(|o| o.iter().all(|&b| b == 0xAA)));
+}
+
+fn tagged_gcs() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Obj(usize);
+
+    impl Drop for Obj {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let objs = (0..8)
+        .map(|i| TaggedGc::<Obj, 3>::new(Gc::new(Obj(i)), i))
+        .collect::<Vec<_>>();
+    make_garbage(1000);
+    gc::collect();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+    for (i, t) in objs.iter().enumerate() {
+        assert_eq!(t.tag(), i);
+        assert_eq!(t.untagged().0, i);
+    }
+
+    let mut t = objs[5];
+    t.set_tag(2);
+    assert_eq!(t.tag(), 2);
+    assert!(TaggedGc::ptr_eq(&t, &objs[5]));
+    assert_ne!(t, objs[5]);
+    assert!(std::panic::catch_unwind(move || t.set_tag(8)).is_err());
+    assert_eq!(Gc::from(objs[0]).0, 0);
+    assert!(libgc::tagged::TAG_BITS >= 3);
+}
+
+fn slices() {
+    let s = Gc::<[u32]>::from_slice(&[1, 2, 3]);
+    assert_eq!(&*s, &[1, 2, 3]);
+
+    let uninit = Gc::<[Gc<usize>]>::new_slice_uninit(100);
+    let base = Gc::into_raw(uninit) as *mut MaybeUninit<Gc<usize>>;
+    for i in 0..100 {
+        unsafe { base.add(i).write(MaybeUninit::new(Gc::new(i))) };
+    }
+    let gcs = unsafe { uninit.assume_init() };
+    gc::collect();
+    assert!(gcs.iter().enumerate().all(|(i, g)| **g == i));
+
+    let zeroed = unsafe { Gc::<[u64]>::new_slice_zeroed(10).assume_init() };
+    assert_eq!(&*zeroed, &[0; 10]);
+}
+
+#[inline(never)]
+fn parse_header(line: &str) -> (GcBytes, GcBytes) {
+    let mut rest = GcBytes::from(line);
+    let colon = rest.iter().position(|&b| b == b':').unwrap();
+    let name = rest.split_to(colon);
+    let value = rest.slice_ref(rest[1..].trim_ascii());
+    (name, value)
+}
+
+fn gc_bytes() {
+    // Only the views keep the block alive.
+    let (name, value) = parse_header("Content-Type:  text/html ");
+    make_garbage(1000);
+    gc::collect();
+    assert_eq!(name, *b"Content-Type".as_slice());
+    assert_eq!(format!("{:?}", value), "b\"text/html\"");
+    assert!(Gc::ptr_eq(&name.parent(), &value.parent()));
+
+    let mut b = GcBytes::from(&b"0123456789"[..]);
+    let tail = b.split_off(6);
+    assert_eq!(&*tail, b"6789");
+    assert_eq!(&*b.slice(2..=3), b"23");
+    assert!(b.slice(6..).is_empty());
+    assert!(GcBytes::new().is_empty());
+}
+
+fn gc_vec() {
+    let mut v = GcVec::new();
+    for i in 0..1000 {
+        v.push(Gc::new(i));
+    }
+    make_garbage(1000);
+    gc::collect();
+    assert!(v.iter().enumerate().all(|(i, g)| **g == i));
+    assert_eq!(*v.pop().unwrap(), 999);
+    v.truncate(10);
+    assert_eq!(v.len(), 10);
+
+    let slice: Gc<[Gc<usize>]> = v.into_gc_slice();
+    gc::collect();
+    assert_eq!(
+        slice.iter().map(|g| **g).collect::<Vec<_>>(),
+        (0..10).collect::<Vec<_>>()
+    );
+
+    let strings = (0..100).map(|i| i.to_string()).collect::<GcVec<_>>();
+    assert_eq!(strings.clone(), strings);
+    let strings = Gc::<[String]>::from(strings);
+    assert_eq!(strings[42], "42");
+    assert!(GcVec::<u8>::new().into_gc_slice().is_empty());
+}
+
+fn gc_cell() {
+    let cell = Gc::new(GcCell::new(Vec::new()));
+    for i in 0..100 {
+        cell.borrow_mut().push(Gc::new(i));
+    }
+    gc::collect();
+    assert!(cell.borrow().iter().enumerate().all(|(i, g)| **g == i));
+
+    {
+        let _r = cell.borrow();
+        assert!(cell.try_borrow_mut().is_err());
+        assert!(cell.try_borrow().is_ok());
+    }
+    let old = cell.replace(vec![Gc::new(7)]);
+    assert_eq!(old.len(), 100);
+    assert_eq!(*cell.borrow()[0], 7);
+}
+
+#[inline(never)]
+fn make_borrowed_garbage(n: usize) {
+    for _ in 0..n {
+        let cell = Gc::new(GcCell::new(0));
+        // As if the collector had missed a reference: the borrow outlives
+        // every `Gc` pointing to its cell.
+        mem::forget(cell.borrow());
+    }
+}
+
+fn gc_cell_finalized_while_borrowed() {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    GcConfig::on_finalizer_panic(FinalizerPanic::Collect);
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    make_borrowed_garbage(100);
+    make_garbage(1000);
+    gc::collect();
+    std::panic::set_hook(hook);
+    GcConfig::on_finalizer_panic(FinalizerPanic::Log);
+
+    let panics = alloc::take_finalizer_panics();
+    assert!(panics
+        .iter()
+        .any(|p| p.ty.contains("GcCell") && p.message == "a GcCell was dropped while borrowed"));
+}
+
+fn new_cyclic() {
+    struct Node {
+        id: usize,
+        next: Option<Gc<Node>>,
+        back: Option<Box<Gc<MaybeUninit<Node>>>>,
+    }
+
+    fn back(node: &Node) -> &Node {
+        unsafe { node.back.as_ref().unwrap().assume_init_ref() }
+    }
+
+    let me = Gc::new_cyclic(|me| Node {
+        id: 0,
+        next: None,
+        back: Some(Box::new(*me)),
+    });
+    let a = Gc::new_cyclic(|a| Node {
+        id: 1,
+        next: Some(Gc::new(Node {
+            id: 2,
+            next: None,
+            back: Some(Box::new(*a)),
+        })),
+        back: None,
+    });
+    make_garbage(1000);
+    gc::collect();
+    assert!(std::ptr::eq(back(&me), &*me));
+    let b = a.next.unwrap();
+    assert_eq!((me.id, a.id, b.id), (0, 1, 2));
+    assert!(std::ptr::eq(back(&b), &*a));
+}
+
+static NUM_DYN_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe fn drop_dyn(_: *mut u8) {
+    NUM_DYN_DROPPED.fetch_add(1, Ordering::SeqCst);
+}
+
+#[inline(never)]
+fn make_dynamic_garbage(layout: &LayoutInfo, n: usize) {
+    for _ in 0..n {
+        black_box(Gc::new_dynamic(&[0; 24], layout, Some(drop_dyn)));
+    }
+}
+
+#[inline(never)]
+fn new_dynamic_holding(layout: &LayoutInfo, referent: Gc<Counted>) -> Gc<DynObject> {
+    let mut bytes = [0u8; 24];
+    bytes[8..16].copy_from_slice(&(Gc::into_raw(referent) as usize).to_ne_bytes());
+    Gc::new_dynamic(&bytes, layout, None)
+}
+
+fn dynamic_objects() {
+    // Only the second of three words is a pointer.
+    let layout = LayoutInfo::new(24, 8, &[8]);
+    let obj = new_dynamic_holding(&layout, Gc::new(Counted(42)));
+    make_dynamic_garbage(&layout, 1000);
+    gc::collect();
+    assert!(NUM_DYN_DROPPED.load(Ordering::SeqCst) > 0);
+
+    let word = unsafe { (obj.as_ptr() as *const *const Counted).add(1).read() };
+    assert_eq!(obj.len(), 24);
+    assert_eq!(Gc::from_raw(word).0, 42);
+}
+
+fn scan_block() {
+    let target = Gc::new(1usize);
+    let holder = Gc::new([0usize, Gc::into_raw(target) as usize, 0]);
+    let found = unsafe { libgc::debug::scan_block(Gc::into_raw(holder) as *const u8) };
+    let p = found
+        .iter()
+        .find(|p| p.value == Gc::into_raw(target) as usize)
+        .unwrap();
+    assert_eq!(p.offset % std::mem::size_of::<usize>(), 0);
+    assert_eq!(p.target_offset(), p.value - p.target as usize);
+    assert!(p.target_size >= std::mem::size_of::<usize>());
+    assert!(unsafe { libgc::debug::scan_block(&0usize as *const usize as *const u8) }.is_empty());
+}
+
+fn emergency_dump() {
+    let mut line = String::new();
+    GcAllocator::get_stats().write_to(&mut line).unwrap();
+    assert!(line.contains(" collections taking "));
+    libgc::debug::emergency_dump();
+}
+
+fn verify_heap() {
+    let live = (0..100).map(|i| Gc::new(vec![i; 10])).collect::<Vec<_>>();
+    let report = libgc::debug::verify_heap();
+    assert!(report.is_ok(), "{:?}", report.anomalies);
+    assert!(report.objects >= live.len());
+    black_box(live);
+}
+
+static IN_DATA_SEGMENT: AtomicUsize = AtomicUsize::new(1);
+
+fn root_snapshots() {
+    let before = libgc::debug::root_snapshot();
+    let addr = &IN_DATA_SEGMENT as *const AtomicUsize as usize;
+    assert!(before
+        .ranges
+        .iter()
+        .any(|r| (r.start..r.start + r.size).contains(&addr)));
+    assert!(before.total_bytes() > 0);
+    let after = libgc::debug::root_snapshot();
+    let diff = before.diff(&after);
+    assert!(diff.is_empty(), "{}", diff);
+}
+
+fn false_retention() {
+    let report = libgc::debug::false_retention_experiment(1000);
+    assert_eq!(report.canaries, 1000);
+    assert!(report.retained < report.canaries, "{}", report);
+    assert!(report.rate() < 1.0);
+}
+
+fn freeze() {
+    let gc = Gc::new(vec![1, 2, 3]);
+    let frozen = gc.freeze();
+    assert_eq!(**frozen, vec![1, 2, 3]);
+    assert_eq!(frozen.len(), 3);
+    assert_eq!(
+        Gc::as_ptr(&frozen) as *const u8,
+        Gc::as_ptr(&gc) as *const u8
+    );
+    assert_eq!(format!("{:?}", frozen), "[1, 2, 3]");
+
+    #[cfg(feature = "debug_checks")]
+    {
+        let frozen = Gc::new(7u64).freeze();
+        unsafe { *(Gc::as_ptr(&frozen) as *mut u64) = 8 };
+        assert!(std::panic::catch_unwind(|| **frozen).is_err());
+    }
+}
+
+fn shared_readonly() {
+    let table: Gc<[u32]> = Gc::from_slice(&(0..10_000).collect::<Vec<_>>());
+    let shared = table.into_shared_readonly();
+    assert_eq!(shared.len(), 10_000);
+    assert!(shared.iter().enumerate().all(|(i, &v)| v as usize == i));
+    assert!(GcAllocator::base(shared.as_ptr() as *const u8).is_none());
+
+    let pair = Gc::new([1.5f64, 2.5]).into_shared_readonly();
+    gc::collect();
+    assert_eq!(*pair, [1.5, 2.5]);
+    assert!(Gc::<[u8]>::from_slice(&[])
+        .into_shared_readonly()
+        .is_empty());
+}
+
+fn executor() {
+    use libgc::executor::{yield_now, Executor};
+    use std::{future::Future, pin::Pin, task};
+
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+    static ABANDONED: AtomicUsize = AtomicUsize::new(0);
+
+    let ex = Executor::new();
+    for _ in 0..10 {
+        ex.spawn(async {
+            yield_now().await;
+            yield_now().await;
+            FINISHED.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    assert_eq!(ex.num_queued(), 10);
+    assert_eq!(ex.run_until_stalled(), 30);
+    assert_eq!(FINISHED.load(Ordering::SeqCst), 10);
+    assert_eq!(ex.num_queued(), 0);
+
+    // A future which drops its waker can never be woken, so once it isn't
+    // queued its task is garbage.
+    struct Abandoned;
+    impl Drop for Abandoned {
+        fn drop(&mut self) {
+            ABANDONED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    impl Future for Abandoned {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, _: &mut task::Context) -> task::Poll<()> {
+            task::Poll::Pending
+        }
+    }
+    for _ in 0..100 {
+        ex.spawn(Abandoned);
+    }
+    assert_eq!(ex.run_until_stalled(), 100);
+    GcAllocator::force_gc();
+    // As in `finalizers_run`, a few tasks may be retained conservatively.
+    assert!(ABANDONED.load(Ordering::SeqCst) > 0);
+}
+
+/// A tiny xorshift generator, so that `random_dynamic_layouts` is
+/// reproducible without pulling in a dependency.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+const NUM_SHAPES: usize = 50;
+const MAX_SHAPE_WORDS: usize = 16;
+const MAX_PLANTED: usize = NUM_SHAPES * MAX_SHAPE_WORDS;
+
+static PLANTED_DROPPED: [AtomicBool; MAX_PLANTED] = [const { AtomicBool::new(false) }; MAX_PLANTED];
+static NUM_DECOYS_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Planted in a word which the layout says holds a pointer.
+struct Planted(usize);
+
+impl Drop for Planted {
+    fn drop(&mut self) {
+        PLANTED_DROPPED[self.0].store(true, Ordering::SeqCst);
+    }
+}
+
+/// Planted in a word which the layout says doesn't hold a pointer.
+struct Decoy;
+
+impl Drop for Decoy {
+    fn drop(&mut self) {
+        NUM_DECOYS_DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Allocate an object with a random shape, with a `Planted` in each traced
+/// word and a `Decoy` in each other word. Returns the object and the ids of
+/// its `Planted`s, by word.
+#[inline(never)]
+fn new_random_shape(
+    rng: &mut XorShift,
+    next_id: &mut usize,
+) -> (Gc<DynObject>, Vec<Option<usize>>) {
+    let words = 1 + rng.below(MAX_SHAPE_WORDS);
+    let traced = (0..words).map(|_| rng.below(2) == 0).collect::<Vec<_>>();
+    let offsets = (0..words)
+        .filter(|w| traced[*w])
+        .map(|w| w * 8)
+        .collect::<Vec<_>>();
+    let layout = LayoutInfo::new(words * 8, 8, &offsets);
+
+    let mut bytes = vec![0u8; words * 8];
+    let mut ids = Vec::with_capacity(words);
+    for (w, &traced) in traced.iter().enumerate() {
+        let (addr, id) = if traced {
+            let id = *next_id;
+            *next_id += 1;
+            (Gc::into_raw(Gc::new(Planted(id))) as usize, Some(id))
+        } else {
+            (Gc::into_raw(Gc::new(Decoy)) as usize, None)
+        };
+        bytes[w * 8..(w + 1) * 8].copy_from_slice(&addr.to_ne_bytes());
+        ids.push(id);
+    }
+    // Scrub the only other copy of the decoys' addresses.
+    let obj = Gc::new_dynamic(&bytes, &layout, None);
+    bytes
+        .iter_mut()
+        .for_each(|b| unsafe { ptr::write_volatile(b, 0) });
+    (obj, ids)
+}
+
+fn random_dynamic_layouts() {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut next_id = 0;
+    let shapes = (0..NUM_SHAPES)
+        .map(|_| new_random_shape(&mut rng, &mut next_id))
+        .collect::<Vec<_>>();
+    gc::collect();
+
+    for (obj, ids) in &shapes {
+        for (w, id) in ids.iter().enumerate() {
+            if let Some(id) = *id {
+                assert!(!PLANTED_DROPPED[id].load(Ordering::SeqCst));
+                let word = unsafe { (obj.as_ptr() as *const *const Planted).add(w).read() };
+                assert_eq!(Gc::from_raw(word).0, id);
+            }
+        }
+    }
+    // The collector is conservative elsewhere, so stale copies may keep a few
+    // decoys alive. Some must have been collected though.
+    assert!(NUM_DECOYS_DROPPED.load(Ordering::SeqCst) > 0);
+}
+
+fn atomic_gc() {
+    use libgc::atomic::AtomicGc;
+
+    // Only reachable through the static slot.
+    static SLOT: AtomicGc<usize> = AtomicGc::empty();
+    const NUM_THREADS: usize = 4;
+    const ROUNDS: usize = 1000;
+
+    SLOT.store(Some(Gc::new(0)), Ordering::Release);
+    let children = (0..NUM_THREADS)
+        .map(|_| {
+            spawn_registered(|| {
+                for _ in 0..ROUNDS {
+                    let mut current = SLOT.load(Ordering::Acquire);
+                    loop {
+                        let next = Gc::new(*current.unwrap() + 1);
+                        match SLOT.compare_exchange(
+                            current,
+                            Some(next),
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                        ) {
+                            Ok(_) => break,
+                            Err(actual) => current = actual,
+                        }
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for child in children {
+        child.join().unwrap();
+    }
+    GcAllocator::force_gc();
+    assert_eq!(*SLOT.load(Ordering::Acquire).unwrap(), NUM_THREADS * ROUNDS);
+
+    let old = SLOT.swap(None, Ordering::AcqRel);
+    assert!(old.is_some());
+    assert!(SLOT.load(Ordering::Relaxed).is_none());
+    assert!(AtomicGc::from(Gc::new(1u8)).into_inner().is_some());
+}
+
+fn foreign_thread() {
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+    struct S(usize);
+    impl Drop for S {
+        fn drop(&mut self) {
+            DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Called on a thread created with `pthread_create`, as a C library would,
+    // which Rust and the collector know nothing about.
+    extern "C" fn callback(_: *mut libc::c_void) -> *mut libc::c_void {
+        libgc::thread::ensure_registered();
+        libgc::thread::ensure_registered();
+        assert!(GcAllocator::thread_registered());
+        let gc = Gc::new(S(42));
+        make_garbage(100);
+        GcAllocator::force_gc();
+        assert_eq!(gc.0, 42);
+        assert!(!DROPPED.load(Ordering::SeqCst));
+        black_box(gc);
+        ptr::null_mut()
+    }
+
+    GcAllocator::allow_register_threads();
+    unsafe {
+        let mut thread = MaybeUninit::uninit();
+        assert_eq!(
+            libc::pthread_create(thread.as_mut_ptr(), ptr::null(), callback, ptr::null_mut()),
+            0
+        );
+        assert_eq!(libc::pthread_join(thread.assume_init(), ptr::null_mut()), 0);
+    }
+}
+
+fn threads_are_scanned() {
+    static COLLECTED: AtomicBool = AtomicBool::new(false);
+    static READY: AtomicUsize = AtomicUsize::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+    const NUM_THREADS: usize = 4;
+    struct S;
+    impl Drop for S {
+        fn drop(&mut self) {
+            COLLECTED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let children = (0..NUM_THREADS)
+        .map(|_| {
+            spawn_registered(|| {
+                let gc = Gc::new(S);
+                READY.fetch_add(1, Ordering::SeqCst);
+                while !DONE.load(Ordering::SeqCst) {
+                    thread::yield_now();
+                }
+                black_box(gc);
+            })
+        })
+        .collect::<Vec<_>>();
+
+    while READY.load(Ordering::SeqCst) < NUM_THREADS {
+        thread::yield_now();
+    }
+    // The only references to each `S` are on the children's stacks.
+    GcAllocator::force_gc();
+    DONE.store(true, Ordering::SeqCst);
+    children.into_iter().for_each(|c| c.join().unwrap());
+    assert!(!COLLECTED.load(Ordering::SeqCst));
+}
+
+fn heap_size_tuning() {
+    let config = GcAllocator::collector_config();
+    assert!(GcAllocator::expand_heap(16 << 20));
+    let grown = GcAllocator::collector_config().heap_size;
+    assert!(grown >= config.heap_size + (16 << 20));
+
+    // Nothing more can be allocated once the heap is at its limit, but the
+    // heap as it is still works.
+    GcAllocator::set_max_heap_size(grown);
+    assert!(!GcAllocator::expand_heap(16 << 20));
+    make_garbage(1000);
+    GcAllocator::set_max_heap_size(0);
+    assert!(GcAllocator::expand_heap(1 << 20));
+
+    GcAllocator::set_free_space_divisor(config.free_space_divisor + 1);
+    assert_eq!(
+        GcAllocator::collector_config().free_space_divisor,
+        config.free_space_divisor + 1
+    );
+    GcAllocator::set_free_space_divisor(config.free_space_divisor);
+}
+
+fn thread_guards() {
+    // The main thread is already registered, so this does nothing.
+    drop(libgc::thread::GcThreadGuard::new());
+    assert!(GcAllocator::thread_registered());
+
+    let handles = (0..4)
+        .map(|i| {
+            libgc::thread::spawn(move || {
+                assert!(GcAllocator::thread_registered());
+                let gc = Gc::new([i; 8]);
+                make_garbage(1000);
+                gc::collect();
+                assert_eq!(*gc, [i; 8]);
+            })
+        })
+        .collect::<Vec<_>>();
+    handles.into_iter().for_each(|h| h.join().unwrap());
+
+    let unregistered = thread::spawn(|| {
+        let guard = libgc::thread::GcThreadGuard::new();
+        assert!(GcAllocator::thread_registered());
+        drop(guard);
+        GcAllocator::thread_registered()
+    });
+    assert!(!unregistered.join().unwrap());
+}
+
+fn thread_handoff() {
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+
+    struct Obj(usize);
+    impl Drop for Obj {
+        fn drop(&mut self) {
+            DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    GcAllocator::allow_register_threads();
+    let (hidden, handoff) = thread::spawn(|| {
+        let guard = libgc::thread::GcThreadGuard::new();
+        let obj = Gc::new(Obj(42));
+        // Other threads only see the object through a hidden pointer.
+        let hidden = !(Gc::as_ptr(&obj) as usize);
+        let root: Gc<dyn Any + Send> = obj;
+        (hidden, guard.handoff(&[root]))
+    })
+    .join()
+    .unwrap();
+
+    // However many collections there are, the object lives until the
+    // receiver has taken its own reference and released the handoff.
+    for _ in 0..3 {
+        make_garbage(1000);
+        gc::collect();
+    }
+    assert!(!DROPPED.load(Ordering::SeqCst));
+    let obj = Gc::from_raw(!hidden as *const Obj);
+    drop(handoff);
+    gc::collect();
+    assert!(!DROPPED.load(Ordering::SeqCst));
+    assert_eq!(obj.0, 42);
+}
+
+fn world_stopped() {
+    static TICKS: AtomicUsize = AtomicUsize::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    let child = spawn_registered(|| {
+        while !DONE.load(Ordering::SeqCst) {
+            TICKS.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    while TICKS.load(Ordering::SeqCst) == 0 {
+        thread::yield_now();
+    }
+
+    let (before, after) = unsafe {
+        GcAllocator::with_world_stopped(|| {
+            let before = TICKS.load(Ordering::SeqCst);
+            for _ in 0..1_000_000 {
+                std::hint::spin_loop();
+            }
+            (before, TICKS.load(Ordering::SeqCst))
+        })
+    };
+    assert_eq!(before, after);
+
+    // In debug builds, allocating with the world stopped fails rather than
+    // deadlocking, whichever way the block is allocated.
+    if cfg!(debug_assertions) {
+        let layout = Layout::new::<u64>();
+        let failed = unsafe {
+            GcAllocator::with_world_stopped(|| {
+                [
+                    ALLOCATOR.allocate(layout).is_err(),
+                    ALLOCATOR.allocate_atomic(layout).is_err(),
+                    ALLOCATOR.allocate_cold(layout).is_err(),
+                    ALLOCATOR.allocate_precise(layout, 1, 1).is_err(),
+                ]
+            })
+        };
+        assert_eq!(failed, [true; 4]);
+    }
+
+    DONE.store(true, Ordering::SeqCst);
+    child.join().unwrap();
+}
+
+fn fork() {
+    let before = Gc::new(vec![1, 2, 3]);
+    match unsafe { libc::fork() } {
+        -1 => panic!("fork failed"),
+        0 => {
+            // Anything going wrong in the child must show in its exit status.
+            let ok = std::panic::catch_unwind(|| {
+                make_garbage(1000);
+                gc::collect();
+                *before == vec![1, 2, 3] && *Gc::new(4usize) == 4
+            });
+            unsafe { libc::_exit(if let Ok(true) = ok { 0 } else { 1 }) }
+        }
+        child => {
+            let mut status = 0;
+            assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+            assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+        }
+    }
+}
+
+#[cfg(feature = "crash_handler")]
+fn crash_handler() {
+    use std::{fs::File, io::Read, os::unix::io::FromRawFd};
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    match unsafe { libc::fork() } {
+        -1 => panic!("fork failed"),
+        0 => {
+            unsafe { libc::dup2(fds[1], libc::STDERR_FILENO) };
+            // The collector may find writes to the heap through write faults,
+            // which must be handed to it without being reported.
+            GcConfig::collection_mode(CollectionMode::Generational { full_freq: 4 });
+            libgc::crash::install_handlers();
+            let slots = (0..100).map(|_| Gc::new(Cell::new(0))).collect::<Vec<_>>();
+            gc::collect();
+            slots.iter().for_each(|slot| slot.set(1));
+            gc::collect();
+            std::process::abort();
+        }
+        child => {
+            unsafe { libc::close(fds[1]) };
+            let mut stderr = String::new();
+            let mut pipe = unsafe { File::from_raw_fd(fds[0]) };
+            pipe.read_to_string(&mut stderr).unwrap();
+            assert_eq!(stderr.matches("fatal signal").count(), 1);
+            assert!(stderr.contains(&format!("fatal signal {} ", libc::SIGABRT)));
+            // The handler must hand the signal on, rather than swallow it.
+            let mut status = 0;
+            assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+            assert!(libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGABRT);
+        }
+    }
+}
+
+#[cfg(feature = "stats_counters")]
+fn stats() {
+    let before = libgc::stats::NUM_REGISTERED_FINALIZERS.load(Ordering::SeqCst);
+    black_box(Gc::new(String::from("finalize me")));
+    let after = libgc::stats::NUM_REGISTERED_FINALIZERS.load(Ordering::SeqCst);
+    assert_eq!(after, before + 1);
+    assert!(libgc::stats::report().registered_finalizers >= after);
+
+    struct Unfinalized(#[allow(dead_code)] String);
+    unsafe impl libgc::gc::NoFinalize for Unfinalized {}
+    black_box(Gc::new(Unfinalized(String::from("leak me"))));
+    assert_eq!(
+        libgc::stats::NUM_REGISTERED_FINALIZERS.load(Ordering::SeqCst),
+        after
+    );
+
+    // Each thread counts its own allocations, which still add up once it has
+    // exited.
+    let before = libgc::stats::bytes_allocated();
+    spawn_registered(|| {
+        black_box(Gc::new([0u8; 64]));
+    })
+    .join()
+    .unwrap();
+    black_box(Gc::new([0u8; 64]));
+    assert!(libgc::stats::bytes_allocated() >= before + 128);
+    assert!(libgc::stats::report().bytes_allocated >= before + 128);
+}
+
+#[cfg(feature = "stats_counters")]
+fn pending_finalizers() {
+    use libgc::stats::{self, Watchdog};
+    use std::sync::mpsc;
+
+    GcAllocator::invoke_finalizers();
+    assert_eq!(stats::pending_finalizers(), 0);
+
+    GcAllocator::set_finalize_on_demand(true);
+    make_garbage(1000);
+    GcAllocator::force_gc();
+    // As in `finalizers_run`, a few objects may be retained conservatively.
+    let pending = stats::pending_finalizers();
+    assert!(pending > 500, "only {} pending", pending);
+    assert_eq!(stats::report().pending_finalizers, pending);
+
+    let (tx, rx) = mpsc::channel();
+    let watchdog =
+        Watchdog::start_with(100, Duration::from_millis(1), move |n| tx.send(n).unwrap());
+    assert!(rx.recv_timeout(Duration::from_secs(10)).unwrap() > 100);
+    drop(watchdog);
+
+    GcAllocator::invoke_finalizers();
+    GcAllocator::set_finalize_on_demand(false);
+    assert_eq!(stats::pending_finalizers(), 0);
+}
+
+#[cfg(feature = "stats_counters")]
+fn sampled_types() {
+    use libgc::stats;
+
+    stats::sample_types(10);
+    let live = (0..10_000)
+        .map(|i| Gc::new([i as u64; 4]))
+        .collect::<Vec<_>>();
+    make_garbage(10_000);
+    gc::collect();
+    let histogram = stats::sampled_type_histogram();
+    let (ty, arrays) = histogram[0];
+    assert_eq!(ty, std::any::type_name::<[u64; 4]>());
+    // Every tenth array on this thread is sampled, so the estimate is exact.
+    assert_eq!(arrays.objects, live.len());
+    assert_eq!(arrays.bytes, live.len() * 32);
+    black_box(live);
+
+    stats::sample_types(0);
+    assert!(stats::sampled_type_histogram().is_empty());
+}
+
+#[cfg(feature = "stats_profiling")]
+fn timeline() {
+    let path = std::env::temp_dir().join(format!("libgc-timeline-{}.json", std::process::id()));
+    libgc::stats::start_timeline(&path).unwrap();
+    make_garbage(100);
+    gc::collect();
+    libgc::stats::stop_timeline().unwrap();
+
+    let trace = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(trace.starts_with("{\"traceEvents\":["));
+    assert!(trace.contains("{\"name\":\"collection\",\"ph\":\"B\""));
+    assert!(trace.contains("{\"name\":\"collection\",\"ph\":\"E\""));
+}
+
+fn collection_modes() {
+    assert_eq!(CollectionMode::current(), CollectionMode::StopTheWorld);
+    GcConfig::collection_mode(CollectionMode::StopTheWorld);
+
+    let mode = CollectionMode::Incremental {
+        time_limit: Duration::from_millis(5),
+        full_freq: 4,
+    };
+    GcConfig::collection_mode(mode);
+    assert_eq!(CollectionMode::current(), mode);
+
+    struct Node(usize, Option<Gc<Node>>);
+    let list = (0..1000).fold(None, |next, i| Some(Gc::new(Node(i, next))));
+    for _ in 0..10 {
+        make_garbage(1000);
+        while gc::collect_slice() {}
+    }
+    gc::collect();
+    let mut expected = 1000;
+    let mut node = list;
+    while let Some(n) = node {
+        expected -= 1;
+        assert_eq!(n.0, expected);
+        node = n.1;
+    }
+    assert_eq!(expected, 0);
+
+    // Objects written to after they were marked, other than through
+    // `GcCell`, must be re-scanned.
+    let slots = (0..100)
+        .map(|_| Gc::new(Cell::new(None)))
+        .collect::<Vec<Gc<Cell<Option<Gc<usize>>>>>>();
+    gc::collect();
+    for round in 0..10 {
+        for (i, slot) in slots.iter().enumerate() {
+            slot.set(Some(Gc::new(round * 100 + i)));
+            if i % 10 == 0 {
+                make_garbage(100);
+                gc::collect_slice();
+            }
+        }
+        while gc::collect_slice() {}
+    }
+    make_garbage(1000);
+    gc::collect();
+    for (i, slot) in slots.iter().enumerate() {
+        assert_eq!(*slot.get().unwrap(), 900 + i);
+    }
+
+    let r = std::panic::catch_unwind(|| GcConfig::collection_mode(CollectionMode::StopTheWorld));
+    assert!(r.is_err());
+}
+
+fn teardown() {
+    let gc = Gc::new(1usize);
+    assert_eq!(gc.try_deref(), Some(&1));
+    gc::teardown();
+    assert_eq!(gc.try_deref(), None);
+    #[cfg(feature = "teardown_checks")]
+    assert!(std::panic::catch_unwind(|| *gc).is_err());
+}